@@ -4,6 +4,7 @@
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt;
 use std::result::Result;
+use termwiz::color::xterm_256_palette;
 pub use termwiz::color::{AnsiColor, ColorAttribute, RgbColor, RgbaTuple};
 
 #[derive(Clone, PartialEq, Eq)]
@@ -175,29 +176,8 @@ impl ColorPalette {
 
         colors[0..16].copy_from_slice(&ANSI);
 
-        // 216 color cube.
-        // This isn't the perfect color cube, but it matches the values used
-        // by xterm, which are slightly brighter.
-        static RAMP6: [u8; 6] = [0, 0x5f, 0x87, 0xaf, 0xd7, 0xff];
-        for idx in 0..216 {
-            let blue = RAMP6[idx % 6];
-            let green = RAMP6[idx / 6 % 6];
-            let red = RAMP6[idx / 6 / 6 % 6];
-
-            colors[16 + idx] = RgbColor::new_8bpc(red, green, blue);
-        }
-
-        // 24 grey scales
-        static GREYS: [u8; 24] = [
-            0x08, 0x12, 0x1c, 0x26, 0x30, 0x3a, 0x44, 0x4e, 0x58, 0x62, 0x6c, 0x76, 0x80, 0x8a,
-            0x94, 0x9e, 0xa8, 0xb2, /* Grey70 */
-            0xbc, 0xc6, 0xd0, 0xda, 0xe4, 0xee,
-        ];
-
-        for idx in 0..24 {
-            let grey = GREYS[idx];
-            colors[232 + idx] = RgbColor::new_8bpc(grey, grey, grey);
-        }
+        // The 216 color cube and 24 grey scales are the same as xterm's
+        colors[16..].copy_from_slice(&xterm_256_palette()[16..]);
 
         let foreground = colors[249]; // Grey70
         let background = colors[AnsiColor::Black as usize];
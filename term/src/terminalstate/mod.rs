@@ -1056,6 +1056,10 @@ impl TerminalState {
     fn perform_device(&mut self, dev: Device) {
         match dev {
             Device::DeviceAttributes(a) => log::warn!("unhandled: {:?}", a),
+            Device::SetKeyboardLeds(leds) => log::trace!("ignoring DECLL {:?}", leds),
+            Device::SelectStatusDisplayType(_) | Device::SelectActiveStatusDisplay(_) => {
+                log::trace!("ignoring status line control {:?}", dev)
+            }
             Device::SoftReset => {
                 // TODO: see https://vt100.net/docs/vt510-rm/DECSTR.html
                 self.pen = CellAttributes::default();
@@ -1116,6 +1120,9 @@ impl TerminalState {
                 self.writer.write(b"\x1b[0n").ok();
                 self.writer.flush().ok();
             }
+            Device::RequestPrinterStatus | Device::RequestUserDefinedKeysStatus => {
+                log::trace!("ignoring status request {:?}", dev)
+            }
             Device::XtSmGraphics(g) => {
                 let response = if matches!(g.item, XtSmGraphicsItem::Unspecified(_)) {
                     XtSmGraphics {
@@ -1615,6 +1622,10 @@ impl TerminalState {
                 log::warn!("unhandled XtermKeyMode {:?} {:?}", resource, value);
             }
 
+            mode @ Mode::AssignColor { .. } => {
+                log::warn!("unhandled {:?}", mode);
+            }
+
             Mode::QueryDecPrivateMode(_) | Mode::QueryMode(_) => {
                 self.decqrm_response(mode, false, false);
             }
@@ -1726,6 +1737,9 @@ impl TerminalState {
                 write!(self.writer, "\x1bP{}!~{:04x}\x1b\\", request_id, checksum).ok();
                 self.writer.flush().ok();
             }
+            Window::CopyRectangularArea { .. } => {
+                log::warn!("unhandled {:?}", window);
+            }
             Window::ResizeWindowCells { .. } => {
                 // We don't allow the application to change the window size; that's
                 // up to the user!
@@ -1965,9 +1979,12 @@ impl TerminalState {
             Cursor::SetTopAndBottomMargins { top, bottom } => {
                 let rows = self.screen().physical_rows;
                 let top = i64::from(top.as_zero_based()).min(rows as i64 - 1).max(0);
-                let bottom = i64::from(bottom.as_zero_based())
-                    .min(rows as i64 - 1)
-                    .max(0);
+                let bottom = match bottom {
+                    Some(bottom) => i64::from(bottom.as_zero_based())
+                        .min(rows as i64 - 1)
+                        .max(0),
+                    None => rows as i64 - 1,
+                };
                 if top >= bottom {
                     return;
                 }
@@ -2148,11 +2165,11 @@ impl TerminalState {
                 self.wrap_next = false;
             }
 
-            Cursor::ActivePositionReport { .. } => {
+            Cursor::ActivePositionReport { .. } | Cursor::ExtendedActivePositionReport { .. } => {
                 // This is really a response from the terminal, and
                 // we don't need to process it as a terminal command
             }
-            Cursor::RequestActivePositionReport => {
+            Cursor::RequestActivePositionReport | Cursor::RequestExtendedActivePositionReport => {
                 let line = OneBased::from_zero_based(
                     (self.cursor.y.saturating_sub(if self.dec_origin_mode {
                         self.top_and_bottom_margins.start
@@ -2167,7 +2184,16 @@ impl TerminalState {
                         0
                     })) as u32,
                 );
-                let report = CSI::Cursor(Cursor::ActivePositionReport { line, col });
+                let report = if matches!(cursor, Cursor::RequestExtendedActivePositionReport) {
+                    CSI::Cursor(Cursor::ExtendedActivePositionReport {
+                        line,
+                        col,
+                        // We only have a single page
+                        page: OneBased::new(1),
+                    })
+                } else {
+                    CSI::Cursor(Cursor::ActivePositionReport { line, col })
+                };
                 write!(self.writer, "{}", report).ok();
                 self.writer.flush().ok();
             }
@@ -2270,6 +2296,11 @@ impl TerminalState {
             Sgr::Underline(underline) => {
                 self.pen.set_underline(underline);
             }
+            Sgr::UnknownUnderline(_) => {
+                // Some kind of underline that we don't know how to
+                // render; a single underline is the closest fit
+                self.pen.set_underline(Underline::Single);
+            }
             Sgr::Overline(overline) => {
                 self.pen.set_overline(overline);
             }
@@ -2297,7 +2328,7 @@ impl TerminalState {
             Sgr::UnderlineColor(col) => {
                 self.pen.set_underline_color(col);
             }
-            Sgr::Font(_) => {}
+            Sgr::Font(_) | Sgr::Fraktur(_) | Sgr::Unknown(_) => {}
         }
     }
 
@@ -406,6 +406,8 @@ impl<'a> Performer<'a> {
             CSI::Device(dev) => self.state.perform_device(*dev),
             CSI::Mouse(mouse) => error!("mouse report sent by app? {:?}", mouse),
             CSI::Window(window) => self.state.perform_csi_window(window),
+            CSI::Presentation(p) => log::warn!("unhandled presentation control: {:?}", p),
+            CSI::StateStack(s) => log::warn!("unhandled state stack control: {:?}", s),
             CSI::Unspecified(unspec) => {
                 log::warn!("unknown unspecified CSI: {:?}", format!("{}", unspec))
             }
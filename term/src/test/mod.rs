@@ -992,7 +992,7 @@ fn test_scroll_margins() {
 
     let margins = CSI::Cursor(termwiz::escape::csi::Cursor::SetTopAndBottomMargins {
         top: OneBased::new(1),
-        bottom: OneBased::new(2),
+        bottom: Some(OneBased::new(2)),
     });
     term.print(format!("{}", margins));
 
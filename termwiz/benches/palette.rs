@@ -0,0 +1,23 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use termwiz::color::{palette_color, xterm_256_palette};
+
+fn palette_lookup(c: &mut Criterion) {
+    c.bench_function("palette_color", |b| {
+        b.iter(|| {
+            for idx in 0..=255u8 {
+                black_box(palette_color(black_box(idx)));
+            }
+        })
+    });
+
+    c.bench_function("xterm_256_palette", |b| {
+        b.iter(|| {
+            for idx in 0..=255u8 {
+                black_box(xterm_256_palette()[black_box(idx) as usize]);
+            }
+        })
+    });
+}
+
+criterion_group!(benches, palette_lookup);
+criterion_main!(benches);
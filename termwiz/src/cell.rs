@@ -1,5 +1,5 @@
 //! Model a cell in the terminal display
-use crate::color::{ColorAttribute, PaletteIndex};
+use crate::color::{ColorAttribute, PaletteIndex, RgbColor};
 pub use crate::emoji::Presentation;
 pub use crate::escape::osc::Hyperlink;
 use crate::image::ImageCell;
@@ -178,6 +178,29 @@ impl Default for Intensity {
     }
 }
 
+/// Computes the color that text with the specified `intensity` is
+/// rendered in when its foreground color is `color`.
+/// `Half` (faint) text is dimmed to 40% of the light output, which is
+/// roughly two thirds of its sRGB value.  `Normal` and `Bold` text are
+/// unchanged; see `apply_intensity_brighten_bold` for renderers that
+/// also show bold text in a brighter color.
+pub fn apply_intensity(color: RgbColor, intensity: Intensity) -> RgbColor {
+    match intensity {
+        Intensity::Half => color.scale_brightness(0.4),
+        Intensity::Normal | Intensity::Bold => color,
+    }
+}
+
+/// Like `apply_intensity`, but for renderers that show `Bold` text in a
+/// brighter color as well as, or instead of, a heavier font; `Bold`
+/// moves the color a quarter of the way towards white.
+pub fn apply_intensity_brighten_bold(color: RgbColor, intensity: Intensity) -> RgbColor {
+    match intensity {
+        Intensity::Bold => color.blend(&RgbColor::new_8bpc(0xff, 0xff, 0xff), 0.25),
+        _ => apply_intensity(color, intensity),
+    }
+}
+
 /// Specify just how underlined you want your `Cell` to be
 #[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -1099,4 +1122,29 @@ mod test {
             vec![raised_fist.to_string()]
         );
     }
+
+    #[test]
+    fn apply_intensity() {
+        let orange = RgbColor::new_8bpc(0xff, 0x80, 0);
+        assert_eq!(super::apply_intensity(orange, Intensity::Normal), orange);
+        assert_eq!(super::apply_intensity(orange, Intensity::Bold), orange);
+
+        let faint = super::apply_intensity(orange, Intensity::Half);
+        assert!(faint.red() > 0x99 && faint.red() < 0xb3);
+        assert!(faint.green() < orange.green());
+        assert_eq!(faint.blue(), 0);
+
+        assert_eq!(
+            apply_intensity_brighten_bold(orange, Intensity::Normal),
+            orange
+        );
+        assert_eq!(
+            apply_intensity_brighten_bold(orange, Intensity::Half),
+            faint
+        );
+        let bright = apply_intensity_brighten_bold(orange, Intensity::Bold);
+        assert_eq!(bright.red(), 0xff);
+        assert!(bright.green() > orange.green());
+        assert!(bright.blue() > orange.blue());
+    }
 }
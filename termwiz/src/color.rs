@@ -57,6 +57,7 @@ pub type RgbaTuple = (f32, f32, f32, f32);
 
 lazy_static::lazy_static! {
     static ref NAMED_COLORS: HashMap<String, RgbColor> = build_colors();
+    static ref XTERM_256_PALETTE: [RgbColor; 256] = build_xterm_256_palette();
 }
 
 fn build_colors() -> HashMap<String, RgbColor> {
@@ -83,6 +84,143 @@ fn build_colors() -> HashMap<String, RgbColor> {
     map
 }
 
+/// Returns the canonical xterm 256 color palette.
+/// The first 16 entries are the xterm defaults for the ANSI colors,
+/// followed by the 6x6x6 color cube and then the 24 step grey ramp.
+/// The table is computed once and then cached.
+pub fn xterm_256_palette() -> &'static [RgbColor; 256] {
+    &XTERM_256_PALETTE
+}
+
+/// Returns the color at `index` in the canonical xterm 256 color palette
+pub fn palette_color(index: u8) -> RgbColor {
+    XTERM_256_PALETTE[index as usize]
+}
+
+/// Computes a 16 color palette that represents `colors`, such as the
+/// pixels sampled from an image, using median cut quantization in
+/// linear colorspace.
+/// The bucket with the widest range along any channel is repeatedly
+/// split at its median along that channel, and each entry is the mean
+/// of the colors in a bucket, so the result is deterministic.
+/// If `colors` has too little variation to fill 16 buckets, the
+/// representative colors are repeated to fill the palette.
+/// An empty input produces a palette of black.
+pub fn derive_palette_16(colors: &[RgbColor]) -> [RgbColor; 16] {
+    let mut palette = [RgbColor::default(); 16];
+    if colors.is_empty() {
+        return palette;
+    }
+
+    fn widest_channel(bucket: &[[f32; 3]]) -> (usize, f32) {
+        (0..3)
+            .map(|chan| {
+                let (min, max) = bucket
+                    .iter()
+                    .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), c| {
+                        (min.min(c[chan]), max.max(c[chan]))
+                    });
+                (chan, max - min)
+            })
+            .fold(
+                (0, 0.),
+                |best, cand| if cand.1 > best.1 { cand } else { best },
+            )
+    }
+
+    let mut buckets: Vec<Vec<[f32; 3]>> = vec![colors
+        .iter()
+        .map(|c| {
+            let (red, green, blue, _) = c.to_linear_tuple_rgba();
+            [red, green, blue]
+        })
+        .collect()];
+
+    while buckets.len() < palette.len() {
+        // Ties go to the earliest bucket
+        let (idx, chan, range) = buckets
+            .iter()
+            .enumerate()
+            .map(|(idx, bucket)| {
+                let (chan, range) = widest_channel(bucket);
+                (idx, chan, range)
+            })
+            .fold(
+                (0, 0, 0.),
+                |best, cand| if cand.2 > best.2 { cand } else { best },
+            );
+        if range <= 0. {
+            // Every bucket holds a single distinct color
+            break;
+        }
+
+        let mut lower = buckets.remove(idx);
+        lower.sort_by(|a, b| {
+            a[chan]
+                .partial_cmp(&b[chan])
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        let upper = lower.split_off(lower.len() / 2);
+        buckets.insert(idx, upper);
+        buckets.insert(idx, lower);
+    }
+
+    let representatives: Vec<RgbColor> = buckets
+        .iter()
+        .map(|bucket| {
+            let n = bucket.len() as f32;
+            let sum = bucket.iter().fold([0.; 3], |sum, c| {
+                [sum[0] + c[0], sum[1] + c[1], sum[2] + c[2]]
+            });
+            RgbColor::from_linear_f32(sum[0] / n, sum[1] / n, sum[2] / n)
+        })
+        .collect();
+
+    for (entry, color) in palette.iter_mut().zip(representatives.iter().cycle()) {
+        *entry = *color;
+    }
+    palette
+}
+
+fn build_xterm_256_palette() -> [RgbColor; 256] {
+    let mut colors = [RgbColor::default(); 256];
+
+    static ANSI: [RgbColor; 16] = [
+        RgbColor::new_8bpc(0x00, 0x00, 0x00),
+        RgbColor::new_8bpc(0xcd, 0x00, 0x00),
+        RgbColor::new_8bpc(0x00, 0xcd, 0x00),
+        RgbColor::new_8bpc(0xcd, 0xcd, 0x00),
+        RgbColor::new_8bpc(0x00, 0x00, 0xee),
+        RgbColor::new_8bpc(0xcd, 0x00, 0xcd),
+        RgbColor::new_8bpc(0x00, 0xcd, 0xcd),
+        RgbColor::new_8bpc(0xe5, 0xe5, 0xe5),
+        RgbColor::new_8bpc(0x7f, 0x7f, 0x7f),
+        RgbColor::new_8bpc(0xff, 0x00, 0x00),
+        RgbColor::new_8bpc(0x00, 0xff, 0x00),
+        RgbColor::new_8bpc(0xff, 0xff, 0x00),
+        RgbColor::new_8bpc(0x5c, 0x5c, 0xff),
+        RgbColor::new_8bpc(0xff, 0x00, 0xff),
+        RgbColor::new_8bpc(0x00, 0xff, 0xff),
+        RgbColor::new_8bpc(0xff, 0xff, 0xff),
+    ];
+    colors[0..16].copy_from_slice(&ANSI);
+
+    static RAMP6: [u8; 6] = [0, 0x5f, 0x87, 0xaf, 0xd7, 0xff];
+    for idx in 0..216 {
+        let blue = RAMP6[idx % 6];
+        let green = RAMP6[idx / 6 % 6];
+        let red = RAMP6[idx / 6 / 6 % 6];
+        colors[16 + idx] = RgbColor::new_8bpc(red, green, blue);
+    }
+
+    for idx in 0..24 {
+        let grey = 0x08 + (idx as u8 * 10);
+        colors[232 + idx] = RgbColor::new_8bpc(grey, grey, grey);
+    }
+
+    colors
+}
+
 /// Describes a color in the SRGB colorspace using red, green and blue
 /// components in the range 0-255.
 #[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
@@ -92,6 +230,21 @@ pub struct RgbColor {
     bits: u32,
 }
 
+/// Selects how `RgbColor::blend_mode` combines a color with the
+/// color being composited over it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
+pub enum BlendMode {
+    /// The top color replaces the base color
+    Normal,
+    /// Multiplies the channels, which always darkens
+    Multiply,
+    /// Multiplies the inverted channels, which always lightens
+    Screen,
+    /// Multiplies dark base channels and screens light ones
+    Overlay,
+}
+
 const TEN_BITS: u16 = 0b11_1111_1111;
 const MAX_TEN: f32 = 1023.;
 
@@ -108,6 +261,14 @@ impl RgbColor {
         }
     }
 
+    /// Construct a color from a packed `0xRRGGBB` value, such as
+    /// a hex literal.  The most significant byte is ignored.
+    pub const fn from_u32_rgb(v: u32) -> Self {
+        Self {
+            bits: v & 0xff_ff_ff,
+        }
+    }
+
     /// Construct a color from discrete red, green, blue values
     /// in the range 0-1023.
     pub const fn new_10bpc(red: u16, green: u16, blue: u16) -> Self {
@@ -121,11 +282,24 @@ impl RgbColor {
 
     /// Construct a color from discrete red, green, blue values
     /// in the range 0.0-1.0 in the sRGB colorspace.
+    /// Values are rounded to the nearest 10bpc level; values outside
+    /// of the 0.0-1.0 range are clamped, and NaN or infinite values
+    /// are treated as 0.0.
     pub fn new_f32(red: f32, green: f32, blue: f32) -> Self {
-        let red = (red * MAX_TEN) as u16;
-        let green = (green * MAX_TEN) as u16;
-        let blue = (blue * MAX_TEN) as u16;
-        Self::new_10bpc(red, green, blue)
+        fn quantize(v: f32) -> u16 {
+            if !v.is_finite() {
+                0
+            } else {
+                (v * MAX_TEN).round().max(0.).min(MAX_TEN) as u16
+            }
+        }
+        Self::new_10bpc(quantize(red), quantize(green), quantize(blue))
+    }
+
+    /// Construct a color from an index into the 256 color palette,
+    /// using the canonical xterm values for each entry.
+    pub fn from_ansi_256(index: u8) -> Self {
+        palette_color(index)
     }
 
     /// Returns red, green, blue as 8bpc values.
@@ -148,6 +322,83 @@ impl RgbColor {
         }
     }
 
+    /// Returns the red channel as an 8bpc value.
+    pub fn red(&self) -> u8 {
+        self.to_tuple_rgb8().0
+    }
+
+    /// Returns the green channel as an 8bpc value.
+    pub fn green(&self) -> u8 {
+        self.to_tuple_rgb8().1
+    }
+
+    /// Returns the blue channel as an 8bpc value.
+    pub fn blue(&self) -> u8 {
+        self.to_tuple_rgb8().2
+    }
+
+    /// Returns the largest of the 8bpc red, green and blue values.
+    pub fn max_channel(&self) -> u8 {
+        let (r, g, b) = self.to_tuple_rgb8();
+        r.max(g).max(b)
+    }
+
+    /// Returns the smallest of the 8bpc red, green and blue values.
+    pub fn min_channel(&self) -> u8 {
+        let (r, g, b) = self.to_tuple_rgb8();
+        r.min(g).min(b)
+    }
+
+    /// Returns the mean of the 8bpc red, green and blue values,
+    /// rounded to the nearest integer.
+    pub fn avg_channel(&self) -> u8 {
+        let (r, g, b) = self.to_tuple_rgb8();
+        ((u16::from(r) + u16::from(g) + u16::from(b) + 1) / 3) as u8
+    }
+
+    /// Returns true if the 8bpc channels differ from each other by
+    /// no more than `tolerance`, which means that the color is a
+    /// shade of gray, or close to one.
+    pub fn is_monochrome(&self, tolerance: u8) -> bool {
+        self.max_channel() - self.min_channel() <= tolerance
+    }
+
+    /// Returns the red channel as a 10bpc value.
+    pub fn red10(&self) -> u16 {
+        self.to_tuple_rgb10().0
+    }
+
+    /// Returns the green channel as a 10bpc value.
+    pub fn green10(&self) -> u16 {
+        self.to_tuple_rgb10().1
+    }
+
+    /// Returns the blue channel as a 10bpc value.
+    pub fn blue10(&self) -> u16 {
+        self.to_tuple_rgb10().2
+    }
+
+    /// Returns true if the color is stored with 10 bits per channel.
+    pub fn is_10bpc(self) -> bool {
+        self.bits & 0x8000_0000 != 0
+    }
+
+    /// Returns red, green, blue as 10bpc values in the range 0-1023.
+    /// Will scale up from 8bpc if that is the internal storage.
+    pub fn to_tuple_rgb10(self) -> (u16, u16, u16) {
+        if self.is_10bpc() {
+            (
+                (self.bits >> 20) as u16 & TEN_BITS,
+                (self.bits >> 10) as u16 & TEN_BITS,
+                self.bits as u16 & TEN_BITS,
+            )
+        } else {
+            let (red, green, blue) = self.to_tuple_rgb8();
+            let scale = |v: u8| ((v as f32 / 255.0) * MAX_TEN).round() as u16;
+            (scale(red), scale(green), scale(blue))
+        }
+    }
+
     /// Returns red, green, blue as floating point values in the range 0.0-1.0.
     /// An alpha channel with the value of 1.0 is included.
     /// The values are in the sRGB colorspace.
@@ -187,6 +438,426 @@ impl RgbColor {
         (to_linear(red), to_linear(green), to_linear(blue), 1.0)
     }
 
+    /// Construct a color from red, green, blue values in the range
+    /// 0.0-1.0 in linear colorspace, converting them to sRGB.
+    fn from_linear_f32(red: f32, green: f32, blue: f32) -> Self {
+        fn to_srgb(v: f32) -> f32 {
+            if v <= 0.0031308 {
+                v * 12.92
+            } else {
+                1.055 * v.powf(1.0 / 2.4) - 0.055
+            }
+        }
+        Self::new_f32(to_srgb(red), to_srgb(green), to_srgb(blue))
+    }
+
+    /// Returns the color that is `amount` of the way from self to `other`,
+    /// where `amount` is in the range 0.0-1.0.
+    /// The interpolation is performed in linear colorspace.
+    pub fn blend(&self, other: &RgbColor, amount: f32) -> Self {
+        let amount = amount.max(0.).min(1.);
+        let (r1, g1, b1, _) = self.to_linear_tuple_rgba();
+        let (r2, g2, b2, _) = other.to_linear_tuple_rgba();
+        let mix = |a: f32, b: f32| a + (b - a) * amount;
+        Self::from_linear_f32(mix(r1, r2), mix(g1, g2), mix(b1, b2))
+    }
+
+    /// Converts self to the Oklab colorspace, returning the
+    /// lightness, green-red and blue-yellow components.
+    /// See <https://bottosson.github.io/posts/oklab/>
+    fn to_oklab(self) -> (f64, f64, f64) {
+        let (r, g, b, _) = self.to_linear_tuple_rgba();
+        let (r, g, b) = (r as f64, g as f64, b as f64);
+        let l = (0.412_221_470_8 * r + 0.536_332_536_3 * g + 0.051_445_992_9 * b).cbrt();
+        let m = (0.211_903_498_2 * r + 0.680_699_545_1 * g + 0.107_396_956_6 * b).cbrt();
+        let s = (0.088_302_461_9 * r + 0.281_718_837_6 * g + 0.629_978_700_5 * b).cbrt();
+        (
+            0.210_454_255_3 * l + 0.793_617_785_0 * m - 0.004_072_046_8 * s,
+            1.977_998_495_1 * l - 2.428_592_205_0 * m + 0.450_593_709_9 * s,
+            0.025_904_037_1 * l + 0.782_771_766_2 * m - 0.808_675_766_0 * s,
+        )
+    }
+
+    /// Construct a color from Oklab components, as returned by `to_oklab`
+    fn from_oklab(lightness: f64, a: f64, b: f64) -> Self {
+        let l = (lightness + 0.396_337_777_4 * a + 0.215_803_757_3 * b).powi(3);
+        let m = (lightness - 0.105_561_345_8 * a - 0.063_854_172_8 * b).powi(3);
+        let s = (lightness - 0.089_484_177_5 * a - 1.291_485_548_0 * b).powi(3);
+        Self::from_linear_f32(
+            (4.076_741_662_1 * l - 3.307_711_591_3 * m + 0.230_969_929_2 * s) as f32,
+            (-1.268_438_004_6 * l + 2.609_757_401_1 * m - 0.341_319_396_5 * s) as f32,
+            (-0.004_196_086_3 * l - 0.703_418_614_7 * m + 1.707_614_701_0 * s) as f32,
+        )
+    }
+
+    /// Returns the color that is `t` of the way from self to `other`,
+    /// where `t` is in the range 0.0-1.0, interpolating in the Oklab
+    /// colorspace.
+    /// Oklab is designed to be perceptually uniform, so gradients
+    /// produced this way have more even steps in perceived lightness
+    /// and hue than those produced by `blend`.
+    pub fn lerp_oklab(&self, other: &RgbColor, t: f32) -> Self {
+        if t <= 0. {
+            return *self;
+        }
+        if t >= 1. {
+            return *other;
+        }
+        let t = t as f64;
+        let (l1, a1, b1) = self.to_oklab();
+        let (l2, a2, b2) = other.to_oklab();
+        let mix = |a: f64, b: f64| a + (b - a) * t;
+        Self::from_oklab(mix(l1, l2), mix(a1, a2), mix(b1, b2))
+    }
+
+    /// Composites `other` over self using the specified blend mode.
+    /// Self is the base layer and `other` is the top layer.
+    /// As with most image editors, the channels are combined
+    /// in the sRGB colorspace rather than in linear colorspace.
+    pub fn blend_mode(&self, other: &RgbColor, mode: BlendMode) -> Self {
+        let (r1, g1, b1, _) = self.to_tuple_rgba();
+        let (r2, g2, b2, _) = other.to_tuple_rgba();
+        let channel = |base: f32, top: f32| match mode {
+            BlendMode::Normal => top,
+            BlendMode::Multiply => base * top,
+            BlendMode::Screen => 1. - (1. - base) * (1. - top),
+            BlendMode::Overlay => {
+                if base < 0.5 {
+                    2. * base * top
+                } else {
+                    1. - 2. * (1. - base) * (1. - top)
+                }
+            }
+        };
+        Self::new_f32(channel(r1, r2), channel(g1, g2), channel(b1, b2))
+    }
+
+    /// Returns a copy of self with the intensity of each channel
+    /// multiplied by `factor` in linear colorspace, such as `0.5` to
+    /// dim a color to half of its intensity.
+    /// The result is clamped to the valid range.
+    /// Unlike `with_lightness`, this scales the physical light output
+    /// rather than the perceptual HSL lightness.
+    pub fn scale_brightness(&self, factor: f32) -> Self {
+        let factor = factor.max(0.);
+        let (red, green, blue, _) = self.to_linear_tuple_rgba();
+        let scale = |v: f32| (v * factor).min(1.);
+        Self::from_linear_f32(scale(red), scale(green), scale(blue))
+    }
+
+    /// Returns `steps` evenly spaced colors going from self to `to`,
+    /// including both endpoints, interpolated in linear colorspace.
+    /// A `steps` value of 0 yields an empty gradient, while 1 yields
+    /// just self.
+    pub fn gradient(&self, to: &RgbColor, steps: usize) -> Vec<RgbColor> {
+        match steps {
+            0 => vec![],
+            1 => vec![*self],
+            _ => {
+                let last = steps - 1;
+                (0..steps)
+                    .map(|i| match i {
+                        0 => *self,
+                        i if i == last => *to,
+                        i => self.blend(to, i as f32 / last as f32),
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Construct a color from cyan, magenta, yellow and black components
+    /// in the range 0.0-1.0.
+    /// This is the naive conversion that doesn't take into account
+    /// any ICC color profile, so it is only an approximation of how
+    /// the color would appear in print.
+    pub fn from_cmyk(cyan: f32, magenta: f32, yellow: f32, black: f32) -> Self {
+        let clamp = |v: f32| v.max(0.).min(1.);
+        let white = 1. - clamp(black);
+        Self::new_f32(
+            (1. - clamp(cyan)) * white,
+            (1. - clamp(magenta)) * white,
+            (1. - clamp(yellow)) * white,
+        )
+    }
+
+    /// Returns the color as `(cyan, magenta, yellow, black)` components
+    /// in the range 0.0-1.0.
+    /// This is the naive conversion that doesn't take into account
+    /// any ICC color profile, so it is only an approximation of how
+    /// the color would appear in print.
+    pub fn to_cmyk(&self) -> (f32, f32, f32, f32) {
+        let (red, green, blue, _alpha) = self.to_tuple_rgba();
+        let white = red.max(green).max(blue);
+        if white == 0. {
+            return (0., 0., 0., 1.);
+        }
+        (
+            (white - red) / white,
+            (white - green) / white,
+            (white - blue) / white,
+            1. - white,
+        )
+    }
+
+    /// Construct a color that approximates the color of a blackbody
+    /// radiator at the specified temperature, such as `2700.` for a warm
+    /// white or `6500.` for daylight white.
+    /// `kelvin` is clamped to the range 1000-40000, over which the
+    /// approximation is valid.
+    /// This uses the curve fit described in
+    /// <https://tannerhelland.com/2012/09/18/convert-temperature-rgb-algorithm-code.html>
+    pub fn from_temperature(kelvin: f32) -> Self {
+        let temp = f64::from(kelvin.max(1000.).min(40000.)) / 100.;
+        let clamp = |v: f64| v.round().max(0.).min(255.) as u8;
+
+        let red = if temp <= 66. {
+            255.
+        } else {
+            329.698727446 * (temp - 60.).powf(-0.1332047592)
+        };
+        let green = if temp <= 66. {
+            99.4708025861 * temp.ln() - 161.1195681661
+        } else {
+            288.1221695283 * (temp - 60.).powf(-0.0755148492)
+        };
+        let blue = if temp >= 66. {
+            255.
+        } else if temp <= 19. {
+            0.
+        } else {
+            138.5177312231 * (temp - 10.).ln() - 305.0447927307
+        };
+
+        Self::new_8bpc(clamp(red), clamp(green), clamp(blue))
+    }
+
+    /// Construct a color from the HSL color space.
+    /// `hue` is measured in degrees; values outside the range 0-360
+    /// are wrapped around.  `sat` and `light` are in the range 0.0-1.0.
+    pub fn from_hsl(hue: f32, sat: f32, light: f32) -> Self {
+        let hue = hue.rem_euclid(360.);
+        let a = sat * light.min(1. - light);
+        let f = |n: f32| -> f32 {
+            let k = (n + hue / 30.) % 12.;
+            light - a * (k - 3.).min(9. - k).min(1.).max(-1.)
+        };
+        Self::new_f32(f(0.), f(8.), f(4.))
+    }
+
+    /// Returns the color as `(hue, sat, light)` in the HSL color space.
+    /// `hue` is in degrees in the range 0-360, while `sat` and `light`
+    /// are in the range 0.0-1.0.
+    /// The hue of an achromatic (grey) color is reported as 0.
+    pub fn to_hsl(self) -> (f32, f32, f32) {
+        let (red, green, blue, _alpha) = self.to_tuple_rgba();
+        let max = red.max(green).max(blue);
+        let min = red.min(green).min(blue);
+        let light = (max + min) / 2.;
+        let chroma = max - min;
+        if chroma == 0. {
+            return (0., 0., light);
+        }
+        let sat = chroma / (1. - (2. * light - 1.).abs());
+        let hue = if max == red {
+            ((green - blue) / chroma).rem_euclid(6.)
+        } else if max == green {
+            (blue - red) / chroma + 2.
+        } else {
+            (red - green) / chroma + 4.
+        } * 60.;
+        (hue, sat, light)
+    }
+
+    /// Returns a copy of self with the hue set to `hue` degrees,
+    /// keeping the saturation and lightness.
+    /// Achromatic colors have no hue, so they are returned unchanged.
+    pub fn with_hue(self, hue: f32) -> Self {
+        let (_, sat, light) = self.to_hsl();
+        if sat == 0. {
+            return self;
+        }
+        Self::from_hsl(hue, sat, light)
+    }
+
+    /// Returns a copy of self with the saturation set to `sat`,
+    /// which is in the range 0.0-1.0, keeping the hue and lightness.
+    pub fn with_saturation(self, sat: f32) -> Self {
+        let (hue, _, light) = self.to_hsl();
+        Self::from_hsl(hue, sat.max(0.).min(1.), light)
+    }
+
+    /// Returns a copy of self with the lightness set to `light`,
+    /// which is in the range 0.0-1.0, keeping the hue and saturation.
+    pub fn with_lightness(self, light: f32) -> Self {
+        let (hue, sat, _) = self.to_hsl();
+        Self::from_hsl(hue, sat, light.max(0.).min(1.))
+    }
+
+    /// Returns an approximation of the perceptual distance between
+    /// self and other, using the "redmean" weighted euclidean distance.
+    /// See <https://www.compuphase.com/cmetric.htm>
+    fn perceptual_distance(self, other: RgbColor) -> f32 {
+        let (r1, g1, b1) = self.to_tuple_rgb8();
+        let (r2, g2, b2) = other.to_tuple_rgb8();
+        let rmean = (r1 as f32 + r2 as f32) / 2.;
+        let r = r1 as f32 - r2 as f32;
+        let g = g1 as f32 - g2 as f32;
+        let b = b1 as f32 - b2 as f32;
+        (((512. + rmean) * r * r) / 256. + 4. * g * g + ((767. - rmean) * b * b) / 256.).sqrt()
+    }
+
+    /// Returns the perceptual error that would result from replacing
+    /// self with the closest entry in `palette`.
+    /// Renderers can use this to decide whether downgrading a true color
+    /// value to a palette index is acceptable, or whether the true color
+    /// value should be retained.
+    /// Returns `f32::INFINITY` if the palette is empty.
+    pub fn downgrade_error(&self, palette: &[RgbColor]) -> f32 {
+        self.nearest_with(palette, |a, b| a.perceptual_distance(*b))
+            .1
+    }
+
+    /// Returns the index of the entry in `palette` that is closest to
+    /// self according to `metric`, along with its distance.
+    /// `metric` is called with self as its first argument, which allows
+    /// callers to plug in their preferred measure, such as euclidean
+    /// distance in RGB or Oklab.
+    /// When several entries are equally close, the first is returned.
+    /// Returns `(0, f32::INFINITY)` if the palette is empty.
+    pub fn nearest_with<F: Fn(&RgbColor, &RgbColor) -> f32>(
+        &self,
+        palette: &[RgbColor],
+        metric: F,
+    ) -> (usize, f32) {
+        let mut best = (0, f32::INFINITY);
+        for (idx, color) in palette.iter().enumerate() {
+            let dist = metric(self, color);
+            if dist < best.1 {
+                best = (idx, dist);
+            }
+        }
+        best
+    }
+
+    /// Returns the indices of the two entries in `palette` that are
+    /// perceptually closest to self, nearest first, along with the
+    /// factor in the range 0.0-1.0 that places self between them.
+    /// The factor is the projection of self onto the line between the
+    /// two entries in linear colorspace, so that blending the first
+    /// entry towards the second by that amount approximates self;
+    /// ordered dithering can use it as the threshold for choosing
+    /// between them.
+    /// Only the first 256 entries of `palette` are considered.
+    /// If `palette` has a single entry then both indices refer to it,
+    /// and if it is empty then `(0, 0, 0.0)` is returned.
+    pub fn two_nearest_palette(&self, palette: &[RgbColor]) -> (u8, u8, f32) {
+        let mut nearest: Option<(usize, f32)> = None;
+        let mut second: Option<(usize, f32)> = None;
+        for (idx, color) in palette.iter().enumerate().take(256) {
+            let dist = self.perceptual_distance(*color);
+            match nearest {
+                Some((_, best)) if dist >= best => match second {
+                    Some((_, next)) if dist >= next => {}
+                    _ => second = Some((idx, dist)),
+                },
+                _ => {
+                    second = nearest;
+                    nearest = Some((idx, dist));
+                }
+            }
+        }
+
+        let (first, second) = match (nearest, second) {
+            (Some((first, _)), Some((second, _))) => (first, second),
+            (Some((first, _)), None) => return (first as u8, first as u8, 0.),
+            _ => return (0, 0, 0.),
+        };
+
+        let (r, g, b, _) = self.to_linear_tuple_rgba();
+        let (r1, g1, b1, _) = palette[first].to_linear_tuple_rgba();
+        let (r2, g2, b2, _) = palette[second].to_linear_tuple_rgba();
+        let (dr, dg, db) = (r2 - r1, g2 - g1, b2 - b1);
+        let len = dr * dr + dg * dg + db * db;
+        let factor = if len > 0. {
+            ((r - r1) * dr + (g - g1) * dg + (b - b1) * db) / len
+        } else {
+            0.
+        };
+
+        (first as u8, second as u8, factor.max(0.).min(1.))
+    }
+
+    /// Returns the relative luminance of self, as defined by WCAG 2.
+    fn relative_luminance(self) -> f32 {
+        let (r, g, b, _) = self.to_linear_tuple_rgba();
+        0.2126 * r + 0.7152 * g + 0.0722 * b
+    }
+
+    /// Returns the WCAG 2 contrast ratio between self and `other`,
+    /// which ranges from 1.0 for identical luminance up to 21.0 for
+    /// black against white.
+    pub fn contrast_ratio(&self, other: &RgbColor) -> f32 {
+        let a = self.relative_luminance();
+        let b = other.relative_luminance();
+        (a.max(b) + 0.05) / (a.min(b) + 0.05)
+    }
+
+    /// Returns the entry in `candidates` that has the highest contrast
+    /// ratio against self, such as picking a foreground color for
+    /// self as the background.
+    /// When several candidates tie, the first of them is returned.
+    /// Returns None if `candidates` is empty.
+    pub fn best_contrast<'a>(&self, candidates: &'a [RgbColor]) -> Option<&'a RgbColor> {
+        let mut best: Option<(&'a RgbColor, f32)> = None;
+        for color in candidates {
+            let ratio = self.contrast_ratio(color);
+            match best {
+                Some((_, best_ratio)) if ratio <= best_ratio => {}
+                _ => best = Some((color, ratio)),
+            }
+        }
+        best.map(|(color, _)| color)
+    }
+
+    /// Returns a copy of self with its HSL lightness changed by the
+    /// smallest amount that achieves a contrast ratio of at least
+    /// `target_ratio` against `background`, keeping the hue and
+    /// saturation, such as making a theme color legible at the WCAG
+    /// AA level of 4.5.
+    /// Both lightening and darkening are tried in small steps, and
+    /// whichever reaches the target first is used.
+    /// Self is returned unchanged if it already meets the target, and
+    /// if neither direction can reach it then the color with the
+    /// highest contrast that was tried is returned.
+    pub fn adjust_for_contrast(&self, background: &RgbColor, target_ratio: f32) -> Self {
+        const STEP: f32 = 0.005;
+        let mut best = (*self, self.contrast_ratio(background));
+        if best.1 >= target_ratio {
+            return *self;
+        }
+
+        let (hue, sat, light) = self.to_hsl();
+        let mut delta = STEP;
+        while light - delta > -STEP || light + delta < 1. + STEP {
+            for &candidate in &[light - delta, light + delta] {
+                if candidate <= -STEP || candidate >= 1. + STEP {
+                    continue;
+                }
+                let color = Self::from_hsl(hue, sat, candidate.max(0.).min(1.));
+                let ratio = color.contrast_ratio(background);
+                if ratio > best.1 {
+                    best = (color, ratio);
+                }
+            }
+            if best.1 >= target_ratio {
+                break;
+            }
+            delta += STEP;
+        }
+        best.0
+    }
+
     /// Construct a color from an X11/SVG/CSS3 color name.
     /// Returns None if the supplied name is not recognized.
     /// The list of names can be found here:
@@ -195,6 +866,16 @@ impl RgbColor {
         NAMED_COLORS.get(&name.to_ascii_lowercase()).cloned()
     }
 
+    /// Returns true if `name` is a recognized color name whose value
+    /// is the same as self when both are reduced to 8 bits per channel.
+    /// Returns false if the name is not recognized.
+    pub fn matches_named(&self, name: &str) -> bool {
+        match Self::from_named(name) {
+            Some(named) => named.to_tuple_rgb8() == self.to_tuple_rgb8(),
+            None => false,
+        }
+    }
+
     /// Returns a string of the form `#RRGGBB`
     pub fn to_rgb_string(self) -> String {
         let (red, green, blue) = self.to_tuple_rgb8();
@@ -216,6 +897,10 @@ impl RgbColor {
     /// in the HSL color space, where `hue` is measure in degrees and has
     /// a range of 0-360, and both `sat` and `light` are specified in percentage
     /// in the range 0-100.
+    /// A bare decimal triple such as `255,0,0` is accepted as a last
+    /// resort when none of the prefixed forms match.  Each of the three
+    /// fields must consist solely of decimal digits in the range 0-255,
+    /// so that it can never be confused with a color name.
     pub fn from_rgb_str(s: &str) -> Option<RgbColor> {
         if s.len() > 0 && s.as_bytes()[0] == b'#' {
             // Probably `#RGB`
@@ -325,21 +1010,21 @@ impl RgbColor {
                 // Expected to be percentage in range 0-100
                 let l: i32 = fields[2].parse().ok()?;
 
-                fn hsl_to_rgb(hue: i32, sat: i32, light: i32) -> (f32, f32, f32) {
-                    let hue = hue % 360;
-                    let hue = if hue < 0 { hue + 360 } else { hue } as f32;
-                    let sat = sat as f32 / 100.;
-                    let light = light as f32 / 100.;
-                    let a = sat * light.min(1. - light);
-                    let f = |n: f32| -> f32 {
-                        let k = (n + hue / 30.) % 12.;
-                        light - a * (k - 3.).min(9. - k).min(1.).max(-1.)
-                    };
-                    (f(0.), f(8.), f(4.))
-                }
-
-                let (r, g, b) = hsl_to_rgb(h, s, l);
-                Some(Self::new_f32(r, g, b))
+                Some(Self::from_hsl(h as f32, s as f32 / 100., l as f32 / 100.))
+            } else {
+                None
+            }
+        } else if s.contains(',') {
+            let fields: Vec<_> = s.split(',').map(str::trim).collect();
+            if fields.len() == 3
+                && fields
+                    .iter()
+                    .all(|f| !f.is_empty() && f.bytes().all(|b| b.is_ascii_digit()))
+            {
+                let red: u8 = fields[0].parse().ok()?;
+                let green: u8 = fields[1].parse().ok()?;
+                let blue: u8 = fields[2].parse().ok()?;
+                Some(Self::new_8bpc(red, green, blue))
             } else {
                 None
             }
@@ -355,11 +1040,24 @@ impl RgbColor {
     /// in the HSL color space, where `hue` is measure in degrees and has
     /// a range of 0-360, and both `sat` and `light` are specified in percentage
     /// in the range 0-100.
+    /// Surrounding whitespace is ignored, as is the case of the `rgb:`
+    /// and `hsl:` prefixes.
     /// Returns None if the supplied name is not recognized.
     /// The list of names can be found here:
     /// <https://ogeon.github.io/docs/palette/master/palette/named/index.html>
     pub fn from_named_or_rgb_string(s: &str) -> Option<Self> {
-        RgbColor::from_rgb_str(&s).or_else(|| RgbColor::from_named(&s))
+        let s = s.trim();
+        let normalized;
+        let s = match s.get(..4) {
+            Some(prefix)
+                if prefix.eq_ignore_ascii_case("rgb:") || prefix.eq_ignore_ascii_case("hsl:") =>
+            {
+                normalized = format!("{}{}", prefix.to_ascii_lowercase(), &s[4..]);
+                normalized.as_str()
+            }
+            _ => s,
+        };
+        RgbColor::from_rgb_str(s).or_else(|| RgbColor::from_named(s))
     }
 }
 
@@ -394,6 +1092,70 @@ impl<'de> Deserialize<'de> for RgbColor {
     }
 }
 
+/// Wraps an `RgbColor` so that it is serialized as a struct with
+/// explicit channels, such as `{"r": 255, "g": 0, "b": 0}`, rather
+/// than as a color string, for APIs that want structured color data.
+/// Deserialization accepts either the struct or any of the string
+/// forms accepted by `RgbColor`, but requires a self-describing
+/// format such as JSON.
+/// The channels are 8 bits each, so 10bpc colors lose precision.
+#[cfg(feature = "use_serde")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RgbChannels(pub RgbColor);
+
+#[cfg(feature = "use_serde")]
+impl From<RgbColor> for RgbChannels {
+    fn from(color: RgbColor) -> Self {
+        Self(color)
+    }
+}
+
+#[cfg(feature = "use_serde")]
+impl From<RgbChannels> for RgbColor {
+    fn from(channels: RgbChannels) -> Self {
+        channels.0
+    }
+}
+
+#[cfg(feature = "use_serde")]
+#[derive(Serialize, Deserialize)]
+struct RgbChannelsStruct {
+    r: u8,
+    g: u8,
+    b: u8,
+}
+
+#[cfg(feature = "use_serde")]
+impl Serialize for RgbChannels {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let (r, g, b) = self.0.to_tuple_rgb8();
+        RgbChannelsStruct { r, g, b }.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "use_serde")]
+impl<'de> Deserialize<'de> for RgbChannels {
+    fn deserialize<D>(deserializer: D) -> Result<RgbChannels, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Input {
+            Channels(RgbChannelsStruct),
+            String(RgbColor),
+        }
+
+        Ok(RgbChannels(match Input::deserialize(deserializer)? {
+            Input::Channels(RgbChannelsStruct { r, g, b }) => RgbColor::new_8bpc(r, g, b),
+            Input::String(color) => color,
+        }))
+    }
+}
+
 /// An index into the fixed color palette.
 pub type PaletteIndex = u8;
 
@@ -401,6 +1163,7 @@ pub type PaletteIndex = u8;
 /// This differs from `ColorAttribute` in that this type can only
 /// specify one of the possible color types at once, whereas the
 /// `ColorAttribute` type can specify a TrueColor value and a fallback.
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum ColorSpec {
     Default,
@@ -450,6 +1213,67 @@ impl Default for ColorAttribute {
     }
 }
 
+impl ColorAttribute {
+    /// Resolves the color that will be displayed, given whether the
+    /// terminal supports true color and its palette.
+    /// Returns None if the default color will be used.
+    fn resolve(&self, truecolor: bool, palette: &[RgbColor; 256]) -> Option<RgbColor> {
+        match self {
+            ColorAttribute::TrueColorWithPaletteFallback(color, _)
+            | ColorAttribute::TrueColorWithDefaultFallback(color)
+                if truecolor =>
+            {
+                Some(*color)
+            }
+            ColorAttribute::TrueColorWithPaletteFallback(_, idx)
+            | ColorAttribute::PaletteIndex(idx) => Some(palette[*idx as usize]),
+            ColorAttribute::TrueColorWithDefaultFallback(_) | ColorAttribute::Default => None,
+        }
+    }
+
+    /// Returns true if self and other will be displayed using the same
+    /// color, given whether the terminal supports true color and the
+    /// palette that it uses.
+    /// This is useful when diffing attributes to avoid emitting
+    /// redundant SGR sequences.
+    pub fn renders_same(
+        &self,
+        other: &ColorAttribute,
+        truecolor: bool,
+        palette: &[RgbColor; 256],
+    ) -> bool {
+        self.resolve(truecolor, palette) == other.resolve(truecolor, palette)
+    }
+
+    /// Resolves self to the concrete color that it displays as when
+    /// used in the position given by `role`, such as when exporting
+    /// the screen to a format that has no notion of default colors.
+    /// True color values are used in preference to their fallbacks,
+    /// and `Default` resolves to `default_fg` or `default_bg`
+    /// according to `role`.
+    pub fn flatten(
+        &self,
+        default_fg: RgbColor,
+        default_bg: RgbColor,
+        role: ColorRole,
+        palette: &[RgbColor; 256],
+    ) -> RgbColor {
+        self.resolve(true, palette).unwrap_or(match role {
+            ColorRole::Foreground => default_fg,
+            ColorRole::Background => default_bg,
+        })
+    }
+}
+
+/// The position in which a color is used, which determines the
+/// color that `ColorAttribute::Default` refers to.
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ColorRole {
+    Foreground,
+    Background,
+}
+
 impl From<AnsiColor> for ColorAttribute {
     fn from(col: AnsiColor) -> Self {
         ColorAttribute::PaletteIndex(col as u8)
@@ -466,6 +1290,27 @@ impl From<ColorSpec> for ColorAttribute {
     }
 }
 
+/// Computes the `(foreground, background)` colors to render when
+/// inverse video (`Sgr::Inverse`) is `inverse`, by swapping `fg` and `bg`
+/// when it is set.
+/// Note that `ColorAttribute::Default` means the default for the position
+/// that it occupies, so a `Default` that has been swapped into the
+/// foreground position must be resolved using the default *background*
+/// color and vice versa.  In particular, when both are `Default` the
+/// returned pair is unchanged, but the cell must still be rendered using
+/// the default background color as its foreground.
+pub fn apply_inverse(
+    fg: ColorAttribute,
+    bg: ColorAttribute,
+    inverse: bool,
+) -> (ColorAttribute, ColorAttribute) {
+    if inverse {
+        (bg, fg)
+    } else {
+        (fg, bg)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -475,12 +1320,270 @@ mod tests {
         assert_eq!(dark_green.bits, 0x006400);
     }
 
+    #[test]
+    fn matches_named() {
+        let dark_green = RgbColor::new_8bpc(0, 0x64, 0);
+        assert!(dark_green.matches_named("DarkGreen"));
+        assert!(dark_green.matches_named("darkgreen"));
+        assert!(!dark_green.matches_named("Green"));
+        assert!(!dark_green.matches_named("NotAColor"));
+        assert!(RgbColor::new_10bpc(1023, 0, 0).matches_named("red"));
+    }
+
+    #[test]
+    fn two_nearest_palette() {
+        let black = RgbColor::new_8bpc(0, 0, 0);
+        let white = RgbColor::new_8bpc(0xff, 0xff, 0xff);
+        let red = RgbColor::new_8bpc(0xff, 0, 0);
+        let palette = [black, white];
+
+        // Halfway between black and white in linear colorspace
+        let grey = black.blend(&white, 0.5);
+        let (first, second, factor) = grey.two_nearest_palette(&palette);
+        assert_eq!((first, second), (1, 0));
+        assert!((factor - 0.5).abs() < 0.01, "factor {}", factor);
+
+        // 0x80 is about 21.6% of full intensity in linear colorspace
+        let dark_red = RgbColor::new_8bpc(0x80, 0, 0);
+        let (first, second, factor) = dark_red.two_nearest_palette(&[white, red, black]);
+        assert_eq!((first, second), (2, 1));
+        assert!((factor - 0.216).abs() < 0.001, "factor {}", factor);
+        assert_eq!(white.two_nearest_palette(&palette), (1, 0, 0.));
+        assert_eq!(white.two_nearest_palette(&[red]), (0, 0, 0.));
+        assert_eq!(white.two_nearest_palette(&[]), (0, 0, 0.));
+    }
+
+    #[test]
+    fn adjust_for_contrast() {
+        let white = RgbColor::new_8bpc(0xff, 0xff, 0xff);
+        let black = RgbColor::new_8bpc(0, 0, 0);
+
+        // Just short of AA against white, so it needs darkening a little
+        let gray = RgbColor::new_8bpc(0x77, 0x77, 0x77);
+        assert!(gray.contrast_ratio(&white) < 4.5);
+        let adjusted = gray.adjust_for_contrast(&white, 4.5);
+        assert!(adjusted.contrast_ratio(&white) >= 4.5);
+        assert!(adjusted.red() < gray.red() && adjusted.red() >= 0x70);
+
+        // Darkening can't get there against black, so it is lightened
+        let dark = RgbColor::new_8bpc(0x66, 0x33, 0x33);
+        let adjusted = dark.adjust_for_contrast(&black, 4.5);
+        assert!(adjusted.contrast_ratio(&black) >= 4.5);
+        assert!(adjusted.red() > dark.red());
+        let (hue, _, _) = adjusted.to_hsl();
+        assert!(hue < 1. || hue > 359.);
+
+        assert_eq!(gray.adjust_for_contrast(&black, 4.5), gray);
+        assert_eq!(
+            gray.adjust_for_contrast(&white, 25.).to_tuple_rgba(),
+            black.to_tuple_rgba()
+        );
+    }
+
+    #[test]
+    fn nearest_with() {
+        let palette = [
+            RgbColor::new_8bpc(0, 0, 0),
+            RgbColor::new_8bpc(0xff, 0, 0xc0),
+            RgbColor::new_8bpc(0x80, 0x80, 0x80),
+        ];
+        let color = RgbColor::new_8bpc(0xc0, 0x40, 0xa0);
+
+        let rgb = |a: &RgbColor, b: &RgbColor| {
+            let (r1, g1, b1, _) = a.to_tuple_rgba();
+            let (r2, g2, b2, _) = b.to_tuple_rgba();
+            ((r1 - r2).powi(2) + (g1 - g2).powi(2) + (b1 - b2).powi(2)).sqrt()
+        };
+        let (idx, dist) = color.nearest_with(&palette, rgb);
+        assert_eq!(idx, 1);
+        assert!((dist - rgb(&color, &palette[1])).abs() < 0.0001);
+
+        // Only considering lightness picks the gray instead
+        let lightness = |a: &RgbColor, b: &RgbColor| (a.to_oklab().0 - b.to_oklab().0).abs() as f32;
+        assert_eq!(color.nearest_with(&palette, lightness).0, 2);
+
+        assert_eq!(color.nearest_with(&[], rgb), (0, f32::INFINITY));
+        let expected = palette
+            .iter()
+            .map(|c| color.perceptual_distance(*c))
+            .fold(f32::INFINITY, f32::min);
+        assert_eq!(color.downgrade_error(&palette), expected);
+    }
+
+    #[test]
+    fn channel_stats() {
+        let gray = RgbColor::new_8bpc(0x80, 0x82, 0x7f);
+        assert_eq!(gray.max_channel(), 0x82);
+        assert_eq!(gray.min_channel(), 0x7f);
+        assert_eq!(gray.avg_channel(), 0x80);
+        assert!(gray.is_monochrome(3));
+        assert!(!gray.is_monochrome(2));
+        assert!(RgbColor::new_8bpc(0x40, 0x40, 0x40).is_monochrome(0));
+
+        let orange = RgbColor::new_8bpc(0xff, 0x80, 0);
+        assert_eq!(orange.max_channel(), 0xff);
+        assert_eq!(orange.min_channel(), 0);
+        assert_eq!(orange.avg_channel(), 0x80);
+        assert!(!orange.is_monochrome(0x80));
+        assert!(orange.is_monochrome(0xff));
+    }
+
+    #[test]
+    fn flatten() {
+        let palette = xterm_256_palette();
+        let fg = RgbColor::new_8bpc(0xee, 0xee, 0xee);
+        let bg = RgbColor::new_8bpc(0x11, 0x11, 0x11);
+        let orange = RgbColor::new_8bpc(0xff, 0x80, 0);
+
+        for &role in &[ColorRole::Foreground, ColorRole::Background] {
+            let flatten = |attr: ColorAttribute| attr.flatten(fg, bg, role, palette);
+            assert_eq!(
+                flatten(ColorAttribute::TrueColorWithPaletteFallback(orange, 1)),
+                orange
+            );
+            assert_eq!(
+                flatten(ColorAttribute::TrueColorWithDefaultFallback(orange)),
+                orange
+            );
+            assert_eq!(flatten(ColorAttribute::PaletteIndex(1)), palette[1]);
+            assert_eq!(
+                flatten(ColorAttribute::Default),
+                match role {
+                    ColorRole::Foreground => fg,
+                    ColorRole::Background => bg,
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn best_contrast() {
+        let white = RgbColor::new_8bpc(0xff, 0xff, 0xff);
+        let black = RgbColor::new_8bpc(0, 0, 0);
+        assert!((white.contrast_ratio(&black) - 21.).abs() < 0.01);
+        assert!((black.contrast_ratio(&white) - 21.).abs() < 0.01);
+        assert!((white.contrast_ratio(&white) - 1.).abs() < 0.001);
+
+        let candidates = [
+            RgbColor::new_8bpc(0xee, 0xee, 0xee),
+            RgbColor::new_8bpc(0x20, 0x20, 0x40),
+            RgbColor::new_8bpc(0xff, 0xcc, 0x00),
+            RgbColor::new_8bpc(0x80, 0x80, 0x80),
+        ];
+        assert_eq!(white.best_contrast(&candidates), Some(&candidates[1]));
+        assert_eq!(black.best_contrast(&candidates), Some(&candidates[0]));
+        assert_eq!(white.best_contrast(&[]), None);
+    }
+
+    #[test]
+    fn from_u32_rgb() {
+        const DARK_GREEN: RgbColor = RgbColor::from_u32_rgb(0x006400);
+        assert_eq!(DARK_GREEN, RgbColor::from_named("DarkGreen").unwrap());
+        assert_eq!(
+            RgbColor::from_u32_rgb(0xff_12_34_56),
+            RgbColor::new_8bpc(0x12, 0x34, 0x56)
+        );
+    }
+
     #[test]
     fn from_hsl() {
         let foo = RgbColor::from_rgb_str("hsl:235 100  50").unwrap();
         assert_eq!(foo.to_rgb_string(), "#0015ff");
     }
 
+    #[test]
+    fn from_decimal_triple() {
+        let red = Some(RgbColor::new_8bpc(0xff, 0, 0));
+        assert_eq!(RgbColor::from_rgb_str("255,0,0"), red);
+        assert_eq!(RgbColor::from_rgb_str("255, 0, 0"), red);
+        assert_eq!(RgbColor::from_named_or_rgb_string(" 255,0,0 "), red);
+        assert_eq!(RgbColor::from_rgb_str("300,0,0"), None);
+        assert_eq!(RgbColor::from_rgb_str("255,0"), None);
+        assert_eq!(RgbColor::from_rgb_str("255,0,0,0"), None);
+        assert_eq!(RgbColor::from_rgb_str("+255,0,0"), None);
+        assert_eq!(RgbColor::from_rgb_str("255,,0"), None);
+    }
+
+    #[test]
+    fn derive_palette_16() {
+        assert_eq!(super::derive_palette_16(&[]), [RgbColor::default(); 16]);
+
+        let red = RgbColor::new_8bpc(0xff, 0, 0);
+        let blue = RgbColor::new_8bpc(0, 0, 0xff);
+        let palette = super::derive_palette_16(&[red, red, blue, red, blue]);
+        let mut distinct: Vec<_> = palette.iter().map(|c| c.to_tuple_rgb8()).collect();
+        distinct.sort();
+        distinct.dedup();
+        assert_eq!(distinct, vec![(0, 0, 0xff), (0xff, 0, 0)]);
+
+        // Two noisy clusters produce entries close to one or the other
+        let mut colors = vec![];
+        for i in 0..20u8 {
+            colors.push(RgbColor::new_8bpc(0xf0 + i % 10, i, 0x10));
+            colors.push(RgbColor::new_8bpc(0x10, i, 0xf0 + i % 10));
+        }
+        let palette = super::derive_palette_16(&colors);
+        assert_eq!(palette, super::derive_palette_16(&colors));
+        let near = |c: &RgbColor, target: RgbColor| c.perceptual_distance(target) < 60.;
+        let reddish = RgbColor::new_8bpc(0xf5, 0x0a, 0x10);
+        let bluish = RgbColor::new_8bpc(0x10, 0x0a, 0xf5);
+        assert!(palette.iter().any(|c| near(c, reddish)));
+        assert!(palette.iter().any(|c| near(c, bluish)));
+        assert!(palette.iter().all(|c| near(c, reddish) || near(c, bluish)));
+    }
+
+    #[test]
+    fn hsl_setters() {
+        let red = RgbColor::new_8bpc(0xff, 0, 0);
+        let (h, s, l) = red.to_hsl();
+        assert_eq!((h, s, l), (0., 1., 0.5));
+
+        assert_eq!(red.with_hue(120.).to_rgb_string(), "#00ff00");
+        assert_eq!(red.with_hue(-120.).to_rgb_string(), "#0000ff");
+        assert_eq!(red.with_saturation(0.).to_rgb_string(), "#7f7f7f");
+        assert_eq!(red.with_lightness(1.).to_rgb_string(), "#ffffff");
+        assert_eq!(red.with_lightness(0.).to_rgb_string(), "#000000");
+
+        let (h, s, l) = red.with_lightness(0.25).to_hsl();
+        assert_eq!(h, 0.);
+        assert_eq!(s, 1.);
+        assert!((l - 0.25).abs() < 0.001, "{}", l);
+
+        // Achromatic colors have no hue to rotate
+        let grey = RgbColor::new_8bpc(0x80, 0x80, 0x80);
+        assert_eq!(grey.with_hue(200.), grey);
+    }
+
+    #[test]
+    fn downgrade_error() {
+        let palette = xterm_256_palette();
+        let exact = RgbColor::new_8bpc(0x87, 0xaf, 0xd7);
+        assert!(exact.downgrade_error(palette) < 0.001);
+
+        let between = RgbColor::new_8bpc(0x70, 0x20, 0xc0);
+        assert!(between.downgrade_error(palette) > 10.);
+
+        assert_eq!(exact.downgrade_error(&[]), f32::INFINITY);
+    }
+
+    #[test]
+    fn renders_same() {
+        let palette = xterm_256_palette();
+        let red = RgbColor::new_8bpc(0xff, 0, 0);
+        let with_palette = ColorAttribute::TrueColorWithPaletteFallback(red, 1);
+        let with_default = ColorAttribute::TrueColorWithDefaultFallback(red);
+        let bright_red = ColorAttribute::PaletteIndex(9);
+
+        assert!(with_palette.renders_same(&with_default, true, palette));
+        assert!(with_palette.renders_same(&bright_red, true, palette));
+        assert!(!with_palette.renders_same(&ColorAttribute::Default, true, palette));
+
+        assert!(!with_palette.renders_same(&with_default, false, palette));
+        assert!(!with_palette.renders_same(&bright_red, false, palette));
+        assert!(with_palette.renders_same(&ColorAttribute::PaletteIndex(1), false, palette));
+        assert!(with_default.renders_same(&ColorAttribute::Default, false, palette));
+    }
+
     #[test]
     fn from_rgb() {
         assert!(RgbColor::from_rgb_str("").is_none());
@@ -505,6 +1608,46 @@ mod tests {
         assert_eq!(grey.bits, 0xf0f0f0);
     }
 
+    #[test]
+    fn new_f32_rounding() {
+        assert_eq!(
+            RgbColor::new_f32(1.0, 1.0, 1.0),
+            RgbColor::new_10bpc(1023, 1023, 1023)
+        );
+        assert_eq!(
+            RgbColor::new_f32(0.9999, 0.5, 0.0),
+            RgbColor::new_10bpc(1023, 512, 0)
+        );
+        assert_eq!(
+            RgbColor::new_f32(1.5, -0.5, f32::NAN),
+            RgbColor::new_10bpc(1023, 0, 0)
+        );
+        assert_eq!(
+            RgbColor::new_f32(f32::INFINITY, f32::NEG_INFINITY, 1.0),
+            RgbColor::new_10bpc(0, 0, 1023)
+        );
+    }
+
+    #[test]
+    fn cached_palette() {
+        assert!(std::ptr::eq(xterm_256_palette(), xterm_256_palette()));
+        for idx in 0..=255u8 {
+            assert_eq!(palette_color(idx), xterm_256_palette()[idx as usize]);
+        }
+    }
+
+    #[test]
+    fn from_ansi_256() {
+        assert_eq!(RgbColor::from_ansi_256(0).to_rgb_string(), "#000000");
+        assert_eq!(RgbColor::from_ansi_256(15).to_rgb_string(), "#ffffff");
+        assert_eq!(RgbColor::from_ansi_256(16).to_rgb_string(), "#000000");
+        assert_eq!(RgbColor::from_ansi_256(196).to_rgb_string(), "#ff0000");
+        assert_eq!(RgbColor::from_ansi_256(231).to_rgb_string(), "#ffffff");
+        assert_eq!(RgbColor::from_ansi_256(232).to_rgb_string(), "#080808");
+        assert_eq!(RgbColor::from_ansi_256(244).to_rgb_string(), "#808080");
+        assert_eq!(RgbColor::from_ansi_256(255).to_rgb_string(), "#eeeeee");
+    }
+
     #[cfg(feature = "use_serde")]
     #[test]
     fn roundtrip_rgbcolor() {
@@ -512,4 +1655,223 @@ mod tests {
         eprintln!("serialized as {:?}", data);
         let _decoded: RgbColor = varbincode::deserialize(data.as_slice()).unwrap();
     }
+
+    #[cfg(feature = "use_serde")]
+    #[test]
+    fn rgb_channels_serde() {
+        let red = RgbColor::new_8bpc(0xff, 0, 0x10);
+        let json = serde_json::to_string(&RgbChannels(red)).unwrap();
+        assert_eq!(json, r#"{"r":255,"g":0,"b":16}"#);
+
+        let decoded: RgbChannels = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, RgbChannels(red));
+
+        let decoded: RgbChannels = serde_json::from_str(r##""#ff0010""##).unwrap();
+        assert_eq!(RgbColor::from(decoded), red);
+        let decoded: RgbChannels = serde_json::from_str(r#""DarkGreen""#).unwrap();
+        assert_eq!(decoded.0, RgbColor::from_named("DarkGreen").unwrap());
+
+        assert!(serde_json::from_str::<RgbChannels>(r#"{"r":255,"g":0}"#).is_err());
+        assert!(serde_json::from_str::<RgbChannels>(r#""not a color""#).is_err());
+    }
+
+    #[test]
+    fn gradient() {
+        let black = RgbColor::new_8bpc(0, 0, 0);
+        let white = RgbColor::new_8bpc(0xff, 0xff, 0xff);
+
+        let stops = black.gradient(&white, 3);
+        assert_eq!(stops.len(), 3);
+        assert_eq!(stops[0], black);
+        assert_eq!(stops[2], white);
+        let (r, g, b) = stops[1].to_tuple_rgb8();
+        assert_eq!((r, g), (b, b));
+        // Linear 0.5 is approximately sRGB 0xbc
+        assert!((0xba..=0xbd).contains(&r), "mid gray is {}", r);
+
+        assert!(black.gradient(&white, 0).is_empty());
+        assert_eq!(black.gradient(&white, 1), vec![black]);
+        assert_eq!(black.gradient(&white, 2), vec![black, white]);
+    }
+
+    #[test]
+    fn inverse() {
+        let red = ColorAttribute::PaletteIndex(AnsiColor::Maroon as u8);
+        let blue = ColorAttribute::TrueColorWithDefaultFallback(RgbColor::new_8bpc(0, 0, 0xff));
+
+        assert_eq!(apply_inverse(red, blue, false), (red, blue));
+        assert_eq!(apply_inverse(red, blue, true), (blue, red));
+        assert_eq!(
+            apply_inverse(red, ColorAttribute::Default, true),
+            (ColorAttribute::Default, red)
+        );
+        assert_eq!(
+            apply_inverse(ColorAttribute::Default, ColorAttribute::Default, true),
+            (ColorAttribute::Default, ColorAttribute::Default)
+        );
+    }
+
+    #[test]
+    fn from_named_or_rgb_string_normalization() {
+        let red = Some(RgbColor::new_8bpc(0xff, 0, 0));
+        assert_eq!(RgbColor::from_named_or_rgb_string(" #ff0000 "), red);
+        assert_eq!(RgbColor::from_named_or_rgb_string("\t#FF0000\n"), red);
+        assert_eq!(RgbColor::from_named_or_rgb_string("RGB:ff/00/00"), red);
+        assert_eq!(
+            RgbColor::from_named_or_rgb_string(" Rgb:FFFF/0000/0000 "),
+            red
+        );
+        assert_eq!(
+            RgbColor::from_named_or_rgb_string("HSL:0 100 50").map(RgbColor::to_tuple_rgb8),
+            Some((0xff, 0, 0))
+        );
+        assert_eq!(RgbColor::from_named_or_rgb_string("  Red  "), red);
+        assert_eq!(RgbColor::from_named_or_rgb_string("RGB:"), None);
+        assert_eq!(RgbColor::from_named_or_rgb_string("   "), None);
+    }
+
+    #[test]
+    fn from_temperature() {
+        let (r, g, b) = RgbColor::from_temperature(6500.).to_tuple_rgb8();
+        assert!(
+            r >= 240 && g >= 240 && b >= 240,
+            "6500K is {} {} {}",
+            r,
+            g,
+            b
+        );
+
+        let (r, g, b) = RgbColor::from_temperature(2000.).to_tuple_rgb8();
+        assert_eq!(r, 255);
+        assert!(g > 100 && g < 160, "2000K is {} {} {}", r, g, b);
+        assert!(b < 40, "2000K is {} {} {}", r, g, b);
+
+        // Out of range values are clamped
+        assert_eq!(
+            RgbColor::from_temperature(100.),
+            RgbColor::from_temperature(1000.)
+        );
+        assert_eq!(
+            RgbColor::from_temperature(100_000.),
+            RgbColor::from_temperature(40000.)
+        );
+    }
+
+    #[test]
+    fn channels() {
+        let c = RgbColor::new_8bpc(0x12, 0x34, 0x56);
+        assert_eq!((c.red(), c.green(), c.blue()), c.to_tuple_rgb8());
+        assert_eq!((c.red10(), c.green10(), c.blue10()), c.to_tuple_rgb10());
+
+        let c = RgbColor::new_10bpc(1023, 512, 3);
+        assert_eq!((c.red(), c.green(), c.blue()), c.to_tuple_rgb8());
+        assert_eq!((c.red10(), c.green10(), c.blue10()), (1023, 512, 3));
+
+        let white = RgbColor::new_8bpc(0xff, 0xff, 0xff);
+        assert_eq!(white.red10(), 1023);
+    }
+
+    #[test]
+    fn cmyk() {
+        assert_eq!(RgbColor::new_8bpc(0, 0, 0).to_cmyk(), (0., 0., 0., 1.));
+        assert_eq!(
+            RgbColor::new_8bpc(0xff, 0xff, 0xff).to_cmyk(),
+            (0., 0., 0., 0.)
+        );
+        assert_eq!(RgbColor::new_8bpc(0xff, 0, 0).to_cmyk(), (0., 1., 1., 0.));
+        assert_eq!(
+            RgbColor::from_cmyk(0., 0., 0., 1.).to_tuple_rgb8(),
+            (0, 0, 0)
+        );
+        assert_eq!(
+            RgbColor::from_cmyk(0., 0., 0., 0.).to_tuple_rgb8(),
+            (0xff, 0xff, 0xff)
+        );
+
+        for color in &[
+            RgbColor::new_8bpc(0x12, 0x34, 0x56),
+            RgbColor::new_8bpc(0xff, 0x80, 0x00),
+            RgbColor::new_8bpc(0x7f, 0x7f, 0x7f),
+        ] {
+            let (c, m, y, k) = color.to_cmyk();
+            let (r1, g1, b1, _) = color.to_tuple_rgba();
+            let (r2, g2, b2, _) = RgbColor::from_cmyk(c, m, y, k).to_tuple_rgba();
+            for (a, b) in &[(r1, r2), (g1, g2), (b1, b2)] {
+                assert!((a - b).abs() < 0.002, "{:?}: {} vs {}", color, a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn scale_brightness() {
+        let color = RgbColor::new_8bpc(0xff, 0x80, 0x40);
+        let (r1, g1, b1, _) = color.to_linear_tuple_rgba();
+        let (r2, g2, b2, _) = color.scale_brightness(0.5).to_linear_tuple_rgba();
+        for (full, half) in &[(r1, r2), (g1, g2), (b1, b2)] {
+            assert!((full / 2. - half).abs() < 0.002, "{} vs {}", full, half);
+        }
+
+        assert_eq!(color.scale_brightness(0.).to_tuple_rgb8(), (0, 0, 0));
+        assert_eq!(
+            color.scale_brightness(1.).to_tuple_rgb8(),
+            color.to_tuple_rgb8()
+        );
+        // Scaling up is clamped
+        assert_eq!(color.scale_brightness(100.).to_tuple_rgb8().0, 0xff);
+    }
+
+    #[test]
+    fn lerp_oklab() {
+        let black = RgbColor::new_8bpc(0, 0, 0);
+        let white = RgbColor::new_8bpc(0xff, 0xff, 0xff);
+        let red = RgbColor::new_8bpc(0xff, 0, 0);
+        assert_eq!(black.lerp_oklab(&white, 0.), black);
+        assert_eq!(black.lerp_oklab(&white, 1.), white);
+        assert_eq!(red.lerp_oklab(&white, -1.), red);
+
+        // Converting to Oklab and back preserves the color
+        for color in &[red, white, RgbColor::new_8bpc(0x12, 0x80, 0xc0)] {
+            let (l, a, b) = color.to_oklab();
+            let (r1, g1, b1, _) = RgbColor::from_oklab(l, a, b).to_tuple_rgba();
+            let (r2, g2, b2, _) = color.to_tuple_rgba();
+            for (x, y) in &[(r1, r2), (g1, g2), (b1, b2)] {
+                assert!((x - y).abs() < 0.001, "{:?} vs {:?}", (r1, g1, b1), color);
+            }
+        }
+
+        // The Oklab midpoint of black and white is a neutral grey that
+        // is darker than the linear midpoint, which appears too light
+        let (r, g, b) = black.lerp_oklab(&white, 0.5).to_tuple_rgb8();
+        assert_eq!(r, g);
+        assert_eq!(g, b);
+        let (linear, _, _) = black.blend(&white, 0.5).to_tuple_rgb8();
+        assert!(r < linear, "{} vs {}", r, linear);
+        assert!((r as i32 - 0x63).abs() <= 1, "{}", r);
+    }
+
+    #[test]
+    fn blend_mode() {
+        let base = RgbColor::new_f32(0.2, 0.5, 0.8);
+        let top = RgbColor::new_f32(0.5, 0.4, 1.0);
+
+        fn assert_close(color: RgbColor, expected: (f32, f32, f32)) {
+            let (red, green, blue, _) = color.to_tuple_rgba();
+            for (actual, expected) in &[(red, expected.0), (green, expected.1), (blue, expected.2)]
+            {
+                assert!(
+                    (actual - expected).abs() < 0.002,
+                    "{:?} vs {:?}",
+                    (red, green, blue),
+                    expected
+                );
+            }
+        }
+
+        assert_eq!(base.blend_mode(&top, BlendMode::Normal), top);
+        assert_close(base.blend_mode(&top, BlendMode::Multiply), (0.1, 0.2, 0.8));
+        assert_close(base.blend_mode(&top, BlendMode::Screen), (0.6, 0.7, 1.0));
+        // 0.2 < 0.5 multiplies: 2 * 0.2 * 0.5
+        // 0.5 and 0.8 screen: 1 - 2 * 0.5 * 0.6 and 1 - 2 * 0.2 * 0.0
+        assert_close(base.blend_mode(&top, BlendMode::Overlay), (0.2, 0.4, 1.0));
+    }
 }
@@ -4,10 +4,13 @@ use crate::color::{AnsiColor, ColorSpec, RgbColor};
 use crate::input::{Modifiers, MouseButtons};
 use num_derive::*;
 use num_traits::{FromPrimitive, ToPrimitive};
+#[cfg(feature = "use_serde")]
+use serde::{Deserialize, Serialize};
 use std::fmt::{Display, Error as FmtError, Formatter};
 
 pub use vtparse::CsiParam;
 
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CSI {
     /// SGR: Set Graphics Rendition.
@@ -27,21 +30,38 @@ pub enum CSI {
 
     Window(Window),
 
+    /// ECMA-48 presentation controls
+    Presentation(Presentation),
+
+    /// xterm controls that save and restore state on a stack
+    StateStack(StateStack),
+
     /// Unknown or unspecified; should be rare and is rather
     /// large, so it is boxed and kept outside of the enum
     /// body to help reduce space usage in the common cases.
     Unspecified(Box<Unspecified>),
 }
 
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Unspecified {
     pub params: Vec<CsiParam>,
     /// if true, more than two intermediates arrived and the
-    /// remaining data was ignored
+    /// remaining data was ignored, so the encoded form of
+    /// this value will differ from the original sequence.
+    /// See `CSI::is_lossy`.
     pub parameters_truncated: bool,
     /// The final character in the CSI sequence; this typically
     /// defines how to interpret the other parameters.
     pub control: char,
+    /// Text that immediately followed the sequence, up to the next
+    /// control.  This is only populated by a `Parser` that has had
+    /// `set_capture_unknown_csi_payload` enabled, and is otherwise empty.
+    #[cfg_attr(
+        feature = "use_serde",
+        serde(default, skip_serializing_if = "String::is_empty")
+    )]
+    pub payload: String,
 }
 
 impl Display for Unspecified {
@@ -49,7 +69,7 @@ impl Display for Unspecified {
         for p in &self.params {
             write!(f, "{}", p)?;
         }
-        write!(f, "{}", self.control)
+        write!(f, "{}{}", self.control, self.payload)
     }
 }
 
@@ -69,11 +89,14 @@ impl Display for CSI {
             CSI::Mouse(mouse) => mouse.fmt(f)?,
             CSI::Device(dev) => dev.fmt(f)?,
             CSI::Window(window) => window.fmt(f)?,
+            CSI::Presentation(p) => p.fmt(f)?,
+            CSI::StateStack(s) => s.fmt(f)?,
         };
         Ok(())
     }
 }
 
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive)]
 pub enum CursorStyle {
     Default = 0,
@@ -91,6 +114,43 @@ impl Default for CursorStyle {
     }
 }
 
+impl CursorStyle {
+    /// Returns the shape selected by this style, without regard to
+    /// whether it blinks.
+    pub fn shape(self) -> CursorStyleShape {
+        match self {
+            Self::Default => CursorStyleShape::Default,
+            Self::BlinkingBlock | Self::SteadyBlock => CursorStyleShape::Block,
+            Self::BlinkingUnderline | Self::SteadyUnderline => CursorStyleShape::Underline,
+            Self::BlinkingBar | Self::SteadyBar => CursorStyleShape::Bar,
+        }
+    }
+
+    /// Returns true if this style selects a blinking cursor.
+    /// As with `surface::CursorShape::is_blinking`, this is false for
+    /// `Default`, whose blink state is up to the terminal.
+    pub fn blinking(self) -> bool {
+        matches!(
+            self,
+            Self::BlinkingBlock | Self::BlinkingUnderline | Self::BlinkingBar
+        )
+    }
+}
+
+/// The shape component of a `CursorStyle`.
+/// `Default` is the terminal's configured cursor; DEC terminals
+/// use a blinking block for it, but emulators usually let the user
+/// choose it.
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorStyleShape {
+    Default,
+    Block,
+    Underline,
+    Bar,
+}
+
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq, FromPrimitive, ToPrimitive)]
 pub enum DeviceAttributeCodes {
     Columns132 = 1,
@@ -107,12 +167,14 @@ pub enum DeviceAttributeCodes {
     AnsiTextLocator = 29,
 }
 
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum DeviceAttribute {
     Code(DeviceAttributeCodes),
     Unspecified(CsiParam),
 }
 
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DeviceAttributeFlags {
     pub attributes: Vec<DeviceAttribute>,
@@ -151,6 +213,7 @@ impl DeviceAttributeFlags {
     }
 }
 
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum DeviceAttributes {
     Vt100WithAdvancedVideoOption,
@@ -161,6 +224,51 @@ pub enum DeviceAttributes {
     Vt420(DeviceAttributeFlags),
 }
 
+/// A capability advertised in a primary device attributes (DA1) response
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DaCapability {
+    Vt100WithAdvancedVideoOption,
+    Vt101WithNoOptions,
+    Vt102,
+    /// Conformance level 62
+    Vt220,
+    /// Conformance level 63
+    Vt320,
+    /// Conformance level 64
+    Vt420,
+    Attribute(DeviceAttributeCodes),
+    Unknown(CsiParam),
+}
+
+impl DeviceAttributes {
+    /// Decodes the terminal class and the well-known attribute codes
+    /// from the response into a list of capabilities, so that a client
+    /// can test for a particular feature.
+    /// The terminal class is always the first element.
+    pub fn capabilities(&self) -> Vec<DaCapability> {
+        let (class, flags) = match self {
+            Self::Vt100WithAdvancedVideoOption => {
+                (DaCapability::Vt100WithAdvancedVideoOption, None)
+            }
+            Self::Vt101WithNoOptions => (DaCapability::Vt101WithNoOptions, None),
+            Self::Vt102 => (DaCapability::Vt102, None),
+            Self::Vt220(flags) => (DaCapability::Vt220, Some(flags)),
+            Self::Vt320(flags) => (DaCapability::Vt320, Some(flags)),
+            Self::Vt420(flags) => (DaCapability::Vt420, Some(flags)),
+        };
+        let mut caps = vec![class];
+        if let Some(flags) = flags {
+            caps.extend(flags.attributes.iter().map(|attr| match attr {
+                DeviceAttribute::Code(code) => DaCapability::Attribute(code.clone()),
+                DeviceAttribute::Unspecified(param) => DaCapability::Unknown(param.clone()),
+            }));
+        }
+        caps
+    }
+}
+
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum XtSmGraphicsItem {
     NumberOfColorRegisters,
@@ -180,6 +288,7 @@ impl Display for XtSmGraphicsItem {
     }
 }
 
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum XtSmGraphicsAction {
     ReadAttribute,
@@ -199,6 +308,7 @@ impl XtSmGraphicsAction {
     }
 }
 
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum XtSmGraphicsStatus {
     Success,
@@ -218,6 +328,7 @@ impl XtSmGraphicsStatus {
     }
 }
 
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct XtSmGraphics {
     pub item: XtSmGraphicsItem,
@@ -246,20 +357,16 @@ impl XtSmGraphics {
         }
     }
 
-    pub fn parse(params: &[CsiParam]) -> Result<CSI, ()> {
+    pub fn parse(params: &[CsiParam]) -> Result<CSI, CsiParseError> {
         let params = Cracked::parse(&params[1..])?;
         Ok(CSI::Device(Box::new(Device::XtSmGraphics(XtSmGraphics {
-            item: match params.get(0).ok_or(())? {
-                CsiParam::Integer(1) => XtSmGraphicsItem::NumberOfColorRegisters,
-                CsiParam::Integer(2) => XtSmGraphicsItem::SixelGraphicsGeometry,
-                CsiParam::Integer(3) => XtSmGraphicsItem::RegisGraphicsGeometry,
-                CsiParam::Integer(n) => XtSmGraphicsItem::Unspecified(*n),
-                _ => return Err(()),
-            },
-            action_or_status: match params.get(1).ok_or(())? {
-                CsiParam::Integer(n) => *n,
-                _ => return Err(()),
+            item: match params.int(0)? {
+                1 => XtSmGraphicsItem::NumberOfColorRegisters,
+                2 => XtSmGraphicsItem::SixelGraphicsGeometry,
+                3 => XtSmGraphicsItem::RegisGraphicsGeometry,
+                n => XtSmGraphicsItem::Unspecified(n),
             },
+            action_or_status: params.int(1)?,
             value: params.params[2..]
                 .iter()
                 .filter_map(|p| match p {
@@ -271,6 +378,7 @@ impl XtSmGraphics {
     }
 }
 
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Device {
     DeviceAttributes(DeviceAttributes),
@@ -280,11 +388,43 @@ pub enum Device {
     RequestSecondaryDeviceAttributes,
     RequestTertiaryDeviceAttributes,
     StatusReport,
+    /// DSR - https://vt100.net/docs/vt510-rm/DSR-PP.html
+    /// Requests the status of the printer (`CSI ? 15 n`)
+    RequestPrinterStatus,
+    /// DSR - https://vt100.net/docs/vt510-rm/DSR-UDK.html
+    /// Requests whether the user defined keys are locked (`CSI ? 25 n`)
+    RequestUserDefinedKeysStatus,
     /// https://github.com/mintty/mintty/issues/881
     /// https://gitlab.gnome.org/GNOME/vte/-/issues/235
     RequestTerminalNameAndVersion,
     RequestTerminalParameters(i64),
     XtSmGraphics(XtSmGraphics),
+    /// DECLL - https://vt100.net/docs/vt510-rm/DECLL.html
+    /// Each value is either 0 to turn off all of the LEDs,
+    /// 1-4 to turn on the corresponding LED, or 21-24 to
+    /// turn off the corresponding LED.
+    SetKeyboardLeds(Vec<u8>),
+    /// DECSSDT - https://vt100.net/docs/vt510-rm/DECSSDT.html
+    SelectStatusDisplayType(StatusDisplayType),
+    /// DECSASD - https://vt100.net/docs/vt510-rm/DECSASD.html
+    SelectActiveStatusDisplay(ActiveStatusDisplay),
+}
+
+/// The kind of status line selected by DECSSDT
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+pub enum StatusDisplayType {
+    None = 0,
+    Indicator = 1,
+    HostWritable = 2,
+}
+
+/// The display that receives output, as selected by DECSASD
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+pub enum ActiveStatusDisplay {
+    MainDisplay = 0,
+    StatusLine = 1,
 }
 
 impl Display for Device {
@@ -305,6 +445,8 @@ impl Display for Device {
             Device::RequestTerminalNameAndVersion => write!(f, ">q")?,
             Device::RequestTerminalParameters(n) => write!(f, "{};1;1;128;128;1;0x", n + 2)?,
             Device::StatusReport => write!(f, "5n")?,
+            Device::RequestPrinterStatus => write!(f, "?15n")?,
+            Device::RequestUserDefinedKeysStatus => write!(f, "?25n")?,
             Device::XtSmGraphics(g) => {
                 write!(f, "?{};{}", g.item, g.action_or_status)?;
                 for v in &g.value {
@@ -312,11 +454,190 @@ impl Display for Device {
                 }
                 write!(f, "S")?;
             }
+            Device::SetKeyboardLeds(leds) => {
+                for (idx, led) in leds.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ";")?;
+                    }
+                    write!(f, "{}", led)?;
+                }
+                write!(f, "q")?;
+            }
+            Device::SelectStatusDisplayType(t) => {
+                write!(f, "{}$~", t.to_u8().ok_or_else(|| FmtError)?)?
+            }
+            Device::SelectActiveStatusDisplay(d) => {
+                write!(f, "{}$}}", d.to_u8().ok_or_else(|| FmtError)?)?
+            }
         };
         Ok(())
     }
 }
 
+/// xterm controls that save and restore state on a stack.
+/// These use the `#` intermediate.
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateStack {
+    /// XTPUSHSGR: save the SGR state onto a stack.
+    /// If any aspects are specified then only those aspects
+    /// of the state are saved; otherwise, all of them are.
+    PushSgr(Vec<SgrAspect>),
+
+    /// XTPOPSGR: restore the SGR state most recently saved by `PushSgr`.
+    PopSgr,
+
+    /// XTPUSHSGR, sent using the `CSI # p` form that xterm also accepts.
+    /// It has the same effect as `PushSgr`, but is kept distinct so
+    /// that it is encoded with the same final byte.
+    PushSgrAlias(Vec<SgrAspect>),
+
+    /// XTPOPSGR, sent using the `CSI # q` form; see `PushSgrAlias`.
+    PopSgrAlias,
+
+    /// XTPUSHCOLORS: save the dynamic and ANSI palette colors onto a stack.
+    /// Each parameter, in the range 1-10, stores the palette into that
+    /// slot of the stack without pushing; no parameters pushes.
+    PushColors(Vec<u8>),
+
+    /// XTPOPCOLORS: restore the dynamic and ANSI palette colors from a
+    /// stack.  Each parameter, in the range 1-10, restores the palette
+    /// from that slot of the stack without popping; no parameters pops.
+    PopColors(Vec<u8>),
+
+    /// XTREPORTCOLORS: report the current entry on the palette stack
+    /// and the number of palettes stored on it.
+    ReportColors,
+}
+
+impl Display for StateStack {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        /// Writes the `;` separated parameters of a push or pop
+        fn write_params<T: Display>(
+            f: &mut Formatter,
+            params: impl Iterator<Item = T>,
+        ) -> Result<(), FmtError> {
+            for (idx, param) in params.enumerate() {
+                if idx > 0 {
+                    write!(f, ";")?;
+                }
+                write!(f, "{}", param)?;
+            }
+            Ok(())
+        }
+
+        match self {
+            Self::PushSgr(aspects) => {
+                write_params(f, aspects.iter().map(|aspect| *aspect as u8))?;
+                write!(f, "#{{")
+            }
+            Self::PopSgr => write!(f, "#}}"),
+            Self::PushSgrAlias(aspects) => {
+                write_params(f, aspects.iter().map(|aspect| *aspect as u8))?;
+                write!(f, "#p")
+            }
+            Self::PopSgrAlias => write!(f, "#q"),
+            Self::PushColors(slots) => {
+                write_params(f, slots.iter())?;
+                write!(f, "#P")
+            }
+            Self::PopColors(slots) => {
+                write_params(f, slots.iter())?;
+                write!(f, "#Q")
+            }
+            Self::ReportColors => write!(f, "#R"),
+        }
+    }
+}
+
+/// Identifies an aspect of the SGR state that can be saved by
+/// XTPUSHSGR.
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+pub enum SgrAspect {
+    Bold = 1,
+    Faint = 2,
+    Italic = 3,
+    Underline = 4,
+    Blink = 5,
+    Inverse = 7,
+    Invisible = 8,
+    StrikeThrough = 9,
+    DoubleUnderline = 21,
+    Foreground = 30,
+    Background = 31,
+}
+
+/// ECMA-48 controls that affect the presentation of graphic
+/// characters.  These use the SP intermediate.
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Presentation {
+    /// GSM - GRAPHIC SIZE MODIFICATION
+    /// Modifies the height and/or width of subsequent graphic characters,
+    /// expressed as a percentage of the size established by
+    /// GRAPHIC SIZE SELECTION (GSS).  The default value of both is 100.
+    GraphicSizeModification { height: u32, width: u32 },
+    /// PPA - PAGE POSITION ABSOLUTE
+    /// Moves the active data position to the corresponding position
+    /// on the specified page.  The default value is 1.
+    PagePositionAbsolute(u32),
+    /// PPR - PAGE POSITION FORWARD
+    /// Moves the active data position to the corresponding position
+    /// on the page that is the specified number of pages after the
+    /// active page.  The default value is 1.
+    PagePositionForward(u32),
+    /// PPB - PAGE POSITION BACKWARD
+    /// Moves the active data position to the corresponding position
+    /// on the page that is the specified number of pages before the
+    /// active page.  The default value is 1.
+    PagePositionBackward(u32),
+    /// JFY - JUSTIFY
+    /// Indicates the beginning of a string of graphic characters that
+    /// are to be laid out according to the listed justification
+    /// modes, such as 2 for word fill or 6 for centering.
+    /// An empty parameter list is equivalent to a single 0, which
+    /// means no justification.
+    Justify(Vec<u8>),
+    /// SPI - SPACING INCREMENT
+    /// Establishes the line spacing and the character spacing for
+    /// subsequent text, in the units established by SIZE UNIT
+    /// SELECTION (SSU).  Both parameters are required.
+    SpacingIncrement { line: u32, character: u32 },
+    /// STAB - SELECTIVE TABULATION
+    /// Aligns the following text with the tabulation stop identified
+    /// by the parameter, as established by TATE, TALE, TAC or TCC.
+    /// The parameter has no default value.
+    SelectiveTabulation(u32),
+}
+
+impl Display for Presentation {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        match self {
+            Self::GraphicSizeModification { height, width } => {
+                write!(f, "{};{} B", height, width)
+            }
+            Self::PagePositionAbsolute(n) => write!(f, "{} P", n),
+            Self::PagePositionForward(n) => write!(f, "{} Q", n),
+            Self::PagePositionBackward(n) => write!(f, "{} R", n),
+            Self::Justify(modes) => {
+                for (idx, mode) in modes.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ";")?;
+                    }
+                    write!(f, "{}", mode)?;
+                }
+                write!(f, " F")
+            }
+            Self::SpacingIncrement { line, character } => {
+                write!(f, "{};{} G", line, character)
+            }
+            Self::SelectiveTabulation(n) => write!(f, "{} ^", n),
+        }
+    }
+}
+
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum MouseButton {
     Button1Press,
@@ -348,6 +669,7 @@ impl From<MouseButton> for MouseButtons {
     }
 }
 
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Window {
     DeIconify,
@@ -404,6 +726,22 @@ pub enum Window {
         bottom: OneBased,
         right: OneBased,
     },
+    /// DECCRA - https://vt100.net/docs/vt510-rm/DECCRA.html
+    /// Copies the rectangle bounded by `top`, `left`, `bottom` and
+    /// `right` on page `page` so that its top left corner is at
+    /// `dest_top`, `dest_left` on page `dest_page`.
+    /// A `bottom` or `right` of None means the last line or column
+    /// of the page.
+    CopyRectangularArea {
+        top: OneBased,
+        left: OneBased,
+        bottom: Option<OneBased>,
+        right: Option<OneBased>,
+        page: i64,
+        dest_top: OneBased,
+        dest_left: OneBased,
+        dest_page: i64,
+    },
 }
 
 fn numstr_or_empty(x: &Option<i64>) -> String {
@@ -476,10 +814,32 @@ impl Display for Window {
                 "{};{};{};{};{};{}*y",
                 request_id, page_number, top, left, bottom, right,
             ),
+            Window::CopyRectangularArea {
+                top,
+                left,
+                bottom,
+                right,
+                page,
+                dest_top,
+                dest_left,
+                dest_page,
+            } => write!(
+                f,
+                "{};{};{};{};{};{};{};{}$v",
+                top,
+                left,
+                bottom.map(|b| b.to_string()).unwrap_or_default(),
+                right.map(|r| r.to_string()).unwrap_or_default(),
+                page,
+                dest_top,
+                dest_left,
+                dest_page,
+            ),
         }
     }
 }
 
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum MouseReport {
     SGR1006 {
@@ -585,6 +945,7 @@ impl Display for MouseReport {
     }
 }
 
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum XtermKeyModifierResource {
     Keyboard,
@@ -605,6 +966,7 @@ impl XtermKeyModifierResource {
     }
 }
 
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Mode {
     SetDecPrivateMode(DecPrivateMode),
@@ -619,6 +981,15 @@ pub enum Mode {
         resource: XtermKeyModifierResource,
         value: Option<i64>,
     },
+    /// DECAC - ASSIGN COLOR
+    /// <https://vt100.net/docs/vt510-rm/DECAC.html>
+    /// Assigns the `fg` and `bg` color indices to `item`,
+    /// where 1 is normal text and 2 is the window frame.
+    AssignColor {
+        item: u16,
+        fg: u16,
+        bg: u16,
+    },
 }
 
 impl Display for Mode {
@@ -674,16 +1045,19 @@ impl Display for Mode {
                 }
                 write!(f, "m")
             }
+            Mode::AssignColor { item, fg, bg } => write!(f, "{};{};{},|", item, fg, bg),
         }
     }
 }
 
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum DecPrivateMode {
     Code(DecPrivateModeCode),
     Unspecified(u16),
 }
 
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq, FromPrimitive, ToPrimitive)]
 pub enum DecPrivateModeCode {
     /// https://vt100.net/docs/vt510-rm/DECCKM.html
@@ -759,12 +1133,14 @@ pub enum DecPrivateModeCode {
     SixelScrollsRight = 8452,
 }
 
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TerminalMode {
     Code(TerminalModeCode),
     Unspecified(u16),
 }
 
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq, FromPrimitive, ToPrimitive)]
 pub enum TerminalModeCode {
     /// https://vt100.net/docs/vt510-rm/KAM.html
@@ -780,6 +1156,7 @@ pub enum TerminalModeCode {
     ShowCursor = 25,
 }
 
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Cursor {
     /// CBT Moves cursor to the Ps tabs backward. The default value of Ps is 1.
@@ -808,6 +1185,9 @@ pub enum Cursor {
     /// HVP - CHARACTER AND LINE POSITION
     /// HVP Moves cursor to the Ps1-th line and to the Ps2-th column. The
     /// default value of Ps1 and Ps2 is 1.
+    /// This has the same effect as `Position` (CUP), but is kept as a
+    /// distinct variant so that it encodes with the `f` final byte that
+    /// it was parsed from.
     CharacterAndLinePosition {
         line: OneBased,
         col: OneBased,
@@ -863,8 +1243,24 @@ pub enum Cursor {
     /// The terminal will respond with ActivePositionReport.
     RequestActivePositionReport,
 
+    /// DECXCPR: https://vt100.net/docs/vt510-rm/DECXCPR.html
+    /// Like `RequestActivePositionReport`, but the terminal responds
+    /// with `ExtendedActivePositionReport`, which includes the page number.
+    RequestExtendedActivePositionReport,
+
+    /// DECXCPR: the response to `RequestExtendedActivePositionReport`,
+    /// encoded as `CSI ? Pl ; Pc ; Pp R`.
+    ExtendedActivePositionReport {
+        line: OneBased,
+        col: OneBased,
+        page: OneBased,
+    },
+
     /// SCP - Save Cursor Position.
-    /// Only works when DECLRMM is disabled
+    /// Only works when DECLRMM is disabled.
+    /// `CSI s` is ambiguous with `SetLeftAndRightMargins`; the parser
+    /// always produces this variant for it, so callers must track the
+    /// DECLRMM mode to know which meaning applies.
     SaveCursor,
     RestoreCursor,
 
@@ -904,12 +1300,22 @@ pub enum Cursor {
     LineTabulation(u32),
 
     /// DECSTBM - Set top and bottom margins.
+    /// The margins are reported as they appeared in the sequence;
+    /// the parser doesn't apply origin mode, nor does it clamp the
+    /// margins to the size of the screen.  That is the responsibility
+    /// of the consumer.
+    /// `bottom` is None when it was omitted or specified as 0, which
+    /// means that the bottom margin is the last line of the screen.
     SetTopAndBottomMargins {
         top: OneBased,
-        bottom: OneBased,
+        bottom: Option<OneBased>,
     },
 
     /// https://vt100.net/docs/vt510-rm/DECSLRM.html
+    /// Only takes effect when DECLRMM is enabled.
+    /// Without DECLRMM, xterm treats `CSI Pl ; Pr s` as SCP and ignores
+    /// the parameters, so callers must track the DECLRMM mode to know
+    /// which meaning applies.
     SetLeftAndRightMargins {
         left: OneBased,
         right: OneBased,
@@ -918,6 +1324,7 @@ pub enum Cursor {
     CursorStyle(CursorStyle),
 }
 
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Edit {
     /// DCH - DELETE CHARACTER
@@ -1020,10 +1427,19 @@ pub enum Edit {
     /// ED - ERASE IN PAGE (XTerm calls this Erase in Display)
     EraseInDisplay(EraseInDisplay),
 
-    /// REP - Repeat the preceding character n times
+    /// REP - Repeat the preceding character n times.
+    /// The character that is repeated is the last graphic character
+    /// that was output, so if that was a wide character then each
+    /// repetition occupies two cells.
+    /// The count is clamped to `MAX_REPEAT_COUNT` when parsing.
     Repeat(u32),
 }
 
+/// The upper bound for the count parsed from a REP sequence.
+/// Without this, a sequence like `CSI 4294967295 b` could cause
+/// a consumer to try to produce an enormous amount of output.
+pub const MAX_REPEAT_COUNT: u32 = 65535;
+
 trait EncodeCSIParam {
     fn write_csi(&self, f: &mut Formatter, control: &str) -> Result<(), FmtError>;
 }
@@ -1077,6 +1493,69 @@ impl Display for Edit {
     }
 }
 
+impl Cursor {
+    /// Constructs a CUP (`Cursor::Position`) for the 1-based
+    /// `line` and `col`, returning an error if either is zero or
+    /// out of range.
+    pub fn try_position(line: i64, col: i64) -> Result<Self, CsiBuildError> {
+        Ok(Cursor::Position {
+            line: checked_one_based("line", line)?,
+            col: checked_one_based("col", col)?,
+        })
+    }
+
+    /// Constructs a CHA (`Cursor::CharacterAbsolute`) for the 1-based
+    /// `col`, returning an error if it is zero or out of range.
+    pub fn try_character_absolute(col: i64) -> Result<Self, CsiBuildError> {
+        Ok(Cursor::CharacterAbsolute(checked_one_based("col", col)?))
+    }
+
+    /// Constructs a DECSTBM (`Cursor::SetTopAndBottomMargins`) for the
+    /// 1-based `top` and `bottom` lines, returning an error if either is
+    /// zero or out of range, or if `top` is not above `bottom`.
+    pub fn try_set_top_and_bottom_margins(top: i64, bottom: i64) -> Result<Self, CsiBuildError> {
+        let top = checked_one_based("top", top)?;
+        let bottom = checked_one_based("bottom", bottom)?;
+        if top.as_one_based() >= bottom.as_one_based() {
+            return Err(CsiBuildError::InvalidMargins {
+                top: top.as_one_based(),
+                bottom: bottom.as_one_based(),
+            });
+        }
+        Ok(Cursor::SetTopAndBottomMargins {
+            top,
+            bottom: Some(bottom),
+        })
+    }
+
+    /// For the CNL (`NextLine`) and CPL (`PrecedingLine`) motions, computes
+    /// the absolute one-based `(line, col)` that the cursor moves to when
+    /// it is currently on the one-based `current_line`.
+    /// Both motions reset the column to 1.  A count of 0 is treated as 1,
+    /// and moving up is clamped to line 1.  The result is not constrained
+    /// to the scrolling margins or the screen height; that is left to the
+    /// caller, which knows the dimensions of the screen.
+    /// Returns None for all other cursor motions.
+    pub fn resolve(&self, current_line: u32) -> Option<(u32, u32)> {
+        match self {
+            Cursor::NextLine(n) => Some((current_line.saturating_add((*n).max(1)), 1)),
+            Cursor::PrecedingLine(n) => Some((current_line.saturating_sub((*n).max(1)).max(1), 1)),
+            _ => None,
+        }
+    }
+
+    /// Returns true for SCOSC (`CSI s`, `Cursor::SaveCursor`) and
+    /// SCORC (`CSI u`, `Cursor::RestoreCursor`).
+    /// These are the CSI forms of DECSC and DECRC (`ESC 7` and `ESC 8`,
+    /// see `EscCode::DecSaveCursorPosition`), but they save only the cursor
+    /// position, whereas the ESC forms also save the rendition and
+    /// character set state.  Consumers that handle both should not
+    /// assume that a `CSI u` restores state saved by `ESC 7`.
+    pub fn is_save_restore(&self) -> bool {
+        matches!(self, Cursor::SaveCursor | Cursor::RestoreCursor)
+    }
+}
+
 impl Display for Cursor {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
         match self {
@@ -1101,21 +1580,24 @@ impl Display for Cursor {
             Cursor::LinePositionAbsolute(n) => n.write_csi(f, "d")?,
             Cursor::LinePositionBackward(n) => n.write_csi(f, "k")?,
             Cursor::LinePositionForward(n) => n.write_csi(f, "e")?,
-            Cursor::SetTopAndBottomMargins { top, bottom } => {
-                if top.as_one_based() == 1 && bottom.as_one_based() == u32::max_value() {
-                    write!(f, "r")?;
-                } else {
-                    write!(f, "{};{}r", top, bottom)?;
-                }
-            }
+            Cursor::SetTopAndBottomMargins { top, bottom } => match bottom {
+                None if top.as_one_based() == 1 => write!(f, "r")?,
+                None => write!(f, "{}r", top)?,
+                Some(bottom) => write!(f, "{};{}r", top, bottom)?,
+            },
             Cursor::SetLeftAndRightMargins { left, right } => {
-                if left.as_one_based() == 1 && right.as_one_based() == u32::max_value() {
-                    write!(f, "s")?;
+                // Always emit the left margin; a bare `CSI s` is SCP
+                if right.as_one_based() == u32::max_value() {
+                    write!(f, "{}s", left)?;
                 } else {
                     write!(f, "{};{}s", left, right)?;
                 }
             }
             Cursor::RequestActivePositionReport => write!(f, "6n")?,
+            Cursor::RequestExtendedActivePositionReport => write!(f, "?6n")?,
+            Cursor::ExtendedActivePositionReport { line, col, page } => {
+                write!(f, "?{};{};{}R", line, col, page)?
+            }
             Cursor::SaveCursor => write!(f, "s")?,
             Cursor::RestoreCursor => write!(f, "u")?,
             Cursor::CursorStyle(style) => write!(f, "{} q", *style as u8)?,
@@ -1129,27 +1611,27 @@ impl Display for Cursor {
 /// but in some we build out an enum.  The trait helps to generalize
 /// the parser code while keeping it relatively terse.
 trait ParseParams: Sized {
-    fn parse_params(params: &[CsiParam]) -> Result<Self, ()>;
+    fn parse_params(params: &[CsiParam]) -> Result<Self, CsiParseError>;
 }
 
 /// Parse an input parameter into a 1-based unsigned value
 impl ParseParams for u32 {
-    fn parse_params(params: &[CsiParam]) -> Result<u32, ()> {
+    fn parse_params(params: &[CsiParam]) -> Result<u32, CsiParseError> {
         match params {
             [] => Ok(1),
             [p] => to_1b_u32(p),
-            _ => Err(()),
+            _ => Err(wrong_parameter_count(params)),
         }
     }
 }
 
 /// Parse an input parameter into a 1-based unsigned value
 impl ParseParams for OneBased {
-    fn parse_params(params: &[CsiParam]) -> Result<OneBased, ()> {
+    fn parse_params(params: &[CsiParam]) -> Result<OneBased, CsiParseError> {
         match params {
             [] => Ok(OneBased::new(1)),
-            [p] => OneBased::from_esc_param(p),
-            _ => Err(()),
+            [p] => one_based(p),
+            _ => Err(wrong_parameter_count(params)),
         }
     }
 }
@@ -1158,15 +1640,13 @@ impl ParseParams for OneBased {
 /// This is typically used to build a struct comprised of
 /// the pair of values.
 impl ParseParams for (OneBased, OneBased) {
-    fn parse_params(params: &[CsiParam]) -> Result<(OneBased, OneBased), ()> {
+    fn parse_params(params: &[CsiParam]) -> Result<(OneBased, OneBased), CsiParseError> {
         match params {
             [] => Ok((OneBased::new(1), OneBased::new(1))),
-            [p] => Ok((OneBased::from_esc_param(p)?, OneBased::new(1))),
-            [a, CsiParam::P(b';'), b] => {
-                Ok((OneBased::from_esc_param(a)?, OneBased::from_esc_param(b)?))
-            }
-            [CsiParam::P(b';'), b] => Ok((OneBased::new(1), OneBased::from_esc_param(b)?)),
-            _ => Err(()),
+            [p] => Ok((one_based(p)?, OneBased::new(1))),
+            [a, CsiParam::P(b';'), b] => Ok((one_based(a)?, one_based(b)?)),
+            [CsiParam::P(b';'), b] => Ok((OneBased::new(1), one_based(b)?)),
+            _ => Err(wrong_parameter_count(params)),
         }
     }
 }
@@ -1181,15 +1661,17 @@ trait ParamEnum: FromPrimitive {
 
 /// implement ParseParams for the enums that also implement ParamEnum.
 impl<T: ParamEnum> ParseParams for T {
-    fn parse_params(params: &[CsiParam]) -> Result<Self, ()> {
+    fn parse_params(params: &[CsiParam]) -> Result<Self, CsiParseError> {
         match params {
             [] => Ok(ParamEnum::default()),
-            [CsiParam::Integer(i)] => FromPrimitive::from_i64(*i).ok_or(()),
-            _ => Err(()),
+            [CsiParam::Integer(i)] => enum_param(*i),
+            [p] => Err(CsiParseError::UnexpectedParameter(p.clone())),
+            _ => Err(wrong_parameter_count(params)),
         }
     }
 }
 
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq, FromPrimitive, Copy, ToPrimitive)]
 pub enum CursorTabulationControl {
     SetCharacterTabStopAtActivePosition = 0,
@@ -1207,6 +1689,11 @@ impl ParamEnum for CursorTabulationControl {
     }
 }
 
+/// The parameter of TBC, which selects the tab stops to clear.
+/// It complements `CursorTabulationControl`, which can also set them.
+/// Most terminals, including xterm, only implement
+/// `ClearCharacterTabStopAtActivePosition` and `ClearAllCharacterTabStops`.
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq, FromPrimitive, Copy, ToPrimitive)]
 pub enum TabulationClear {
     ClearCharacterTabStopAtActivePosition = 0,
@@ -1223,6 +1710,7 @@ impl ParamEnum for TabulationClear {
     }
 }
 
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq, FromPrimitive, Copy, ToPrimitive)]
 pub enum EraseInLine {
     EraseToEndOfLine = 0,
@@ -1236,6 +1724,7 @@ impl ParamEnum for EraseInLine {
     }
 }
 
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq, FromPrimitive, Copy, ToPrimitive)]
 pub enum EraseInDisplay {
     /// the active presentation position and the character positions up to the
@@ -1257,6 +1746,7 @@ impl ParamEnum for EraseInDisplay {
     }
 }
 
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Sgr {
     /// Resets rendition to defaults.  Typically switches off
@@ -1267,7 +1757,14 @@ pub enum Sgr {
     Underline(Underline),
     UnderlineColor(ColorSpec),
     Blink(Blink),
+    /// Note that SGR 23 turns off both italic and Fraktur, so parsing
+    /// it yields `Italic(false)`, which should be understood to also
+    /// imply `Fraktur(false)`, and vice versa.
     Italic(bool),
+    /// Fraktur (blackletter) font; SGR 20 turns it on.
+    /// There is no dedicated code to turn it off: `Fraktur(false)`
+    /// is encoded as SGR 23, which also turns off italic.
+    Fraktur(bool),
     Inverse(bool),
     Invisible(bool),
     StrikeThrough(bool),
@@ -1275,10 +1772,65 @@ pub enum Sgr {
     Foreground(ColorSpec),
     Background(ColorSpec),
     Overline(bool),
+    /// An SGR code that isn't recognized.  It is preserved as a
+    /// placeholder so that the codes that follow it in the same
+    /// sequence can still be interpreted.
+    Unknown(i64),
+    /// An underline style subparameter, `4:N`, that isn't recognized,
+    /// such as a style introduced by a newer terminal.  It is preserved
+    /// so that it can be re-encoded without loss.
+    /// This isn't an `Underline` variant because `Underline` is packed
+    /// into a few bits of `CellAttributes`; for the purposes of
+    /// `SgrState` it is treated as another underline style.
+    UnknownUnderline(u16),
 }
 
-impl Display for Sgr {
+/// Options that influence how `Sgr` values are encoded.
+/// The defaults produce the most widely compatible output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SgrEncodeOptions {
+    /// `CSI 0 m` and `CSI m` are equivalent; when this is set,
+    /// `Sgr::Reset` is encoded using the shorter bare form.
+    pub omit_default_reset_param: bool,
+    /// When set, the bright foreground colors (palette indices 8-15)
+    /// are encoded using the legacy convention of bold plus the
+    /// corresponding `30`-`37` code, for example `1;31m` rather than
+    /// `91m`, for compatibility with 8 color terminals that don't
+    /// support the `90`-`97` codes.
+    /// Note that this turns on bold as a side effect.  Bright
+    /// backgrounds have no equivalent and are not affected.
+    pub bright_foreground_as_bold: bool,
+    /// When set, the bright foreground and background colors (palette
+    /// indices 8-15) are encoded using the 256 color form, for example
+    /// `38;5;9m` rather than `91m`, for terminals that understand the
+    /// 256 color palette but not the `90`-`97` and `100`-`107` codes.
+    /// The semicolon separated form is used as it is more widely
+    /// understood by older terminals than the colon separated form.
+    /// `bright_foreground_as_bold` takes precedence for foreground colors.
+    pub bright_colors_as_256: bool,
+}
+
+/// Helper returned by `Sgr::with_options` that encodes the `Sgr`
+/// according to the supplied `SgrEncodeOptions`.
+pub struct SgrWithOptions<'a> {
+    sgr: &'a Sgr,
+    options: SgrEncodeOptions,
+}
+
+impl<'a> Display for SgrWithOptions<'a> {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        self.sgr.encode(f, &self.options)
+    }
+}
+
+impl Sgr {
+    /// Returns a value whose `Display` impl encodes self using
+    /// the provided options.
+    pub fn with_options(&self, options: SgrEncodeOptions) -> SgrWithOptions<'_> {
+        SgrWithOptions { sgr: self, options }
+    }
+
+    fn encode(&self, f: &mut Formatter, options: &SgrEncodeOptions) -> Result<(), FmtError> {
         macro_rules! code {
             ($t:ident) => {
                 write!(f, "{}m", SgrCode::$t as i64)?
@@ -1297,7 +1849,22 @@ impl Display for Sgr {
             }
         }
 
+        macro_rules! true_color {
+            ($code:ident, $color:expr) => {{
+                let (red, green, blue) = $color.to_tuple_rgb8();
+                write!(
+                    f,
+                    "{}:2::{}:{}:{}m",
+                    SgrCode::$code as i64,
+                    red,
+                    green,
+                    blue
+                )?
+            }};
+        }
+
         match self {
+            Sgr::Reset if options.omit_default_reset_param => write!(f, "m")?,
             Sgr::Reset => code!(Reset),
             Sgr::Intensity(Intensity::Bold) => code!(IntensityBold),
             Sgr::Intensity(Intensity::Half) => code!(IntensityDim),
@@ -1313,6 +1880,8 @@ impl Display for Sgr {
             Sgr::Blink(Blink::None) => code!(BlinkOff),
             Sgr::Italic(true) => code!(ItalicOn),
             Sgr::Italic(false) => code!(ItalicOff),
+            Sgr::Fraktur(true) => code!(Fraktur),
+            Sgr::Fraktur(false) => code!(ItalicOff),
             Sgr::Inverse(true) => code!(InverseOn),
             Sgr::Inverse(false) => code!(InverseOff),
             Sgr::Invisible(true) => code!(InvisibleOn),
@@ -1334,6 +1903,21 @@ impl Display for Sgr {
             Sgr::Font(_) => { /* there are no other possible font values */ }
             Sgr::Foreground(ColorSpec::Default) => code!(ForegroundDefault),
             Sgr::Background(ColorSpec::Default) => code!(BackgroundDefault),
+            Sgr::Foreground(ColorSpec::PaletteIndex(idx))
+                if options.bright_foreground_as_bold && (8..16).contains(idx) =>
+            {
+                write!(
+                    f,
+                    "{};{}m",
+                    SgrCode::IntensityBold as i64,
+                    SgrCode::ForegroundBlack as i64 + i64::from(*idx - 8)
+                )?
+            }
+            Sgr::Foreground(ColorSpec::PaletteIndex(idx))
+                if options.bright_colors_as_256 && (8..16).contains(idx) =>
+            {
+                write!(f, "{};5;{}m", SgrCode::ForegroundColor as i64, idx)?
+            }
             Sgr::Foreground(ColorSpec::PaletteIndex(idx)) => ansi_color!(
                 *idx,
                 ForegroundColor,
@@ -1345,9 +1929,9 @@ impl Display for Sgr {
                 (Purple, ForegroundMagenta),
                 (Teal, ForegroundCyan),
                 (Silver, ForegroundWhite),
-                // Note: these brights are emitted using codes in the 100 range.
-                // I don't know how portable this is vs. the 256 color sequences,
-                // so we may need to make an adjustment here later.
+                // Note: these brights are emitted using codes in the 90 and
+                // 100 ranges; see SgrEncodeOptions::bright_colors_as_256 for
+                // terminals that don't understand them.
                 (Grey, ForegroundBrightBlack),
                 (Red, ForegroundBrightRed),
                 (Lime, ForegroundBrightGreen),
@@ -1357,16 +1941,11 @@ impl Display for Sgr {
                 (Aqua, ForegroundBrightCyan),
                 (White, ForegroundBrightWhite)
             ),
-            Sgr::Foreground(ColorSpec::TrueColor(c)) => {
-                let (red, green, blue) = c.to_tuple_rgb8();
-                write!(
-                    f,
-                    "{}:2::{}:{}:{}m",
-                    SgrCode::ForegroundColor as i64,
-                    red,
-                    green,
-                    blue
-                )?
+            Sgr::Foreground(ColorSpec::TrueColor(c)) => true_color!(ForegroundColor, c),
+            Sgr::Background(ColorSpec::PaletteIndex(idx))
+                if options.bright_colors_as_256 && (8..16).contains(idx) =>
+            {
+                write!(f, "{};5;{}m", SgrCode::BackgroundColor as i64, idx)?
             }
             Sgr::Background(ColorSpec::PaletteIndex(idx)) => ansi_color!(
                 *idx,
@@ -1379,9 +1958,9 @@ impl Display for Sgr {
                 (Purple, BackgroundMagenta),
                 (Teal, BackgroundCyan),
                 (Silver, BackgroundWhite),
-                // Note: these brights are emitted using codes in the 100 range.
-                // I don't know how portable this is vs. the 256 color sequences,
-                // so we may need to make an adjustment here later.
+                // Note: these brights are emitted using codes in the 90 and
+                // 100 ranges; see SgrEncodeOptions::bright_colors_as_256 for
+                // terminals that don't understand them.
                 (Grey, BackgroundBrightBlack),
                 (Red, BackgroundBrightRed),
                 (Lime, BackgroundBrightGreen),
@@ -1391,817 +1970,1958 @@ impl Display for Sgr {
                 (Aqua, BackgroundBrightCyan),
                 (White, BackgroundBrightWhite)
             ),
-            Sgr::Background(ColorSpec::TrueColor(c)) => {
-                let (red, green, blue) = c.to_tuple_rgb8();
-                write!(
-                    f,
-                    "{}:2::{}:{}:{}m",
-                    SgrCode::BackgroundColor as i64,
-                    red,
-                    green,
-                    blue
-                )?
-            }
+            Sgr::Background(ColorSpec::TrueColor(c)) => true_color!(BackgroundColor, c),
             Sgr::UnderlineColor(ColorSpec::Default) => code!(ResetUnderlineColor),
-            Sgr::UnderlineColor(ColorSpec::TrueColor(c)) => {
-                let (red, green, blue) = c.to_tuple_rgb8();
-                write!(
-                    f,
-                    "{}:2::{}:{}:{}m",
-                    SgrCode::UnderlineColor as i64,
-                    red,
-                    green,
-                    blue
-                )?
-            }
+            Sgr::UnderlineColor(ColorSpec::TrueColor(c)) => true_color!(UnderlineColor, c),
             Sgr::UnderlineColor(ColorSpec::PaletteIndex(idx)) => {
                 write!(f, "{}:5:{}m", SgrCode::UnderlineColor as i64, *idx)?
             }
+            Sgr::Unknown(code) => write!(f, "{}m", code)?,
+            Sgr::UnknownUnderline(style) => write!(f, "4:{}m", style)?,
         }
         Ok(())
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum Font {
-    Default,
-    Alternate(u8),
+impl Display for Sgr {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        self.encode(f, &SgrEncodeOptions::default())
+    }
 }
 
-/// Constrol Sequence Initiator (CSI) Parser.
-/// Since many sequences allow for composition of actions by separating
-/// `;` character, we need to be able to iterate over
-/// the set of parsed actions from a given CSI sequence.
-/// `CSIParser` implements an Iterator that yields `CSI` instances as
-/// it parses them out from the input sequence.
-struct CSIParser<'a> {
-    /// this flag is set when more than two intermediates
-    /// arrived and subsequent characters were ignored.
-    parameters_truncated: bool,
-    control: char,
-    /// While params is_some we have more data to consume.  The advance_by
-    /// method updates the slice as we consume data.
-    /// In a number of cases an empty params list is used to indicate
-    /// default values, especially for SGR, so we need to be careful not
-    /// to update params to an empty slice.
-    params: Option<&'a [CsiParam]>,
-    orig_params: &'a [CsiParam],
-}
+impl Sgr {
+    /// Parses a string of SGR parameters, such as `"1;3;38;5;160"`,
+    /// without the `ESC [` introducer or the trailing `m`.
+    /// Fields are separated by `;`, and the `:` subparameter separator
+    /// is accepted too, so color specifications may span several fields
+    /// in either form.
+    /// An empty string yields `Sgr::Reset`, matching `CSI m`.
+    pub fn parse_list(s: &str) -> Result<Vec<Sgr>, SgrParseError> {
+        let mut params = vec![];
+        let mut digits = s;
+        while !digits.is_empty() {
+            let end = digits
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(digits.len());
+            if end > 0 {
+                let value = digits[..end]
+                    .parse()
+                    .map_err(|_| SgrParseError::InvalidNumber(digits[..end].to_string()))?;
+                params.push(CsiParam::Integer(value));
+            }
+            match digits[end..].chars().next() {
+                None => break,
+                Some(c @ ';') | Some(c @ ':') => params.push(CsiParam::P(c as u8)),
+                Some(c) => return Err(SgrParseError::InvalidCharacter(c)),
+            }
+            digits = &digits[end + 1..];
+        }
 
-impl CSI {
-    /// Parse a CSI sequence.
-    /// Returns an iterator that yields individual CSI actions.
-    /// Why not a single?  Because sequences like `CSI [ 1 ; 3 m`
-    /// embed two separate actions but are sent as a single unit.
-    /// If no semantic meaning is known for a subsequence, the remainder
-    /// of the sequence is returned wrapped in a `CSI::Unspecified` container.
-    pub fn parse<'a>(
-        params: &'a [CsiParam],
-        parameters_truncated: bool,
-        control: char,
-    ) -> impl Iterator<Item = CSI> + 'a {
-        CSIParser {
-            parameters_truncated,
-            control,
-            params: Some(params),
-            orig_params: params,
+        CSI::parse(&params, false, 'm')
+            .map(|csi| match csi {
+                CSI::Sgr(sgr) => Ok(sgr),
+                CSI::Unspecified(unspec) => Err(SgrParseError::Unrecognized(
+                    unspec.params.iter().map(|p| p.to_string()).collect(),
+                )),
+                // Only digits and separators reach the parser, so it
+                // shouldn't produce anything else, but don't panic if it does
+                _ => Err(SgrParseError::Unrecognized(s.to_string())),
+            })
+            .collect()
+    }
+
+    /// Returns the value that the attribute adjusted by self
+    /// takes on after an `Sgr::Reset`.
+    /// Returns None for `Sgr::Reset` itself and for the unknown codes.
+    fn reset_value(&self) -> Option<Sgr> {
+        match self {
+            Sgr::Reset | Sgr::Unknown(_) => None,
+            Sgr::Intensity(_) => Some(Sgr::Intensity(Intensity::Normal)),
+            Sgr::Underline(_) | Sgr::UnknownUnderline(_) => Some(Sgr::Underline(Underline::None)),
+            Sgr::UnderlineColor(_) => Some(Sgr::UnderlineColor(ColorSpec::Default)),
+            Sgr::Blink(_) => Some(Sgr::Blink(Blink::None)),
+            Sgr::Italic(_) => Some(Sgr::Italic(false)),
+            Sgr::Fraktur(_) => Some(Sgr::Fraktur(false)),
+            Sgr::Inverse(_) => Some(Sgr::Inverse(false)),
+            Sgr::Invisible(_) => Some(Sgr::Invisible(false)),
+            Sgr::StrikeThrough(_) => Some(Sgr::StrikeThrough(false)),
+            Sgr::Font(_) => Some(Sgr::Font(Font::Default)),
+            Sgr::Foreground(_) => Some(Sgr::Foreground(ColorSpec::Default)),
+            Sgr::Background(_) => Some(Sgr::Background(ColorSpec::Default)),
+            Sgr::Overline(_) => Some(Sgr::Overline(false)),
         }
     }
-}
 
-/// A little helper to convert i64 -> u8 if safe
-fn to_u8(v: &CsiParam) -> Result<u8, ()> {
-    match v {
-        CsiParam::P(_) => Err(()),
-        CsiParam::Integer(v) => {
-            if *v <= i64::from(u8::max_value()) {
-                Ok(*v as u8)
-            } else {
-                Err(())
-            }
+    /// Returns true if self and other adjust the same attribute
+    fn same_attribute(&self, other: &Sgr) -> bool {
+        match (self, other) {
+            (
+                Sgr::Underline(_) | Sgr::UnknownUnderline(_),
+                Sgr::Underline(_) | Sgr::UnknownUnderline(_),
+            ) => true,
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other),
         }
     }
-}
 
-/// Convert the input value to 1-based u32.
-/// The intent is to protect consumers from out of range values
-/// when operating on the data, while balancing strictness with
-/// practical implementation bugs.  For example, it is common
-/// to see 0 values being emitted from existing libraries, and
-/// we desire to see the intended output.
-/// Ensures that the value is in the range 1..=max_value.
-/// If the input is 0 it is treated as 1.  If the value is
-/// otherwise outside that range, an error is propagated and
-/// that will typically case the sequence to be reported via
-/// the Unspecified placeholder.
-fn to_1b_u32(v: &CsiParam) -> Result<u32, ()> {
-    match v {
-        CsiParam::Integer(v) if *v == 0 => Ok(1),
-        CsiParam::Integer(v) if *v > 0 && *v <= i64::from(u32::max_value()) => Ok(*v as u32),
-        _ => Err(()),
+    /// Returns true if applying self replaces the effect of other.
+    /// In addition to adjusting the same attribute, SGR 23 turns off
+    /// both italic and Fraktur.
+    fn overrides(&self, other: &Sgr) -> bool {
+        match (self, other) {
+            (Sgr::Italic(false), Sgr::Fraktur(_)) | (Sgr::Fraktur(false), Sgr::Italic(_)) => true,
+            _ => self.same_attribute(other),
+        }
     }
 }
 
-struct Cracked {
-    params: Vec<Option<CsiParam>>,
+/// Accumulates the effect of a series of `Sgr` values, tracking
+/// the set of attributes that differ from their reset values.
+/// `Sgr::Reset` clears everything, including the extended attributes
+/// such as overline and underline color.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SgrState {
+    attributes: Vec<Sgr>,
 }
 
-impl Cracked {
-    pub fn parse(params: &[CsiParam]) -> Result<Self, ()> {
-        let mut res = vec![];
-        let mut iter = params.iter().peekable();
-        while let Some(p) = iter.next() {
-            match p {
-                CsiParam::P(b';') => {
-                    res.push(None);
-                }
-                CsiParam::Integer(_) => {
-                    res.push(Some(p.clone()));
-                    if let Some(CsiParam::P(b';')) = iter.peek() {
-                        iter.next();
-                    }
-                }
-                _ => return Err(()),
+impl SgrState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns true if all attributes have their reset values
+    pub fn is_default(&self) -> bool {
+        self.attributes.is_empty()
+    }
+
+    /// Returns the sequence of `Sgr` values that establishes this
+    /// state, starting from the reset state.
+    /// The sequence is empty when `is_default` is true.
+    pub fn to_sgr_sequence(&self) -> Vec<Sgr> {
+        self.attributes.clone()
+    }
+
+    /// Update the state to reflect the effect of `sgr`
+    pub fn apply(&mut self, sgr: &Sgr) {
+        match sgr {
+            Sgr::Reset => {
+                self.attributes.clear();
+                return;
             }
+            // We don't know what this does, so we can't track it
+            Sgr::Unknown(_) => return,
+            _ => {}
+        }
+        self.attributes.retain(|s| !sgr.overrides(s));
+        if sgr.reset_value().as_ref() != Some(sgr) {
+            self.attributes.push(sgr.clone());
         }
-        Ok(Self { params: res })
     }
 
-    pub fn get(&self, idx: usize) -> Option<&CsiParam> {
-        self.params.get(idx)?.as_ref()
+    /// Returns the sequence of Sgr values required to transition
+    /// from self to target without using a Reset.
+    fn changes_to(&self, target: &SgrState) -> Vec<Sgr> {
+        let mut changes = vec![];
+        for attr in &self.attributes {
+            if !target.attributes.iter().any(|t| t.same_attribute(attr)) {
+                changes.extend(attr.reset_value());
+            }
+        }
+        for attr in &target.attributes {
+            if !self.attributes.contains(attr) || changes.iter().any(|c| c.overrides(attr)) {
+                changes.push(attr.clone());
+            }
+        }
+        changes
     }
+}
 
-    pub fn opt_int(&self, idx: usize) -> Option<i64> {
-        self.get(idx).and_then(CsiParam::as_integer)
+/// Returns true if the shortest way to go from the `from` rendition
+/// to the `to` rendition is to emit `Sgr::Reset` and then establish
+/// `to` from scratch, rather than switching off and on only the
+/// attributes that differ.
+/// Both strategies are measured by their encoded size as a single
+/// collapsed SGR sequence; when they are the same size the direct
+/// change is preferred, so this returns false.
+pub fn needs_reset_to_transition(from: &SgrState, to: &SgrState) -> bool {
+    let direct: Vec<CSI> = from.changes_to(to).into_iter().map(CSI::Sgr).collect();
+    if direct.is_empty() {
+        return false;
     }
+    let rebuild: Vec<CSI> = std::iter::once(Sgr::Reset)
+        .chain(to.to_sgr_sequence())
+        .map(CSI::Sgr)
+        .collect();
+    collapsed_size(&rebuild) < collapsed_size(&direct)
+}
 
-    pub fn int(&self, idx: usize) -> Result<i64, ()> {
-        self.get(idx).and_then(CsiParam::as_integer).ok_or(())
+/// Produces a CSS declaration block, such as
+/// `color:#cd0000;font-weight:bold;text-decoration:underline`,
+/// that renders text in the same style as `attrs`, for use when
+/// exporting terminal output as HTML.
+/// Palette colors are resolved using `palette`.
+/// Default colors produce no declaration and are left to the
+/// surrounding stylesheet, which means that inverse video can only be
+/// represented when the swapped colors are not the defaults.
+/// Alternate fonts, Fraktur and blinking have no CSS equivalent
+/// and are ignored.
+pub fn sgr_to_css(attrs: &SgrState, palette: &[RgbColor; 256]) -> String {
+    let color = |spec: &ColorSpec| match spec {
+        ColorSpec::Default => None,
+        ColorSpec::PaletteIndex(idx) => Some(palette[*idx as usize].to_rgb_string()),
+        ColorSpec::TrueColor(c) => Some(c.to_rgb_string()),
+    };
+
+    let mut foreground = &ColorSpec::Default;
+    let mut background = &ColorSpec::Default;
+    let mut inverse = false;
+    let mut props = vec![];
+    let mut lines = vec![];
+    let mut line_style = None;
+    let mut line_color = None;
+
+    for attr in &attrs.attributes {
+        match attr {
+            Sgr::Foreground(spec) => foreground = spec,
+            Sgr::Background(spec) => background = spec,
+            Sgr::Inverse(on) => inverse = *on,
+            Sgr::Intensity(Intensity::Bold) => props.push("font-weight:bold".to_string()),
+            Sgr::Intensity(Intensity::Half) => props.push("opacity:0.5".to_string()),
+            Sgr::Italic(true) => props.push("font-style:italic".to_string()),
+            Sgr::Invisible(true) => props.push("visibility:hidden".to_string()),
+            Sgr::Underline(underline) => {
+                line_style = match underline {
+                    Underline::None => continue,
+                    Underline::Single => None,
+                    Underline::Double => Some("double"),
+                    Underline::Curly => Some("wavy"),
+                    Underline::Dotted => Some("dotted"),
+                    Underline::Dashed => Some("dashed"),
+                };
+                lines.push("underline");
+            }
+            Sgr::UnderlineColor(spec) => line_color = color(spec),
+            Sgr::Overline(true) => lines.push("overline"),
+            Sgr::StrikeThrough(true) => lines.push("line-through"),
+            _ => {}
+        }
     }
 
-    pub fn len(&self) -> usize {
-        self.params.len()
+    if inverse {
+        std::mem::swap(&mut foreground, &mut background);
     }
+    let mut decls = vec![];
+    if let Some(fg) = color(foreground) {
+        decls.push(format!("color:{}", fg));
+    }
+    if let Some(bg) = color(background) {
+        decls.push(format!("background-color:{}", bg));
+    }
+    decls.extend(props);
+    if !lines.is_empty() {
+        let mut decoration = format!("text-decoration:{}", lines.join(" "));
+        // The style and color apply to all of the lines, so they are
+        // only emitted when there is an underline for them to describe
+        if lines.contains(&"underline") {
+            for value in line_style.iter().copied().chain(line_color.as_deref()) {
+                decoration.push(' ');
+                decoration.push_str(value);
+            }
+        }
+        decls.push(decoration);
+    }
+    decls.join(";")
 }
 
-macro_rules! noparams {
-    ($ns:ident, $variant:ident, $params:expr) => {{
-        if $params.len() != 0 {
-            Err(())
-        } else {
-            Ok(CSI::$ns($ns::$variant))
+/// Coalesces redundant SGR resets in a parsed sequence.
+///
+/// Windows ConPTY tends to emit a full `Sgr::Reset` followed by
+/// re-establishing the complete set of attributes, even when most of
+/// those attributes are already in effect.  This function replaces
+/// each such `Reset` and the run of `Sgr` values that immediately
+/// follows it with just the net change, unless
+/// `needs_reset_to_transition` finds that a `Reset` followed by the
+/// attributes of the new state encodes to fewer bytes, in which case
+/// that is used instead.
+///
+/// The rendition state prior to the first `Reset` in `seq` is unknown,
+/// so that first `Reset` is always preserved.
+pub fn coalesce_sgr_resets(seq: &[CSI]) -> Vec<CSI> {
+    let mut result = vec![];
+    let mut state = SgrState::default();
+    let mut known_state = false;
+    let mut iter = seq.iter().peekable();
+
+    while let Some(csi) = iter.next() {
+        match csi {
+            CSI::Sgr(Sgr::Reset) => {
+                let mut target = SgrState::default();
+                let mut run = vec![];
+                while let Some(CSI::Sgr(sgr)) = iter.peek() {
+                    if *sgr == Sgr::Reset {
+                        break;
+                    }
+                    target.apply(sgr);
+                    run.push(sgr.clone());
+                    iter.next();
+                }
+
+                let has_unknown = run.iter().any(|sgr| matches!(sgr, Sgr::Unknown(_)));
+                if !known_state || has_unknown {
+                    result.push(csi.clone());
+                    result.extend(run.into_iter().map(CSI::Sgr));
+                } else if needs_reset_to_transition(&state, &target) {
+                    result.push(csi.clone());
+                    result.extend(target.to_sgr_sequence().into_iter().map(CSI::Sgr));
+                } else {
+                    result.extend(state.changes_to(&target).into_iter().map(CSI::Sgr));
+                }
+                state = target;
+                known_state = true;
+            }
+            CSI::Sgr(sgr) => {
+                state.apply(sgr);
+                result.push(csi.clone());
+            }
+            _ => result.push(csi.clone()),
         }
-    }};
+    }
+
+    result
 }
 
-macro_rules! parse {
-    ($ns:ident, $variant:ident, $params:expr) => {{
-        let value = ParseParams::parse_params($params)?;
-        Ok(CSI::$ns($ns::$variant(value)))
-    }};
+/// A `fmt::Write` sink that counts the bytes written to it
+#[derive(Default)]
+struct ByteCounter(usize);
 
-    ($ns:ident, $variant:ident, $first:ident, $second:ident, $params:expr) => {{
-        let (p1, p2): (OneBased, OneBased) = ParseParams::parse_params($params)?;
-        Ok(CSI::$ns($ns::$variant {
-            $first: p1,
-            $second: p2,
-        }))
-    }};
+impl std::fmt::Write for ByteCounter {
+    fn write_str(&mut self, s: &str) -> Result<(), FmtError> {
+        self.0 += s.len();
+        Ok(())
+    }
 }
 
-impl<'a> CSIParser<'a> {
-    fn parse_next(&mut self, params: &'a [CsiParam]) -> Result<CSI, ()> {
-        match (self.control, self.orig_params) {
-            ('q', [.., CsiParam::P(b' ')]) => self.cursor_style(params),
-            ('y', [.., CsiParam::P(b'*')]) => self.checksum_area(params),
+fn encoded_len<T: Display>(item: &T) -> usize {
+    use std::fmt::Write;
+    let mut counter = ByteCounter::default();
+    write!(counter, "{}", item).ok();
+    counter.0
+}
 
-            ('c', [CsiParam::P(b'='), ..]) => self
-                .req_tertiary_device_attributes(params)
-                .map(|dev| CSI::Device(Box::new(dev))),
-            ('c', [CsiParam::P(b'>'), ..]) => self
-                .req_secondary_device_attributes(params)
-                .map(|dev| CSI::Device(Box::new(dev))),
+/// Returns the number of bytes that encoding each element of `seq`
+/// would produce, including the `ESC [` introducers, without
+/// allocating the encoded bytes.
+pub fn encoded_size(seq: &[CSI]) -> usize {
+    seq.iter().map(encoded_len).sum()
+}
 
-            ('m', [CsiParam::P(b'<'), ..]) | ('M', [CsiParam::P(b'<'), ..]) => {
-                self.mouse_sgr1006(params).map(CSI::Mouse)
+/// Returns the number of bytes required to encode `seq` if each run
+/// of consecutive `CSI::Sgr` elements were collapsed into a single
+/// sequence, such as `CSI 1 ; 31 m` rather than `CSI 1 m CSI 31 m`.
+/// This can be compared with `encoded_size` to decide whether
+/// collapsing is worthwhile.
+pub fn collapsed_size(seq: &[CSI]) -> usize {
+    let mut size = 0;
+    let mut iter = seq.iter().peekable();
+    while let Some(csi) = iter.next() {
+        match csi {
+            CSI::Sgr(sgr) => {
+                // `ESC [` + the codes separated by `;` + the final `m`
+                size += 2 + encoded_len(sgr);
+                while let Some(CSI::Sgr(sgr)) = iter.peek() {
+                    // Each encoded Sgr ends with `m`; we replace it
+                    // with the `;` separator
+                    size += encoded_len(sgr);
+                    iter.next();
+                }
             }
+            _ => size += encoded_len(csi),
+        }
+    }
+    size
+}
 
-            ('c', [CsiParam::P(b'?'), ..]) => self
-                .secondary_device_attributes(params)
-                .map(|dev| CSI::Device(Box::new(dev))),
+/// Examines `seq` for the DEC private modes that switch between the
+/// primary and alternate screens (1049, 1047 and 47) and returns
+/// whether the alternate screen is active at the end of the sequence.
+/// Returns None if `seq` doesn't change the active screen.
+/// Multiplexers can use this to decide how to buffer output.
+pub fn enters_alt_screen(seq: &[CSI]) -> Option<bool> {
+    fn is_alt_screen(mode: &DecPrivateMode) -> bool {
+        matches!(
+            mode,
+            DecPrivateMode::Code(DecPrivateModeCode::ClearAndEnableAlternateScreen)
+                | DecPrivateMode::Code(DecPrivateModeCode::OptEnableAlternateScreen)
+                | DecPrivateMode::Code(DecPrivateModeCode::EnableAlternateScreen)
+        )
+    }
 
-            ('S', [CsiParam::P(b'?'), ..]) => XtSmGraphics::parse(params),
-            ('p', [CsiParam::Integer(_), CsiParam::P(b'$')])
-            | ('p', [CsiParam::P(b'?'), CsiParam::Integer(_), CsiParam::P(b'$')]) => {
-                self.decrqm(params)
-            }
-            ('h', [CsiParam::P(b'?'), ..]) => self
-                .dec(self.focus(params, 1, 0))
-                .map(|mode| CSI::Mode(Mode::SetDecPrivateMode(mode))),
-            ('l', [CsiParam::P(b'?'), ..]) => self
-                .dec(self.focus(params, 1, 0))
-                .map(|mode| CSI::Mode(Mode::ResetDecPrivateMode(mode))),
-            ('r', [CsiParam::P(b'?'), ..]) => self
-                .dec(self.focus(params, 1, 0))
-                .map(|mode| CSI::Mode(Mode::RestoreDecPrivateMode(mode))),
-            ('q', [CsiParam::P(b'>'), ..]) => self
-                .req_terminal_name_and_version(params)
-                .map(|dev| CSI::Device(Box::new(dev))),
-            ('s', [CsiParam::P(b'?'), ..]) => self
-                .dec(self.focus(params, 1, 0))
-                .map(|mode| CSI::Mode(Mode::SaveDecPrivateMode(mode))),
-            ('m', [CsiParam::P(b'>'), ..]) => self.xterm_key_modifier(params),
+    seq.iter().fold(None, |state, csi| match csi {
+        CSI::Mode(Mode::SetDecPrivateMode(mode)) if is_alt_screen(mode) => Some(true),
+        CSI::Mode(Mode::ResetDecPrivateMode(mode)) if is_alt_screen(mode) => Some(false),
+        _ => state,
+    })
+}
 
-            ('p', [CsiParam::P(b'!')]) => Ok(CSI::Device(Box::new(Device::SoftReset))),
+/// Returns the sequence that erases the line containing the cursor
+/// from the 0-based column `from_col` onwards, along with all of the
+/// lines below it.
+/// ED 0 already erases the remainder of the cursor line, so there is
+/// no need to follow the cursor movement with EL 0.
+pub fn clear_below(from_col: u32) -> Vec<CSI> {
+    vec![
+        CSI::Cursor(Cursor::CharacterAbsolute(OneBased::from_zero_based(
+            from_col,
+        ))),
+        CSI::Edit(Edit::EraseInDisplay(EraseInDisplay::EraseToEndOfDisplay)),
+    ]
+}
 
-            _ => match self.control {
-                'c' => self
-                    .req_primary_device_attributes(params)
-                    .map(|dev| CSI::Device(Box::new(dev))),
-
-                '@' => parse!(Edit, InsertCharacter, params),
-                '`' => parse!(Cursor, CharacterPositionAbsolute, params),
-                'A' => parse!(Cursor, Up, params),
-                'B' => parse!(Cursor, Down, params),
-                'C' => parse!(Cursor, Right, params),
-                'D' => parse!(Cursor, Left, params),
-                'E' => parse!(Cursor, NextLine, params),
-                'F' => parse!(Cursor, PrecedingLine, params),
-                'G' => parse!(Cursor, CharacterAbsolute, params),
-                'H' => parse!(Cursor, Position, line, col, params),
-                'I' => parse!(Cursor, ForwardTabulation, params),
-                'J' => parse!(Edit, EraseInDisplay, params),
-                'K' => parse!(Edit, EraseInLine, params),
-                'L' => parse!(Edit, InsertLine, params),
-                'M' => parse!(Edit, DeleteLine, params),
-                'P' => parse!(Edit, DeleteCharacter, params),
-                'R' => parse!(Cursor, ActivePositionReport, line, col, params),
-                'S' => parse!(Edit, ScrollUp, params),
-                'T' => parse!(Edit, ScrollDown, params),
-                'W' => parse!(Cursor, TabulationControl, params),
-                'X' => parse!(Edit, EraseCharacter, params),
-                'Y' => parse!(Cursor, LineTabulation, params),
-                'Z' => parse!(Cursor, BackwardTabulation, params),
-
-                'a' => parse!(Cursor, CharacterPositionForward, params),
-                'b' => parse!(Edit, Repeat, params),
-                'd' => parse!(Cursor, LinePositionAbsolute, params),
-                'e' => parse!(Cursor, LinePositionForward, params),
-                'f' => parse!(Cursor, CharacterAndLinePosition, line, col, params),
-                'g' => parse!(Cursor, TabulationClear, params),
-                'h' => self
-                    .terminal_mode(params)
-                    .map(|mode| CSI::Mode(Mode::SetMode(mode))),
-                'j' => parse!(Cursor, CharacterPositionBackward, params),
-                'k' => parse!(Cursor, LinePositionBackward, params),
-                'l' => self
-                    .terminal_mode(params)
-                    .map(|mode| CSI::Mode(Mode::ResetMode(mode))),
-
-                'm' => self.sgr(params).map(CSI::Sgr),
-                'n' => self.dsr(params),
-                'r' => self.decstbm(params),
-                's' => self.decslrm(params),
-                't' => self.window(params).map(CSI::Window),
-                'u' => noparams!(Cursor, RestoreCursor, params),
-                'x' => self
-                    .req_terminal_parameters(params)
-                    .map(|dev| CSI::Device(Box::new(dev))),
-
-                _ => Err(()),
-            },
-        }
-    }
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Font {
+    Default,
+    Alternate(u8),
+}
 
-    /// Consume some number of elements from params and update it.
-    /// Take care to avoid setting params back to an empty slice
-    /// as this would trigger returning a default value and/or
-    /// an unterminated parse loop.
-    fn advance_by<T>(&mut self, n: usize, params: &'a [CsiParam], result: T) -> T {
-        let n = if matches!(params.get(n), Some(CsiParam::P(b';'))) {
-            n + 1
-        } else {
-            n
-        };
+/// Constrol Sequence Initiator (CSI) Parser.
+/// Since many sequences allow for composition of actions by separating
+/// `;` character, we need to be able to iterate over
+/// the set of parsed actions from a given CSI sequence.
+/// `CSIParser` implements an Iterator that yields `CSI` instances as
+/// it parses them out from the input sequence.
+struct CSIParser<'a> {
+    /// this flag is set when more than two intermediates
+    /// arrived and subsequent characters were ignored.
+    parameters_truncated: bool,
+    control: char,
+    /// While params is_some we have more data to consume.  The advance_by
+    /// method updates the slice as we consume data.
+    /// In a number of cases an empty params list is used to indicate
+    /// default values, especially for SGR, so we need to be careful not
+    /// to update params to an empty slice.
+    params: Option<&'a [CsiParam]>,
+    orig_params: &'a [CsiParam],
+    /// Set when a subsequence that couldn't be interpreted has been
+    /// skipped over so that parsing can resume after it; holds the
+    /// parameters that it spanned.
+    skipped: Option<&'a [CsiParam]>,
+}
 
-        let (_, next) = params.split_at(n);
-        if !next.is_empty() {
-            self.params = Some(next);
+impl CSI {
+    /// Parse a CSI sequence.
+    /// Returns an iterator that yields individual CSI actions.
+    /// Why not a single?  Because sequences like `CSI [ 1 ; 3 m`
+    /// embed two separate actions but are sent as a single unit.
+    /// If no semantic meaning is known for a subsequence, the remainder
+    /// of the sequence is returned wrapped in a `CSI::Unspecified` container.
+    pub fn parse<'a>(
+        params: &'a [CsiParam],
+        parameters_truncated: bool,
+        control: char,
+    ) -> impl Iterator<Item = CSI> + 'a {
+        CSIParser {
+            parameters_truncated,
+            control,
+            params: Some(params),
+            orig_params: params,
+            skipped: None,
         }
-        result
     }
 
-    fn focus(&self, params: &'a [CsiParam], from_start: usize, from_end: usize) -> &'a [CsiParam] {
-        if params == self.orig_params {
-            let len = params.len();
-            &params[from_start..len - from_end]
-        } else {
-            params
-        }
+    /// Parse a CSI sequence, as `CSI::parse` does, but allow `handler`
+    /// to intercept any subsequence that would otherwise be returned
+    /// as `CSI::Unspecified`.
+    /// If `handler` returns `Some` then that value is yielded in place
+    /// of the `Unspecified` value, allowing vendor specific or
+    /// experimental sequences to be mapped to a custom `CSI`.
+    /// If `handler` returns `None` then the `Unspecified` value is
+    /// yielded as usual.
+    pub fn parse_with_handler<'a, F>(
+        params: &'a [CsiParam],
+        parameters_truncated: bool,
+        control: char,
+        mut handler: F,
+    ) -> impl Iterator<Item = CSI> + 'a
+    where
+        F: FnMut(&Unspecified) -> Option<CSI> + 'a,
+    {
+        Self::parse(params, parameters_truncated, control).map(move |csi| match csi {
+            CSI::Unspecified(unspec) => handler(&unspec).unwrap_or(CSI::Unspecified(unspec)),
+            csi => csi,
+        })
     }
 
-    fn cursor_style(&mut self, params: &'a [CsiParam]) -> Result<CSI, ()> {
-        match params {
-            [CsiParam::Integer(p), CsiParam::P(b' ')] => match FromPrimitive::from_i64(*p) {
-                None => Err(()),
-                Some(style) => {
-                    Ok(self.advance_by(2, params, CSI::Cursor(Cursor::CursorStyle(style))))
-                }
-            },
-            _ => Err(()),
-        }
+    /// Parse a CSI sequence, as `CSI::parse` does, but yield the reason
+    /// that each subsequence couldn't be interpreted in place of
+    /// `CSI::Unspecified`, to help diagnose misbehaving applications.
+    /// SGR codes that are parsed as `Sgr::Unknown` are also reported
+    /// as errors.
+    pub fn try_parse<'a>(
+        params: &'a [CsiParam],
+        parameters_truncated: bool,
+        control: char,
+    ) -> impl Iterator<Item = Result<CSI, CsiParseError>> + 'a {
+        let mut parser = CSIParser {
+            parameters_truncated,
+            control,
+            params: Some(params),
+            orig_params: params,
+            skipped: None,
+        };
+        std::iter::from_fn(move || parser.next_result()).map(|res| match res {
+            Ok(CSI::Sgr(Sgr::Unknown(code))) => Err(CsiParseError::UnknownSgrCode(code)),
+            Ok(csi) => Ok(csi),
+            Err((err, _)) => Err(err),
+        })
     }
 
-    fn checksum_area(&mut self, params: &'a [CsiParam]) -> Result<CSI, ()> {
-        let params = Cracked::parse(&params[..params.len() - 1])?;
+    /// Returns true if self was parsed from a sequence that had data
+    /// discarded by the lower level parser because it had too many
+    /// parameters or intermediates.
+    /// Encoding such a value cannot reproduce the original sequence.
+    /// Only `CSI::Unspecified` retains the `parameters_truncated` flag;
+    /// sequences that are recognized despite the truncation are still
+    /// interpreted and return false here.
+    pub fn is_lossy(&self) -> bool {
+        matches!(self, CSI::Unspecified(unspec) if unspec.parameters_truncated)
+    }
+}
 
-        let request_id = params.int(0)?;
-        let page_number = params.int(1)?;
-        let top = OneBased::from_optional_esc_param(params.get(2))?;
-        let left = OneBased::from_optional_esc_param(params.get(3))?;
-        let bottom = OneBased::from_optional_esc_param(params.get(4))?;
-        let right = OneBased::from_optional_esc_param(params.get(5))?;
-        Ok(CSI::Window(Window::ChecksumRectangularArea {
-            request_id,
-            page_number,
-            top,
-            left,
-            bottom,
-            right,
-        }))
+/// Describes why `CSI::validate` rejected a sequence
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum CsiValidationError {
+    #[error("unknown CSI control {0:?}")]
+    UnknownControl(char),
+    #[error("unexpected intermediates {intermediates:?} for CSI control {control:?}")]
+    UnexpectedIntermediates {
+        control: char,
+        intermediates: Vec<u8>,
+    },
+    #[error(
+        "CSI control {control:?} accepts at most {max} parameters, but {actual} were supplied"
+    )]
+    TooManyParameters {
+        control: char,
+        max: usize,
+        actual: usize,
+    },
+    #[error("parameter {value} at index {index} is out of range for CSI control {control:?}")]
+    ParameterOutOfRange {
+        control: char,
+        index: usize,
+        value: i64,
+    },
+    #[error("incomplete SGR color specification at index {index}")]
+    IncompleteSgrColor { index: usize },
+}
+
+/// Describes why `CSI::try_parse` couldn't interpret a sequence.
+/// Other than `UnknownControl`, the variants relate to the control
+/// that was passed to `CSI::try_parse`.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum CsiParseError {
+    #[error("unknown CSI control {control:?} with intermediates {intermediates:?}")]
+    UnknownControl {
+        control: char,
+        intermediates: Vec<u8>,
+    },
+    #[error("wrong number of parameters ({count})")]
+    WrongParameterCount { count: usize },
+    #[error("parameter value {value} is out of range")]
+    OutOfRangeParameter { value: i64 },
+    #[error("required parameter at index {index} is missing")]
+    MissingParameter { index: usize },
+    /// A separator or intermediate where a value was expected, such
+    /// as the `:` of a code that doesn't accept subparameters
+    #[error("unexpected parameter {0:?}")]
+    UnexpectedParameter(CsiParam),
+    #[error("unknown SGR code {0}")]
+    UnknownSgrCode(i64),
+}
+
+/// Describes why a checked constructor, such as `Cursor::try_position`,
+/// rejected its parameters
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum CsiBuildError {
+    #[error("{name} is 1-based and must not be zero")]
+    Zero { name: &'static str },
+    #[error("{name} value {value} is out of range")]
+    OutOfRange { name: &'static str, value: i64 },
+    #[error("top margin {top} must be above bottom margin {bottom}")]
+    InvalidMargins { top: u32, bottom: u32 },
+}
+
+/// Describes why `Sgr::parse_list` rejected its input
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum SgrParseError {
+    #[error("invalid character {0:?} in SGR parameters")]
+    InvalidCharacter(char),
+    #[error("SGR parameter {0} is out of range")]
+    InvalidNumber(String),
+    #[error("unrecognized SGR parameters {0:?}")]
+    Unrecognized(String),
+}
+
+fn checked_one_based(name: &'static str, value: i64) -> Result<OneBased, CsiBuildError> {
+    if value == 0 {
+        Err(CsiBuildError::Zero { name })
+    } else if value < 0 || value > i64::from(u32::max_value()) {
+        Err(CsiBuildError::OutOfRange { name, value })
+    } else {
+        Ok(OneBased::new(value as u32))
     }
+}
 
-    fn dsr(&mut self, params: &'a [CsiParam]) -> Result<CSI, ()> {
-        match params {
-            [CsiParam::Integer(5)] => {
-                Ok(self.advance_by(1, params, CSI::Device(Box::new(Device::StatusReport))))
+impl CSI {
+    /// Checks that the supplied control, intermediates and parameters
+    /// form a well-formed sequence with the right number of parameters
+    /// and with parameter values in the accepted ranges.
+    /// This is intended to be used by tools that construct CSI
+    /// sequences programmatically, to catch mistakes before the
+    /// sequence is sent to the terminal.
+    pub fn validate(
+        control: char,
+        intermediates: &[u8],
+        params: &[i64],
+    ) -> Result<(), CsiValidationError> {
+        let max_params = |max: usize| {
+            if params.len() > max {
+                Err(CsiValidationError::TooManyParameters {
+                    control,
+                    max,
+                    actual: params.len(),
+                })
+            } else {
+                Ok(())
+            }
+        };
+        let in_range = |index: usize, range: std::ops::RangeInclusive<i64>| match params.get(index)
+        {
+            Some(&value) if !range.contains(&value) => {
+                Err(CsiValidationError::ParameterOutOfRange {
+                    control,
+                    index,
+                    value,
+                })
+            }
+            _ => Ok(()),
+        };
+        let all_in_range = |range: std::ops::RangeInclusive<i64>| {
+            for index in 0..params.len() {
+                in_range(index, range.clone())?;
             }
+            Ok(())
+        };
 
-            [CsiParam::Integer(6)] => {
-                Ok(self.advance_by(1, params, CSI::Cursor(Cursor::RequestActivePositionReport)))
+        match (control, intermediates) {
+            ('q', b" ") => {
+                max_params(1)?;
+                in_range(0, 0..=6)
+            }
+            ('p', b"!") => max_params(0),
+            ('~', b"$") => {
+                max_params(1)?;
+                in_range(0, 0..=2)
+            }
+            ('}', b"$") => {
+                max_params(1)?;
+                in_range(0, 0..=1)
+            }
+            ('@', [])
+            | ('`', [])
+            | ('A'..='G', [])
+            | ('I', [])
+            | ('L', [])
+            | ('M', [])
+            | ('P', [])
+            | ('S', [])
+            | ('T', [])
+            | ('X', [])
+            | ('Y', [])
+            | ('Z', [])
+            | ('a', [])
+            | ('b', [])
+            | ('d', [])
+            | ('e', [])
+            | ('j', [])
+            | ('k', []) => {
+                max_params(1)?;
+                all_in_range(0..=i64::from(u32::max_value()))
+            }
+            ('H', []) | ('f', []) | ('r', []) | ('s', []) => {
+                max_params(2)?;
+                all_in_range(0..=i64::from(u32::max_value()))
+            }
+            ('J', []) => {
+                max_params(1)?;
+                in_range(0, 0..=3)
+            }
+            ('K', []) => {
+                max_params(1)?;
+                in_range(0, 0..=2)
+            }
+            ('W', []) => {
+                max_params(1)?;
+                in_range(0, 0..=6)
+            }
+            ('g', []) => {
+                max_params(1)?;
+                in_range(0, 0..=5)
+            }
+            ('n', []) => {
+                max_params(1)?;
+                in_range(0, 5..=6)
             }
-            _ => Err(()),
+            ('q', []) => {
+                for (index, &value) in params.iter().enumerate() {
+                    if !matches!(value, 0..=4 | 21..=24) {
+                        return Err(CsiValidationError::ParameterOutOfRange {
+                            control,
+                            index,
+                            value,
+                        });
+                    }
+                }
+                Ok(())
+            }
+            ('u', []) => max_params(0),
+            ('m', []) => Self::validate_sgr(params),
+            (_, []) => Err(CsiValidationError::UnknownControl(control)),
+            _ => Err(CsiValidationError::UnexpectedIntermediates {
+                control,
+                intermediates: intermediates.to_vec(),
+            }),
         }
     }
 
-    fn decstbm(&mut self, params: &'a [CsiParam]) -> Result<CSI, ()> {
-        match params {
-            [] => Ok(CSI::Cursor(Cursor::SetTopAndBottomMargins {
-                top: OneBased::new(1),
-                bottom: OneBased::new(u32::max_value()),
-            })),
-            [p] => Ok(self.advance_by(
-                1,
-                params,
-                CSI::Cursor(Cursor::SetTopAndBottomMargins {
-                    top: OneBased::from_esc_param(p)?,
-                    bottom: OneBased::new(u32::max_value()),
-                }),
-            )),
-            [a, CsiParam::P(b';'), b] => Ok(self.advance_by(
-                3,
-                params,
-                CSI::Cursor(Cursor::SetTopAndBottomMargins {
-                    top: OneBased::from_esc_param(a)?,
-                    bottom: OneBased::from_esc_param_with_big_default(b)?,
-                }),
-            )),
-            [CsiParam::P(b';'), b] => Ok(self.advance_by(
-                2,
-                params,
-                CSI::Cursor(Cursor::SetTopAndBottomMargins {
-                    top: OneBased::new(1),
-                    bottom: OneBased::from_esc_param_with_big_default(b)?,
-                }),
-            )),
-            _ => Err(()),
-        }
-    }
+    fn validate_sgr(params: &[i64]) -> Result<(), CsiValidationError> {
+        let out_of_range = |index: usize| CsiValidationError::ParameterOutOfRange {
+            control: 'm',
+            index,
+            value: params[index],
+        };
+        let component = |index: usize| match params.get(index) {
+            None => Err(CsiValidationError::IncompleteSgrColor { index }),
+            Some(v) if *v < 0 || *v > 255 => Err(out_of_range(index)),
+            Some(_) => Ok(()),
+        };
 
-    fn xterm_key_modifier(&mut self, params: &'a [CsiParam]) -> Result<CSI, ()> {
-        match params {
-            [CsiParam::P(b'>'), a, CsiParam::P(b';'), b] => {
-                let resource =
-                    XtermKeyModifierResource::parse(a.as_integer().unwrap()).ok_or_else(|| ())?;
-                Ok(self.advance_by(
-                    4,
-                    params,
-                    CSI::Mode(Mode::XtermKeyMode {
-                        resource,
-                        value: Some(b.as_integer().ok_or_else(|| ())?),
-                    }),
-                ))
-            }
-            [CsiParam::P(b'>'), p] => {
-                let resource =
-                    XtermKeyModifierResource::parse(p.as_integer().unwrap()).ok_or_else(|| ())?;
-                Ok(self.advance_by(
-                    2,
-                    params,
-                    CSI::Mode(Mode::XtermKeyMode {
-                        resource,
-                        value: None,
-                    }),
-                ))
+        let mut index = 0;
+        while index < params.len() {
+            match params[index] {
+                38 | 48 | 58 => match params.get(index + 1) {
+                    Some(5) => {
+                        component(index + 2)?;
+                        index += 3;
+                    }
+                    Some(2) => {
+                        component(index + 2)?;
+                        component(index + 3)?;
+                        component(index + 4)?;
+                        index += 5;
+                    }
+                    Some(_) => return Err(out_of_range(index + 1)),
+                    None => return Err(CsiValidationError::IncompleteSgrColor { index }),
+                },
+                code => {
+                    let known: Option<SgrCode> = FromPrimitive::from_i64(code);
+                    if known.is_none() {
+                        return Err(out_of_range(index));
+                    }
+                    index += 1;
+                }
             }
-            _ => Err(()),
         }
+        Ok(())
     }
+}
 
-    fn decslrm(&mut self, params: &'a [CsiParam]) -> Result<CSI, ()> {
-        match params {
-            [] => {
-                // with no params this is a request to save the cursor
-                // and is technically in conflict with SetLeftAndRightMargins.
-                // The emulator needs to decide based on DECSLRM mode
-                // whether this saves the cursor or is SetLeftAndRightMargins
-                // with default parameters!
-                Ok(CSI::Cursor(Cursor::SaveCursor))
+/// A little helper to convert i64 -> u8 if safe
+fn to_u8(v: &CsiParam) -> Result<u8, CsiParseError> {
+    match v {
+        CsiParam::P(_) => Err(CsiParseError::UnexpectedParameter(v.clone())),
+        CsiParam::Integer(v) => {
+            if *v <= i64::from(u8::max_value()) {
+                Ok(*v as u8)
+            } else {
+                Err(CsiParseError::OutOfRangeParameter { value: *v })
             }
-            [p] => Ok(self.advance_by(
-                1,
-                params,
-                CSI::Cursor(Cursor::SetLeftAndRightMargins {
-                    left: OneBased::from_esc_param(p)?,
-                    right: OneBased::new(u32::max_value()),
-                }),
-            )),
-            [a, CsiParam::P(b';'), b] => Ok(self.advance_by(
-                3,
-                params,
-                CSI::Cursor(Cursor::SetLeftAndRightMargins {
-                    left: OneBased::from_esc_param(a)?,
-                    right: OneBased::from_esc_param(b)?,
-                }),
-            )),
-            [CsiParam::P(b';'), b] => Ok(self.advance_by(
-                2,
-                params,
-                CSI::Cursor(Cursor::SetLeftAndRightMargins {
-                    left: OneBased::new(1),
-                    right: OneBased::from_esc_param(b)?,
-                }),
-            )),
-            _ => Err(()),
         }
     }
+}
 
-    fn req_primary_device_attributes(&mut self, params: &'a [CsiParam]) -> Result<Device, ()> {
-        match params {
-            [] => Ok(Device::RequestPrimaryDeviceAttributes),
-            [CsiParam::Integer(0)] => {
-                Ok(self.advance_by(1, params, Device::RequestPrimaryDeviceAttributes))
-            }
-            _ => Err(()),
-        }
+/// Convert the input value to 1-based u32.
+/// The intent is to protect consumers from out of range values
+/// when operating on the data, while balancing strictness with
+/// practical implementation bugs.  For example, it is common
+/// to see 0 values being emitted from existing libraries, and
+/// we desire to see the intended output.
+/// Ensures that the value is in the range 1..=max_value.
+/// If the input is 0 it is treated as 1.  If the value is
+/// otherwise outside that range, an error is propagated and
+/// that will typically case the sequence to be reported via
+/// the Unspecified placeholder.
+fn to_1b_u32(v: &CsiParam) -> Result<u32, CsiParseError> {
+    match v {
+        CsiParam::Integer(v) if *v == 0 => Ok(1),
+        CsiParam::Integer(v) if *v > 0 && *v <= i64::from(u32::max_value()) => Ok(*v as u32),
+        CsiParam::Integer(v) => Err(CsiParseError::OutOfRangeParameter { value: *v }),
+        CsiParam::P(_) => Err(CsiParseError::UnexpectedParameter(v.clone())),
     }
+}
 
-    fn req_terminal_name_and_version(&mut self, params: &'a [CsiParam]) -> Result<Device, ()> {
-        match params {
-            [_] => Ok(Device::RequestTerminalNameAndVersion),
-
-            [_, CsiParam::Integer(0)] => {
-                Ok(self.advance_by(2, params, Device::RequestTerminalNameAndVersion))
-            }
-            _ => Err(()),
-        }
+/// Convert the input value to u32, saturating at the bounds of
+/// the u32 range rather than failing.
+fn to_u32_saturating(v: &CsiParam) -> Result<u32, CsiParseError> {
+    match v {
+        CsiParam::Integer(v) if *v < 0 => Ok(0),
+        CsiParam::Integer(v) if *v > i64::from(u32::max_value()) => Ok(u32::max_value()),
+        CsiParam::Integer(v) => Ok(*v as u32),
+        CsiParam::P(_) => Err(CsiParseError::UnexpectedParameter(v.clone())),
     }
+}
 
-    fn req_secondary_device_attributes(&mut self, params: &'a [CsiParam]) -> Result<Device, ()> {
-        match params {
-            [CsiParam::P(b'>')] => Ok(Device::RequestSecondaryDeviceAttributes),
-            [CsiParam::P(b'>'), CsiParam::Integer(0)] => {
-                Ok(self.advance_by(2, params, Device::RequestSecondaryDeviceAttributes))
-            }
-            _ => Err(()),
-        }
-    }
+/// Wraps `OneBased::from_esc_param`, reporting why the value was rejected
+fn one_based(v: &CsiParam) -> Result<OneBased, CsiParseError> {
+    OneBased::from_esc_param(v).map_err(|()| match v {
+        CsiParam::Integer(value) => CsiParseError::OutOfRangeParameter { value: *value },
+        CsiParam::P(_) => CsiParseError::UnexpectedParameter(v.clone()),
+    })
+}
 
-    fn req_tertiary_device_attributes(&mut self, params: &'a [CsiParam]) -> Result<Device, ()> {
-        match params {
-            [CsiParam::P(b'=')] => Ok(Device::RequestTertiaryDeviceAttributes),
-            [CsiParam::P(b'='), CsiParam::Integer(0)] => {
-                Ok(self.advance_by(2, params, Device::RequestTertiaryDeviceAttributes))
+/// Wraps `OneBased::from_optional_esc_param`, reporting why the value
+/// was rejected
+fn optional_one_based(o: Option<&CsiParam>) -> Result<OneBased, CsiParseError> {
+    one_based(o.unwrap_or(&CsiParam::Integer(1)))
+}
+
+/// Converts value to a narrower integer type, such as the u16 of a
+/// mode number, rejecting it if it doesn't fit
+fn narrow<T: num_traits::NumCast>(value: i64) -> Result<T, CsiParseError> {
+    num_traits::cast(value).ok_or(CsiParseError::OutOfRangeParameter { value })
+}
+
+/// Maps value to the corresponding variant of an enum, rejecting
+/// it if there is no such variant
+fn enum_param<T: FromPrimitive>(value: i64) -> Result<T, CsiParseError> {
+    FromPrimitive::from_i64(value).ok_or(CsiParseError::OutOfRangeParameter { value })
+}
+
+/// Reports that params has the wrong number of `;` separated
+/// parameters for the control.  Intermediates are not counted.
+fn wrong_parameter_count(params: &[CsiParam]) -> CsiParseError {
+    let separators = params.iter().filter(|p| **p == CsiParam::P(b';')).count();
+    let count = if separators > 0 {
+        separators + 1
+    } else {
+        params.iter().any(|p| p.as_integer().is_some()) as usize
+    };
+    CsiParseError::WrongParameterCount { count }
+}
+
+struct Cracked {
+    params: Vec<Option<CsiParam>>,
+}
+
+impl Cracked {
+    pub fn parse(params: &[CsiParam]) -> Result<Self, CsiParseError> {
+        let mut res = vec![];
+        let mut iter = params.iter().peekable();
+        while let Some(p) = iter.next() {
+            match p {
+                CsiParam::P(b';') => {
+                    res.push(None);
+                }
+                CsiParam::Integer(_) => {
+                    res.push(Some(p.clone()));
+                    if let Some(CsiParam::P(b';')) = iter.peek() {
+                        iter.next();
+                    }
+                }
+                _ => return Err(CsiParseError::UnexpectedParameter(p.clone())),
             }
-            _ => Err(()),
         }
+        Ok(Self { params: res })
     }
 
-    fn secondary_device_attributes(&mut self, params: &'a [CsiParam]) -> Result<Device, ()> {
-        match params {
-            [_, CsiParam::Integer(1), CsiParam::P(b';'), CsiParam::Integer(0)] => Ok(self
-                .advance_by(
-                    4,
-                    params,
-                    Device::DeviceAttributes(DeviceAttributes::Vt101WithNoOptions),
-                )),
-            [_, CsiParam::Integer(6)] => {
-                Ok(self.advance_by(2, params, Device::DeviceAttributes(DeviceAttributes::Vt102)))
-            }
-            [_, CsiParam::Integer(1), CsiParam::P(b';'), CsiParam::Integer(2)] => Ok(self
-                .advance_by(
-                    4,
-                    params,
-                    Device::DeviceAttributes(DeviceAttributes::Vt100WithAdvancedVideoOption),
-                )),
-            [_, CsiParam::Integer(62), ..] => Ok(self.advance_by(
-                params.len(),
-                params,
-                Device::DeviceAttributes(DeviceAttributes::Vt220(
-                    DeviceAttributeFlags::from_params(&params[2..]),
-                )),
-            )),
-            [_, CsiParam::Integer(63), ..] => Ok(self.advance_by(
-                params.len(),
-                params,
-                Device::DeviceAttributes(DeviceAttributes::Vt320(
-                    DeviceAttributeFlags::from_params(&params[2..]),
-                )),
-            )),
-            [_, CsiParam::Integer(64), ..] => Ok(self.advance_by(
-                params.len(),
-                params,
-                Device::DeviceAttributes(DeviceAttributes::Vt420(
-                    DeviceAttributeFlags::from_params(&params[2..]),
-                )),
-            )),
-            _ => Err(()),
-        }
+    pub fn get(&self, idx: usize) -> Option<&CsiParam> {
+        self.params.get(idx)?.as_ref()
     }
 
-    fn req_terminal_parameters(&mut self, params: &'a [CsiParam]) -> Result<Device, ()> {
-        match params {
-            [] | [CsiParam::Integer(0)] => Ok(Device::RequestTerminalParameters(0)),
-            [CsiParam::Integer(1)] => Ok(Device::RequestTerminalParameters(1)),
-            _ => Err(()),
-        }
+    pub fn opt_int(&self, idx: usize) -> Option<i64> {
+        self.get(idx).and_then(CsiParam::as_integer)
     }
 
-    /// Parse extended mouse reports known as SGR 1006 mode
-    fn mouse_sgr1006(&mut self, params: &'a [CsiParam]) -> Result<MouseReport, ()> {
-        let (p0, p1, p2) = match params {
-            [CsiParam::P(b'<'), CsiParam::Integer(p0), CsiParam::P(b';'), CsiParam::Integer(p1), CsiParam::P(b';'), CsiParam::Integer(p2)] => {
-                (*p0, *p1, *p2)
-            }
-            _ => return Err(()),
-        };
+    pub fn int(&self, idx: usize) -> Result<i64, CsiParseError> {
+        self.get(idx)
+            .and_then(CsiParam::as_integer)
+            .ok_or(CsiParseError::MissingParameter { index: idx })
+    }
 
-        // 'M' encodes a press, 'm' a release.
-        let button = match (self.control, p0 & 0b110_0011) {
-            ('M', 0) => MouseButton::Button1Press,
-            ('m', 0) => MouseButton::Button1Release,
-            ('M', 1) => MouseButton::Button2Press,
-            ('m', 1) => MouseButton::Button2Release,
-            ('M', 2) => MouseButton::Button3Press,
-            ('m', 2) => MouseButton::Button3Release,
-            ('M', 64) => MouseButton::Button4Press,
-            ('m', 64) => MouseButton::Button4Release,
-            ('M', 65) => MouseButton::Button5Press,
-            ('m', 65) => MouseButton::Button5Release,
-            ('M', 32) => MouseButton::Button1Drag,
-            ('M', 33) => MouseButton::Button2Drag,
-            ('M', 34) => MouseButton::Button3Drag,
-            // Note that there is some theoretical ambiguity with these None values.
-            // The ambiguity stems from alternative encodings of the mouse protocol;
-            // when set to SGR1006 mode the variants with the `3` parameter do not
-            // occur.  They included here as a reminder for when support for those
-            // other encodings is added and this block is likely copied and pasted
-            // or refactored for re-use with them.
-            ('M', 35) => MouseButton::None, // mouse motion with no buttons
-            ('M', 3) => MouseButton::None,  // legacy notification about button release
-            ('m', 3) => MouseButton::None,  // release+press doesn't make sense
-            _ => {
-                return Err(());
-            }
-        };
+    pub fn len(&self) -> usize {
+        self.params.len()
+    }
+}
 
-        let mut modifiers = Modifiers::NONE;
-        if p0 & 4 != 0 {
-            modifiers |= Modifiers::SHIFT;
-        }
-        if p0 & 8 != 0 {
-            modifiers |= Modifiers::ALT;
-        }
-        if p0 & 16 != 0 {
-            modifiers |= Modifiers::CTRL;
+macro_rules! noparams {
+    ($ns:ident, $variant:ident, $params:expr) => {{
+        if $params.len() != 0 {
+            Err(wrong_parameter_count($params))
+        } else {
+            Ok(CSI::$ns($ns::$variant))
         }
+    }};
+}
 
-        Ok(self.advance_by(
-            6,
-            params,
-            MouseReport::SGR1006 {
-                x: p1 as u16,
-                y: p2 as u16,
-                button,
-                modifiers,
-            },
-        ))
-    }
+macro_rules! parse {
+    ($ns:ident, $variant:ident, $params:expr) => {{
+        let value = ParseParams::parse_params($params)?;
+        Ok(CSI::$ns($ns::$variant(value)))
+    }};
 
-    fn decrqm(&mut self, params: &'a [CsiParam]) -> Result<CSI, ()> {
-        Ok(CSI::Mode(match params {
-            [CsiParam::Integer(p), CsiParam::P(b'$')] => {
-                Mode::QueryMode(match FromPrimitive::from_i64(*p) {
-                    None => TerminalMode::Unspecified(p.to_u16().ok_or(())?),
-                    Some(mode) => TerminalMode::Code(mode),
-                })
+    ($ns:ident, $variant:ident, $first:ident, $second:ident, $params:expr) => {{
+        let (p1, p2): (OneBased, OneBased) = ParseParams::parse_params($params)?;
+        Ok(CSI::$ns($ns::$variant {
+            $first: p1,
+            $second: p2,
+        }))
+    }};
+}
+
+impl<'a> CSIParser<'a> {
+    fn parse_next(&mut self, params: &'a [CsiParam]) -> Result<CSI, CsiParseError> {
+        match (self.control, self.orig_params) {
+            (_, [.., CsiParam::P(b' ')]) => self.space_intermediate(params),
+            ('{', [.., CsiParam::P(b'#')]) => self
+                .sgr_aspects(params)
+                .map(|aspects| CSI::StateStack(StateStack::PushSgr(aspects))),
+            ('|', [.., CsiParam::P(b',')]) => self.assign_color(params),
+            ('}', [CsiParam::P(b'#')]) => Ok(CSI::StateStack(StateStack::PopSgr)),
+            // xterm also accepts `#p` and `#q` as aliases for `#{` and `#}`
+            ('p', [.., CsiParam::P(b'#')]) => self
+                .sgr_aspects(params)
+                .map(|aspects| CSI::StateStack(StateStack::PushSgrAlias(aspects))),
+            ('q', [CsiParam::P(b'#')]) => Ok(CSI::StateStack(StateStack::PopSgrAlias)),
+            ('P', [.., CsiParam::P(b'#')]) => self
+                .color_stack_slots(params)
+                .map(|slots| CSI::StateStack(StateStack::PushColors(slots))),
+            ('Q', [.., CsiParam::P(b'#')]) => self
+                .color_stack_slots(params)
+                .map(|slots| CSI::StateStack(StateStack::PopColors(slots))),
+            ('R', [CsiParam::P(b'#')]) => Ok(CSI::StateStack(StateStack::ReportColors)),
+            // As for the space intermediate above, any other control with
+            // a `#` intermediate is returned as Unspecified.
+            (_, [.., CsiParam::P(b'#')]) => Err(self.unknown_control()),
+            ('y', [.., CsiParam::P(b'*')]) => self.checksum_area(params),
+            ('v', [.., CsiParam::P(b'$')]) => self.copy_area(params),
+            ('~', [.., CsiParam::P(b'$')]) => self
+                .status_display(params)
+                .map(|t| CSI::Device(Box::new(Device::SelectStatusDisplayType(t)))),
+            ('}', [.., CsiParam::P(b'$')]) => self
+                .status_display(params)
+                .map(|d| CSI::Device(Box::new(Device::SelectActiveStatusDisplay(d)))),
+
+            ('c', [CsiParam::P(b'='), ..]) => self
+                .req_tertiary_device_attributes(params)
+                .map(|dev| CSI::Device(Box::new(dev))),
+            ('c', [CsiParam::P(b'>'), ..]) => self
+                .req_secondary_device_attributes(params)
+                .map(|dev| CSI::Device(Box::new(dev))),
+
+            ('m', [CsiParam::P(b'<'), ..]) | ('M', [CsiParam::P(b'<'), ..]) => {
+                self.mouse_sgr1006(params).map(CSI::Mouse)
             }
-            [CsiParam::P(b'?'), CsiParam::Integer(p), CsiParam::P(b'$')] => {
-                Mode::QueryDecPrivateMode(match FromPrimitive::from_i64(*p) {
-                    None => DecPrivateMode::Unspecified(p.to_u16().ok_or(())?),
-                    Some(mode) => DecPrivateMode::Code(mode),
-                })
+
+            ('c', [CsiParam::P(b'?'), ..]) => self
+                .secondary_device_attributes(params)
+                .map(|dev| CSI::Device(Box::new(dev))),
+
+            ('n', [CsiParam::P(b'?'), CsiParam::Integer(6)]) => {
+                Ok(CSI::Cursor(Cursor::RequestExtendedActivePositionReport))
             }
-            _ => return Err(()),
-        }))
+            ('R', [CsiParam::P(b'?'), ..]) => self.extended_active_position_report(params),
+            ('n', [CsiParam::P(b'?'), CsiParam::Integer(15)]) => {
+                Ok(CSI::Device(Box::new(Device::RequestPrinterStatus)))
+            }
+            ('n', [CsiParam::P(b'?'), CsiParam::Integer(25)]) => {
+                Ok(CSI::Device(Box::new(Device::RequestUserDefinedKeysStatus)))
+            }
+
+            ('S', [CsiParam::P(b'?'), ..]) => XtSmGraphics::parse(params),
+            ('p', [CsiParam::Integer(_), CsiParam::P(b'$')])
+            | ('p', [CsiParam::P(b'?'), CsiParam::Integer(_), CsiParam::P(b'$')]) => {
+                self.decrqm(params)
+            }
+            ('h', [CsiParam::P(b'?'), ..]) => self
+                .dec(self.focus(params, 1, 0))
+                .map(|mode| CSI::Mode(Mode::SetDecPrivateMode(mode))),
+            ('l', [CsiParam::P(b'?'), ..]) => self
+                .dec(self.focus(params, 1, 0))
+                .map(|mode| CSI::Mode(Mode::ResetDecPrivateMode(mode))),
+            ('r', [CsiParam::P(b'?'), ..]) => self
+                .dec(self.focus(params, 1, 0))
+                .map(|mode| CSI::Mode(Mode::RestoreDecPrivateMode(mode))),
+            ('q', [CsiParam::P(b'>'), ..]) => self
+                .req_terminal_name_and_version(params)
+                .map(|dev| CSI::Device(Box::new(dev))),
+            ('s', [CsiParam::P(b'?'), ..]) => self
+                .dec(self.focus(params, 1, 0))
+                .map(|mode| CSI::Mode(Mode::SaveDecPrivateMode(mode))),
+            ('m', [CsiParam::P(b'>'), ..]) => self.xterm_key_modifier(params),
+
+            ('p', [CsiParam::P(b'!')]) => Ok(CSI::Device(Box::new(Device::SoftReset))),
+
+            _ => self.parse_plain(params),
+        }
     }
 
-    fn dec(&mut self, params: &'a [CsiParam]) -> Result<DecPrivateMode, ()> {
-        match params {
-            [CsiParam::Integer(p0), ..] => match FromPrimitive::from_i64(*p0) {
-                None => Ok(self.advance_by(
-                    1,
-                    params,
-                    DecPrivateMode::Unspecified(p0.to_u16().ok_or(())?),
-                )),
-                Some(mode) => Ok(self.advance_by(1, params, DecPrivateMode::Code(mode))),
-            },
-            _ => Err(()),
+    /// Parses the controls that have no intermediates or private
+    /// parameter prefix; if any are present then the combination is
+    /// reported as an unknown control.
+    fn parse_plain(&mut self, params: &'a [CsiParam]) -> Result<CSI, CsiParseError> {
+        match self.plain_control(params) {
+            Err(_) if !self.intermediates().is_empty() => Err(self.unknown_control()),
+            res => res,
         }
     }
 
-    fn terminal_mode(&mut self, params: &'a [CsiParam]) -> Result<TerminalMode, ()> {
-        let p0 = params
-            .get(0)
-            .and_then(CsiParam::as_integer)
-            .ok_or_else(|| ())?;
-        match FromPrimitive::from_i64(p0) {
-            None => {
-                Ok(self.advance_by(1, params, TerminalMode::Unspecified(p0.to_u16().ok_or(())?)))
-            }
-            Some(mode) => Ok(self.advance_by(1, params, TerminalMode::Code(mode))),
+    fn plain_control(&mut self, params: &'a [CsiParam]) -> Result<CSI, CsiParseError> {
+        match self.control {
+            'c' => self
+                .req_primary_device_attributes(params)
+                .map(|dev| CSI::Device(Box::new(dev))),
+
+            '@' => parse!(Edit, InsertCharacter, params),
+            '`' => parse!(Cursor, CharacterPositionAbsolute, params),
+            'A' => parse!(Cursor, Up, params),
+            'B' => parse!(Cursor, Down, params),
+            'C' => parse!(Cursor, Right, params),
+            'D' => parse!(Cursor, Left, params),
+            'E' => parse!(Cursor, NextLine, params),
+            'F' => parse!(Cursor, PrecedingLine, params),
+            'G' => parse!(Cursor, CharacterAbsolute, params),
+            'H' => parse!(Cursor, Position, line, col, params),
+            'I' => parse!(Cursor, ForwardTabulation, params),
+            'J' => parse!(Edit, EraseInDisplay, params),
+            'K' => parse!(Edit, EraseInLine, params),
+            'L' => parse!(Edit, InsertLine, params),
+            'M' => parse!(Edit, DeleteLine, params),
+            'P' => parse!(Edit, DeleteCharacter, params),
+            'R' => parse!(Cursor, ActivePositionReport, line, col, params),
+            'S' => parse!(Edit, ScrollUp, params),
+            'T' => parse!(Edit, ScrollDown, params),
+            'W' => parse!(Cursor, TabulationControl, params),
+            'X' => parse!(Edit, EraseCharacter, params),
+            'Y' => parse!(Cursor, LineTabulation, params),
+            'Z' => parse!(Cursor, BackwardTabulation, params),
+
+            'a' => parse!(Cursor, CharacterPositionForward, params),
+            'b' => self.repeat(params),
+            'd' => parse!(Cursor, LinePositionAbsolute, params),
+            'e' => parse!(Cursor, LinePositionForward, params),
+            'f' => parse!(Cursor, CharacterAndLinePosition, line, col, params),
+            'g' => parse!(Cursor, TabulationClear, params),
+            'h' => self
+                .terminal_mode(params)
+                .map(|mode| CSI::Mode(Mode::SetMode(mode))),
+            'j' => parse!(Cursor, CharacterPositionBackward, params),
+            'k' => parse!(Cursor, LinePositionBackward, params),
+            'l' => self
+                .terminal_mode(params)
+                .map(|mode| CSI::Mode(Mode::ResetMode(mode))),
+
+            'm' => self.sgr(params).map(CSI::Sgr).map_err(|err| {
+                self.skip_sgr_subparameters(params);
+                err
+            }),
+            'n' => self.dsr(params),
+            'q' => self.decll(params).map(|dev| CSI::Device(Box::new(dev))),
+            'r' => self.decstbm(params),
+            's' => self.decslrm(params),
+            't' => self.window(params).map(CSI::Window),
+            'u' => noparams!(Cursor, RestoreCursor, params),
+            'x' => self
+                .req_terminal_parameters(params)
+                .map(|dev| CSI::Device(Box::new(dev))),
+
+            _ => Err(self.unknown_control()),
         }
     }
 
-    fn parse_sgr_color(&mut self, params: &'a [CsiParam]) -> Result<ColorSpec, ()> {
-        match params {
-            [_, CsiParam::P(b':'), CsiParam::Integer(2), CsiParam::P(b':'),
-             CsiParam::Integer(_colorspace), CsiParam::P(b':'),
-             red, CsiParam::P(b':'), green, CsiParam::P(b':'), blue, ..] => {
-                let res = RgbColor::new_8bpc(to_u8(red)?, to_u8(green)?, to_u8(blue)?).into();
-                Ok(self.advance_by(11, params, res))
-            }
+    /// Returns the intermediates and private parameter prefix of the
+    /// sequence, such as the `?` of `CSI ? 25 h`
+    fn intermediates(&self) -> Vec<u8> {
+        self.orig_params
+            .iter()
+            .filter_map(|p| match p {
+                CsiParam::P(b';') | CsiParam::P(b':') => None,
+                CsiParam::P(b) => Some(*b),
+                CsiParam::Integer(_) => None,
+            })
+            .collect()
+    }
 
-            [_, CsiParam::P(b':'), CsiParam::Integer(2), CsiParam::P(b':'), /* empty colorspace */ CsiParam::P(b':'), red, CsiParam::P(b':'), green, CsiParam::P(b':'), blue, ..] => {
-                let res = RgbColor::new_8bpc(to_u8(red)?, to_u8(green)?, to_u8(blue)?).into();
-                Ok(self.advance_by(10, params, res))
-            }
+    fn unknown_control(&self) -> CsiParseError {
+        CsiParseError::UnknownControl {
+            control: self.control,
+            intermediates: self.intermediates(),
+        }
+    }
 
-            [_, CsiParam::P(b';'), CsiParam::Integer(2), CsiParam::P(b';'), red, CsiParam::P(b';'), green, CsiParam::P(b';'), blue, ..] |
-            [_, CsiParam::P(b':'), CsiParam::Integer(2), CsiParam::P(b':'), red, CsiParam::P(b':'), green, CsiParam::P(b':'), blue, ..] =>
-            {
-                let res = RgbColor::new_8bpc(to_u8(red)?, to_u8(green)?, to_u8(blue)?).into();
-                Ok(self.advance_by(9, params, res))
-            }
+    /// Consume some number of elements from params and update it.
+    /// Take care to avoid setting params back to an empty slice
+    /// as this would trigger returning a default value and/or
+    /// an unterminated parse loop.
+    fn advance_by<T>(&mut self, n: usize, params: &'a [CsiParam], result: T) -> T {
+        let n = if matches!(params.get(n), Some(CsiParam::P(b';'))) {
+            n + 1
+        } else {
+            n
+        };
 
-            [_, CsiParam::P(b';'), CsiParam::Integer(5), CsiParam::P(b';'), idx, ..] |
-            [_, CsiParam::P(b':'), CsiParam::Integer(5), CsiParam::P(b':'), idx, ..] => {
-                Ok(self.advance_by(5, params, ColorSpec::PaletteIndex(to_u8(idx)?)))
-            }
-            _ => Err(()),
+        let (_, next) = params.split_at(n);
+        if !next.is_empty() {
+            self.params = Some(next);
         }
+        result
     }
 
-    fn window(&mut self, params: &'a [CsiParam]) -> Result<Window, ()> {
-        let params = Cracked::parse(params)?;
+    fn focus(&self, params: &'a [CsiParam], from_start: usize, from_end: usize) -> &'a [CsiParam] {
+        if params == self.orig_params {
+            let len = params.len();
+            &params[from_start..len - from_end]
+        } else {
+            params
+        }
+    }
 
-        let p = params.int(0)?;
-        let arg1 = params.opt_int(1);
-        let arg2 = params.opt_int(2);
+    /// Dispatches the controls that use a space intermediate.
+    /// ECMA-48 defines the following, by final byte; those marked
+    /// with `*` are parsed into a variant:
+    ///
+    /// | | | | | | |
+    /// |-|-|-|-|-|-|
+    /// | `@` SL | `A` SR | `B` GSM `*` | `C` GSS | `D` FNT | `E` TSS |
+    /// | `F` JFY `*` | `G` SPI `*` | `H` QUAD | `I` SSU | `J` PFS | `K` SHS |
+    /// | `L` SVS | `M` IGS | `O` IDCS | `P` PPA `*` | `Q` PPR `*` | `R` PPB `*` |
+    /// | `S` SPD | `T` DTA | `U` SLH | `V` SLL | `W` FNK | `X` SPQR |
+    /// | `Y` SEF | `Z` PEC | `[` SSW | `\` SACS | `]` SAPV | `^` STAB `*` |
+    /// | `_` GCC | `` ` `` TATE | `a` TALE | `b` TAC | `c` TCC | `d` TSR |
+    /// | `e` SCO | `f` SRCS | `g` SCS | `h` SLS | `i` SPH | `j` SPL |
+    /// | `k` SCP | | | | | |
+    ///
+    /// DECSCUSR (`q`) is the only private use control that is parsed.
+    /// Anything else, such as DECTME (`CSI Ps SP ~`), is rejected here
+    /// rather than risk it being mistaken for the same control without
+    /// the intermediate, so that it is returned as Unspecified with
+    /// the space intact.
+    fn space_intermediate(&mut self, params: &'a [CsiParam]) -> Result<CSI, CsiParseError> {
+        match self.control {
+            'q' => self.cursor_style(params),
+            'B' => self.graphic_size_modification(params),
+            'P' | 'Q' | 'R' => self.page_position(params),
+            'F' => self.justify(params),
+            'G' => self.spacing_increment(params),
+            '^' => self.selective_tabulation(params),
+            _ => Err(self.unknown_control()),
+        }
+    }
 
-        match p {
-            1 => Ok(Window::DeIconify),
-            2 => Ok(Window::Iconify),
-            3 => Ok(Window::MoveWindow {
-                x: arg1.unwrap_or(0),
-                y: arg2.unwrap_or(0),
-            }),
-            4 => Ok(Window::ResizeWindowPixels {
-                height: arg1,
-                width: arg2,
-            }),
-            5 => Ok(Window::RaiseWindow),
-            6 => match params.len() {
-                1 => Ok(Window::LowerWindow),
-                _ => Ok(Window::ReportCellSizePixelsResponse {
-                    height: arg1,
-                    width: arg2,
-                }),
-            },
-            7 => Ok(Window::RefreshWindow),
-            8 => Ok(Window::ResizeWindowCells {
-                height: arg1,
-                width: arg2,
-            }),
-            9 => match arg1 {
-                Some(0) => Ok(Window::RestoreMaximizedWindow),
-                Some(1) => Ok(Window::MaximizeWindow),
-                Some(2) => Ok(Window::MaximizeWindowVertically),
-                Some(3) => Ok(Window::MaximizeWindowHorizontally),
-                _ => Err(()),
-            },
-            10 => match arg1 {
-                Some(0) => Ok(Window::UndoFullScreenMode),
-                Some(1) => Ok(Window::ChangeToFullScreenMode),
-                Some(2) => Ok(Window::ToggleFullScreen),
-                _ => Err(()),
-            },
-            11 => Ok(Window::ReportWindowState),
-            13 => match arg1 {
-                None => Ok(Window::ReportWindowPosition),
-                Some(2) => Ok(Window::ReportTextAreaPosition),
-                _ => Err(()),
-            },
-            14 => match arg1 {
-                None => Ok(Window::ReportTextAreaSizePixels),
-                Some(2) => Ok(Window::ReportWindowSizePixels),
-                _ => Err(()),
-            },
-            15 => Ok(Window::ReportScreenSizePixels),
-            16 => Ok(Window::ReportCellSizePixels),
-            18 => Ok(Window::ReportTextAreaSizeCells),
-            19 => Ok(Window::ReportScreenSizeCells),
-            20 => Ok(Window::ReportIconLabel),
-            21 => Ok(Window::ReportWindowTitle),
-            22 => match arg1 {
-                Some(0) => Ok(Window::PushIconAndWindowTitle),
-                Some(1) => Ok(Window::PushIconTitle),
-                Some(2) => Ok(Window::PushWindowTitle),
-                _ => Err(()),
-            },
-            23 => match arg1 {
-                Some(0) => Ok(Window::PopIconAndWindowTitle),
-                Some(1) => Ok(Window::PopIconTitle),
-                Some(2) => Ok(Window::PopWindowTitle),
-                _ => Err(()),
-            },
-            _ => Err(()),
+    fn selective_tabulation(&mut self, params: &'a [CsiParam]) -> Result<CSI, CsiParseError> {
+        let params = Cracked::parse(&params[..params.len() - 1])?;
+        if params.len() != 1 {
+            return Err(CsiParseError::WrongParameterCount {
+                count: params.len(),
+            });
         }
+        let n = narrow(params.int(0)?)?;
+        Ok(CSI::Presentation(Presentation::SelectiveTabulation(n)))
     }
 
-    fn underline(&mut self, params: &'a [CsiParam]) -> Result<Sgr, ()> {
-        let (sgr, n) = match params {
-            [_, CsiParam::P(b':'), CsiParam::Integer(0), ..] => {
-                (Sgr::Underline(Underline::None), 3)
-            }
-            [_, CsiParam::P(b':'), CsiParam::Integer(1), ..] => {
-                (Sgr::Underline(Underline::Single), 3)
-            }
-            [_, CsiParam::P(b':'), CsiParam::Integer(2), ..] => {
-                (Sgr::Underline(Underline::Double), 3)
-            }
-            [_, CsiParam::P(b':'), CsiParam::Integer(3), ..] => {
-                (Sgr::Underline(Underline::Curly), 3)
-            }
-            [_, CsiParam::P(b':'), CsiParam::Integer(4), ..] => {
-                (Sgr::Underline(Underline::Dotted), 3)
+    fn cursor_style(&mut self, params: &'a [CsiParam]) -> Result<CSI, CsiParseError> {
+        match params {
+            // An omitted parameter is equivalent to 0
+            [CsiParam::P(b' ')] => Ok(self.advance_by(
+                1,
+                params,
+                CSI::Cursor(Cursor::CursorStyle(CursorStyle::Default)),
+            )),
+            [CsiParam::Integer(p), CsiParam::P(b' ')] => {
+                let style = enum_param(*p)?;
+                Ok(self.advance_by(2, params, CSI::Cursor(Cursor::CursorStyle(style))))
             }
-            [_, CsiParam::P(b':'), CsiParam::Integer(5), ..] => {
-                (Sgr::Underline(Underline::Dashed), 3)
+            _ => Err(wrong_parameter_count(params)),
+        }
+    }
+
+    fn graphic_size_modification(&mut self, params: &'a [CsiParam]) -> Result<CSI, CsiParseError> {
+        let params = Cracked::parse(&params[..params.len() - 1])?;
+        if params.len() > 2 {
+            return Err(CsiParseError::WrongParameterCount {
+                count: params.len(),
+            });
+        }
+        let percent = |idx: usize| -> Result<u32, CsiParseError> {
+            match params.opt_int(idx) {
+                None => Ok(100),
+                Some(n) => narrow(n),
             }
-            _ => (Sgr::Underline(Underline::Single), 1),
         };
+        Ok(CSI::Presentation(Presentation::GraphicSizeModification {
+            height: percent(0)?,
+            width: percent(1)?,
+        }))
+    }
 
-        Ok(self.advance_by(n, params, sgr))
+    fn page_position(&mut self, params: &'a [CsiParam]) -> Result<CSI, CsiParseError> {
+        let params = Cracked::parse(&params[..params.len() - 1])?;
+        if params.len() > 1 {
+            return Err(CsiParseError::WrongParameterCount {
+                count: params.len(),
+            });
+        }
+        let n = match params.opt_int(0) {
+            None => 1,
+            Some(n) => narrow(n)?,
+        };
+        Ok(CSI::Presentation(match self.control {
+            'P' => Presentation::PagePositionAbsolute(n),
+            'Q' => Presentation::PagePositionForward(n),
+            _ => Presentation::PagePositionBackward(n),
+        }))
     }
 
-    fn sgr(&mut self, params: &'a [CsiParam]) -> Result<Sgr, ()> {
-        if params.is_empty() {
-            // With no parameters, treat as equivalent to Reset.
-            Ok(Sgr::Reset)
-        } else {
-            for p in params {
-                match p {
-                    CsiParam::P(b';') | CsiParam::P(b':') | CsiParam::Integer(_) => {}
-                    _ => return Err(()),
-                }
+    fn justify(&mut self, params: &'a [CsiParam]) -> Result<CSI, CsiParseError> {
+        let params = Cracked::parse(&params[..params.len() - 1])?;
+        let mut modes = vec![];
+        // An empty parameter list is equivalent to a single 0
+        for idx in 0..params.len().max(1) {
+            match params.opt_int(idx).unwrap_or(0) {
+                mode @ 0..=8 => modes.push(mode as u8),
+                value => return Err(CsiParseError::OutOfRangeParameter { value }),
             }
+        }
+        Ok(CSI::Presentation(Presentation::Justify(modes)))
+    }
 
-            // Consume a single parameter and return the parsed result
-            macro_rules! one {
-                ($t:expr) => {
-                    Ok(self.advance_by(1, params, $t))
-                };
-            }
+    fn spacing_increment(&mut self, params: &'a [CsiParam]) -> Result<CSI, CsiParseError> {
+        let params = Cracked::parse(&params[..params.len() - 1])?;
+        if params.len() != 2 {
+            return Err(CsiParseError::WrongParameterCount {
+                count: params.len(),
+            });
+        }
+        let value = |idx: usize| -> Result<u32, CsiParseError> { narrow(params.int(idx)?) };
+        Ok(CSI::Presentation(Presentation::SpacingIncrement {
+            line: value(0)?,
+            character: value(1)?,
+        }))
+    }
+
+    fn assign_color(&mut self, params: &'a [CsiParam]) -> Result<CSI, CsiParseError> {
+        let params = Cracked::parse(&params[..params.len() - 1])?;
+        if params.len() != 3 {
+            return Err(CsiParseError::WrongParameterCount {
+                count: params.len(),
+            });
+        }
+        let value = |idx: usize| -> Result<u16, CsiParseError> { narrow(params.int(idx)?) };
+        Ok(CSI::Mode(Mode::AssignColor {
+            item: value(0)?,
+            fg: value(1)?,
+            bg: value(2)?,
+        }))
+    }
+
+    fn sgr_aspects(&mut self, params: &'a [CsiParam]) -> Result<Vec<SgrAspect>, CsiParseError> {
+        let params = Cracked::parse(&params[..params.len() - 1])?;
+        let mut aspects = vec![];
+        for idx in 0..params.len() {
+            aspects.push(enum_param(params.int(idx)?)?);
+        }
+        Ok(aspects)
+    }
+
+    fn color_stack_slots(&mut self, params: &'a [CsiParam]) -> Result<Vec<u8>, CsiParseError> {
+        let params = Cracked::parse(&params[..params.len() - 1])?;
+        let mut slots = vec![];
+        for idx in 0..params.len() {
+            match params.int(idx)? {
+                slot @ 1..=10 => slots.push(slot as u8),
+                value => return Err(CsiParseError::OutOfRangeParameter { value }),
+            }
+        }
+        Ok(slots)
+    }
+
+    fn extended_active_position_report(
+        &mut self,
+        params: &'a [CsiParam],
+    ) -> Result<CSI, CsiParseError> {
+        let params = Cracked::parse(&params[1..])?;
+        if params.len() != 3 {
+            return Err(CsiParseError::WrongParameterCount {
+                count: params.len(),
+            });
+        }
+        Ok(CSI::Cursor(Cursor::ExtendedActivePositionReport {
+            line: optional_one_based(params.get(0))?,
+            col: optional_one_based(params.get(1))?,
+            page: optional_one_based(params.get(2))?,
+        }))
+    }
+
+    fn checksum_area(&mut self, params: &'a [CsiParam]) -> Result<CSI, CsiParseError> {
+        let params = Cracked::parse(&params[..params.len() - 1])?;
+
+        let request_id = params.int(0)?;
+        let page_number = params.int(1)?;
+        let top = optional_one_based(params.get(2))?;
+        let left = optional_one_based(params.get(3))?;
+        let bottom = optional_one_based(params.get(4))?;
+        let right = optional_one_based(params.get(5))?;
+        Ok(CSI::Window(Window::ChecksumRectangularArea {
+            request_id,
+            page_number,
+            top,
+            left,
+            bottom,
+            right,
+        }))
+    }
+
+    fn copy_area(&mut self, params: &'a [CsiParam]) -> Result<CSI, CsiParseError> {
+        let params = Cracked::parse(&params[..params.len() - 1])?;
+        if params.len() > 8 {
+            return Err(CsiParseError::WrongParameterCount {
+                count: params.len(),
+            });
+        }
+
+        // An omitted or 0 bottom or right edge is the end of the page
+        let edge = |idx: usize| -> Result<Option<OneBased>, CsiParseError> {
+            match params.get(idx) {
+                None | Some(CsiParam::Integer(0)) => Ok(None),
+                Some(p) => one_based(p).map(Some),
+            }
+        };
+
+        Ok(CSI::Window(Window::CopyRectangularArea {
+            top: optional_one_based(params.get(0))?,
+            left: optional_one_based(params.get(1))?,
+            bottom: edge(2)?,
+            right: edge(3)?,
+            page: params.opt_int(4).unwrap_or(1),
+            dest_top: optional_one_based(params.get(5))?,
+            dest_left: optional_one_based(params.get(6))?,
+            dest_page: params.opt_int(7).unwrap_or(1),
+        }))
+    }
+
+    fn dsr(&mut self, params: &'a [CsiParam]) -> Result<CSI, CsiParseError> {
+        match params {
+            [CsiParam::Integer(5)] => {
+                Ok(self.advance_by(1, params, CSI::Device(Box::new(Device::StatusReport))))
+            }
+
+            [CsiParam::Integer(6)] => {
+                Ok(self.advance_by(1, params, CSI::Cursor(Cursor::RequestActivePositionReport)))
+            }
+            [CsiParam::Integer(value)] => Err(CsiParseError::OutOfRangeParameter { value: *value }),
+            _ => Err(wrong_parameter_count(params)),
+        }
+    }
+
+    /// Parses the single, optional, parameter of DECSSDT and DECSASD.
+    /// An omitted parameter is equivalent to 0.
+    fn status_display<T: FromPrimitive>(
+        &mut self,
+        params: &'a [CsiParam],
+    ) -> Result<T, CsiParseError> {
+        let params = Cracked::parse(&params[..params.len() - 1])?;
+        if params.len() > 1 {
+            return Err(CsiParseError::WrongParameterCount {
+                count: params.len(),
+            });
+        }
+        enum_param(params.opt_int(0).unwrap_or(0))
+    }
+
+    fn decll(&mut self, params: &'a [CsiParam]) -> Result<Device, CsiParseError> {
+        let params = Cracked::parse(params)?;
+        let mut leds = vec![];
+        // An empty parameter list is equivalent to a single 0
+        for idx in 0..params.len().max(1) {
+            match params.opt_int(idx).unwrap_or(0) {
+                led @ 0..=4 | led @ 21..=24 => leds.push(led as u8),
+                value => return Err(CsiParseError::OutOfRangeParameter { value }),
+            }
+        }
+        Ok(Device::SetKeyboardLeds(leds))
+    }
+
+    fn decstbm(&mut self, params: &'a [CsiParam]) -> Result<CSI, CsiParseError> {
+        // A bottom margin of 0 is equivalent to the last line
+        fn bottom(p: &CsiParam) -> Result<Option<OneBased>, CsiParseError> {
+            match p {
+                CsiParam::Integer(0) => Ok(None),
+                p => one_based(p).map(Some),
+            }
+        }
+
+        match params {
+            [] => Ok(CSI::Cursor(Cursor::SetTopAndBottomMargins {
+                top: OneBased::new(1),
+                bottom: None,
+            })),
+            [p] => Ok(self.advance_by(
+                1,
+                params,
+                CSI::Cursor(Cursor::SetTopAndBottomMargins {
+                    top: one_based(p)?,
+                    bottom: None,
+                }),
+            )),
+            // Some terminals send a third parameter; it has no
+            // defined meaning for DECSTBM, so we ignore it.
+            [a, CsiParam::P(b';'), b]
+            | [a, CsiParam::P(b';'), b, CsiParam::P(b';'), CsiParam::Integer(_)] => Ok(self
+                .advance_by(
+                    params.len(),
+                    params,
+                    CSI::Cursor(Cursor::SetTopAndBottomMargins {
+                        top: one_based(a)?,
+                        bottom: bottom(b)?,
+                    }),
+                )),
+            [CsiParam::P(b';'), b] => Ok(self.advance_by(
+                2,
+                params,
+                CSI::Cursor(Cursor::SetTopAndBottomMargins {
+                    top: OneBased::new(1),
+                    bottom: bottom(b)?,
+                }),
+            )),
+            _ => Err(wrong_parameter_count(params)),
+        }
+    }
+
+    fn repeat(&mut self, params: &'a [CsiParam]) -> Result<CSI, CsiParseError> {
+        let n = match params {
+            [] => 1,
+            [p] => to_u32_saturating(p)?.max(1).min(MAX_REPEAT_COUNT),
+            _ => return Err(wrong_parameter_count(params)),
+        };
+        Ok(self.advance_by(params.len(), params, CSI::Edit(Edit::Repeat(n))))
+    }
+
+    fn xterm_key_modifier(&mut self, params: &'a [CsiParam]) -> Result<CSI, CsiParseError> {
+        fn resource(p: &CsiParam) -> Result<XtermKeyModifierResource, CsiParseError> {
+            match p {
+                CsiParam::Integer(value) => XtermKeyModifierResource::parse(*value)
+                    .ok_or(CsiParseError::OutOfRangeParameter { value: *value }),
+                CsiParam::P(_) => Err(CsiParseError::UnexpectedParameter(p.clone())),
+            }
+        }
+
+        match params {
+            [CsiParam::P(b'>'), a, CsiParam::P(b';'), b] => {
+                let resource = resource(a)?;
+                Ok(self.advance_by(
+                    4,
+                    params,
+                    CSI::Mode(Mode::XtermKeyMode {
+                        resource,
+                        value: Some(
+                            b.as_integer()
+                                .ok_or_else(|| CsiParseError::UnexpectedParameter(b.clone()))?,
+                        ),
+                    }),
+                ))
+            }
+            [CsiParam::P(b'>'), p] => {
+                let resource = resource(p)?;
+                Ok(self.advance_by(
+                    2,
+                    params,
+                    CSI::Mode(Mode::XtermKeyMode {
+                        resource,
+                        value: None,
+                    }),
+                ))
+            }
+            _ => Err(wrong_parameter_count(params)),
+        }
+    }
+
+    fn decslrm(&mut self, params: &'a [CsiParam]) -> Result<CSI, CsiParseError> {
+        match params {
+            [] => {
+                // with no params this is a request to save the cursor
+                // and is technically in conflict with SetLeftAndRightMargins.
+                // The emulator needs to decide based on DECSLRM mode
+                // whether this saves the cursor or is SetLeftAndRightMargins
+                // with default parameters!
+                Ok(CSI::Cursor(Cursor::SaveCursor))
+            }
+            [p] => Ok(self.advance_by(
+                1,
+                params,
+                CSI::Cursor(Cursor::SetLeftAndRightMargins {
+                    left: one_based(p)?,
+                    right: OneBased::new(u32::max_value()),
+                }),
+            )),
+            [a, CsiParam::P(b';'), b] => Ok(self.advance_by(
+                3,
+                params,
+                CSI::Cursor(Cursor::SetLeftAndRightMargins {
+                    left: one_based(a)?,
+                    right: one_based(b)?,
+                }),
+            )),
+            [CsiParam::P(b';'), b] => Ok(self.advance_by(
+                2,
+                params,
+                CSI::Cursor(Cursor::SetLeftAndRightMargins {
+                    left: OneBased::new(1),
+                    right: one_based(b)?,
+                }),
+            )),
+            _ => Err(wrong_parameter_count(params)),
+        }
+    }
+
+    fn req_primary_device_attributes(
+        &mut self,
+        params: &'a [CsiParam],
+    ) -> Result<Device, CsiParseError> {
+        match params {
+            [] => Ok(Device::RequestPrimaryDeviceAttributes),
+            [CsiParam::Integer(0)] => {
+                Ok(self.advance_by(1, params, Device::RequestPrimaryDeviceAttributes))
+            }
+            [CsiParam::Integer(value)] => Err(CsiParseError::OutOfRangeParameter { value: *value }),
+            _ => Err(wrong_parameter_count(params)),
+        }
+    }
+
+    fn req_terminal_name_and_version(
+        &mut self,
+        params: &'a [CsiParam],
+    ) -> Result<Device, CsiParseError> {
+        match params {
+            [_] => Ok(Device::RequestTerminalNameAndVersion),
+
+            [_, CsiParam::Integer(0)] => {
+                Ok(self.advance_by(2, params, Device::RequestTerminalNameAndVersion))
+            }
+            [_, CsiParam::Integer(value)] => {
+                Err(CsiParseError::OutOfRangeParameter { value: *value })
+            }
+            _ => Err(wrong_parameter_count(params)),
+        }
+    }
+
+    fn req_secondary_device_attributes(
+        &mut self,
+        params: &'a [CsiParam],
+    ) -> Result<Device, CsiParseError> {
+        match params {
+            [CsiParam::P(b'>')] => Ok(Device::RequestSecondaryDeviceAttributes),
+            [CsiParam::P(b'>'), CsiParam::Integer(0)] => {
+                Ok(self.advance_by(2, params, Device::RequestSecondaryDeviceAttributes))
+            }
+            [_, CsiParam::Integer(value)] => {
+                Err(CsiParseError::OutOfRangeParameter { value: *value })
+            }
+            _ => Err(wrong_parameter_count(params)),
+        }
+    }
+
+    fn req_tertiary_device_attributes(
+        &mut self,
+        params: &'a [CsiParam],
+    ) -> Result<Device, CsiParseError> {
+        match params {
+            [CsiParam::P(b'=')] => Ok(Device::RequestTertiaryDeviceAttributes),
+            [CsiParam::P(b'='), CsiParam::Integer(0)] => {
+                Ok(self.advance_by(2, params, Device::RequestTertiaryDeviceAttributes))
+            }
+            [_, CsiParam::Integer(value)] => {
+                Err(CsiParseError::OutOfRangeParameter { value: *value })
+            }
+            _ => Err(wrong_parameter_count(params)),
+        }
+    }
+
+    fn secondary_device_attributes(
+        &mut self,
+        params: &'a [CsiParam],
+    ) -> Result<Device, CsiParseError> {
+        match params {
+            [_, CsiParam::Integer(1), CsiParam::P(b';'), CsiParam::Integer(0)] => Ok(self
+                .advance_by(
+                    4,
+                    params,
+                    Device::DeviceAttributes(DeviceAttributes::Vt101WithNoOptions),
+                )),
+            [_, CsiParam::Integer(6)] => {
+                Ok(self.advance_by(2, params, Device::DeviceAttributes(DeviceAttributes::Vt102)))
+            }
+            [_, CsiParam::Integer(1), CsiParam::P(b';'), CsiParam::Integer(2)] => Ok(self
+                .advance_by(
+                    4,
+                    params,
+                    Device::DeviceAttributes(DeviceAttributes::Vt100WithAdvancedVideoOption),
+                )),
+            [_, CsiParam::Integer(62), ..] => Ok(self.advance_by(
+                params.len(),
+                params,
+                Device::DeviceAttributes(DeviceAttributes::Vt220(
+                    DeviceAttributeFlags::from_params(&params[2..]),
+                )),
+            )),
+            [_, CsiParam::Integer(63), ..] => Ok(self.advance_by(
+                params.len(),
+                params,
+                Device::DeviceAttributes(DeviceAttributes::Vt320(
+                    DeviceAttributeFlags::from_params(&params[2..]),
+                )),
+            )),
+            [_, CsiParam::Integer(64), ..] => Ok(self.advance_by(
+                params.len(),
+                params,
+                Device::DeviceAttributes(DeviceAttributes::Vt420(
+                    DeviceAttributeFlags::from_params(&params[2..]),
+                )),
+            )),
+            [_, CsiParam::Integer(1), CsiParam::P(b';'), CsiParam::Integer(value)]
+            | [_, CsiParam::Integer(value), ..] => {
+                Err(CsiParseError::OutOfRangeParameter { value: *value })
+            }
+            _ => Err(wrong_parameter_count(params)),
+        }
+    }
+
+    fn req_terminal_parameters(&mut self, params: &'a [CsiParam]) -> Result<Device, CsiParseError> {
+        match params {
+            [] | [CsiParam::Integer(0)] => Ok(Device::RequestTerminalParameters(0)),
+            [CsiParam::Integer(1)] => Ok(Device::RequestTerminalParameters(1)),
+            [CsiParam::Integer(value)] => Err(CsiParseError::OutOfRangeParameter { value: *value }),
+            _ => Err(wrong_parameter_count(params)),
+        }
+    }
+
+    /// Parse extended mouse reports known as SGR 1006 mode
+    fn mouse_sgr1006(&mut self, params: &'a [CsiParam]) -> Result<MouseReport, CsiParseError> {
+        let (p0, p1, p2) = match params {
+            [CsiParam::P(b'<'), CsiParam::Integer(p0), CsiParam::P(b';'), CsiParam::Integer(p1), CsiParam::P(b';'), CsiParam::Integer(p2)] => {
+                (*p0, *p1, *p2)
+            }
+            _ => return Err(wrong_parameter_count(params)),
+        };
+
+        // 'M' encodes a press, 'm' a release.
+        let button = match (self.control, p0 & 0b110_0011) {
+            ('M', 0) => MouseButton::Button1Press,
+            ('m', 0) => MouseButton::Button1Release,
+            ('M', 1) => MouseButton::Button2Press,
+            ('m', 1) => MouseButton::Button2Release,
+            ('M', 2) => MouseButton::Button3Press,
+            ('m', 2) => MouseButton::Button3Release,
+            ('M', 64) => MouseButton::Button4Press,
+            ('m', 64) => MouseButton::Button4Release,
+            ('M', 65) => MouseButton::Button5Press,
+            ('m', 65) => MouseButton::Button5Release,
+            ('M', 32) => MouseButton::Button1Drag,
+            ('M', 33) => MouseButton::Button2Drag,
+            ('M', 34) => MouseButton::Button3Drag,
+            // Note that there is some theoretical ambiguity with these None values.
+            // The ambiguity stems from alternative encodings of the mouse protocol;
+            // when set to SGR1006 mode the variants with the `3` parameter do not
+            // occur.  They included here as a reminder for when support for those
+            // other encodings is added and this block is likely copied and pasted
+            // or refactored for re-use with them.
+            ('M', 35) => MouseButton::None, // mouse motion with no buttons
+            ('M', 3) => MouseButton::None,  // legacy notification about button release
+            ('m', 3) => MouseButton::None,  // release+press doesn't make sense
+            _ => {
+                return Err(CsiParseError::OutOfRangeParameter { value: p0 });
+            }
+        };
+
+        let mut modifiers = Modifiers::NONE;
+        if p0 & 4 != 0 {
+            modifiers |= Modifiers::SHIFT;
+        }
+        if p0 & 8 != 0 {
+            modifiers |= Modifiers::ALT;
+        }
+        if p0 & 16 != 0 {
+            modifiers |= Modifiers::CTRL;
+        }
+
+        Ok(self.advance_by(
+            6,
+            params,
+            MouseReport::SGR1006 {
+                x: p1 as u16,
+                y: p2 as u16,
+                button,
+                modifiers,
+            },
+        ))
+    }
+
+    fn decrqm(&mut self, params: &'a [CsiParam]) -> Result<CSI, CsiParseError> {
+        Ok(CSI::Mode(match params {
+            [CsiParam::Integer(p), CsiParam::P(b'$')] => {
+                Mode::QueryMode(match FromPrimitive::from_i64(*p) {
+                    None => TerminalMode::Unspecified(narrow(*p)?),
+                    Some(mode) => TerminalMode::Code(mode),
+                })
+            }
+            [CsiParam::P(b'?'), CsiParam::Integer(p), CsiParam::P(b'$')] => {
+                Mode::QueryDecPrivateMode(match FromPrimitive::from_i64(*p) {
+                    None => DecPrivateMode::Unspecified(narrow(*p)?),
+                    Some(mode) => DecPrivateMode::Code(mode),
+                })
+            }
+            _ => return Err(self.unknown_control()),
+        }))
+    }
+
+    fn dec(&mut self, params: &'a [CsiParam]) -> Result<DecPrivateMode, CsiParseError> {
+        match params {
+            [CsiParam::Integer(p0), ..] => match FromPrimitive::from_i64(*p0) {
+                None => Ok(self.advance_by(1, params, DecPrivateMode::Unspecified(narrow(*p0)?))),
+                Some(mode) => Ok(self.advance_by(1, params, DecPrivateMode::Code(mode))),
+            },
+            [CsiParam::P(b':'), ..] => Err(CsiParseError::UnexpectedParameter(params[0].clone())),
+            _ => Err(CsiParseError::MissingParameter { index: 0 }),
+        }
+    }
+
+    fn terminal_mode(&mut self, params: &'a [CsiParam]) -> Result<TerminalMode, CsiParseError> {
+        let p0 = params
+            .get(0)
+            .and_then(CsiParam::as_integer)
+            .ok_or(CsiParseError::MissingParameter { index: 0 })?;
+        match FromPrimitive::from_i64(p0) {
+            None => Ok(self.advance_by(1, params, TerminalMode::Unspecified(narrow(p0)?))),
+            Some(mode) => Ok(self.advance_by(1, params, TerminalMode::Code(mode))),
+        }
+    }
+
+    fn parse_sgr_color(&mut self, params: &'a [CsiParam]) -> Result<ColorSpec, CsiParseError> {
+        match params {
+            [_, CsiParam::P(b':'), CsiParam::Integer(2), CsiParam::P(b':'),
+             CsiParam::Integer(_colorspace), CsiParam::P(b':'),
+             red, CsiParam::P(b':'), green, CsiParam::P(b':'), blue, ..] => {
+                let res = RgbColor::new_8bpc(to_u8(red)?, to_u8(green)?, to_u8(blue)?).into();
+                Ok(self.advance_by(11, params, res))
+            }
+
+            [_, CsiParam::P(b':'), CsiParam::Integer(2), CsiParam::P(b':'), /* empty colorspace */ CsiParam::P(b':'), red, CsiParam::P(b':'), green, CsiParam::P(b':'), blue, ..] => {
+                let res = RgbColor::new_8bpc(to_u8(red)?, to_u8(green)?, to_u8(blue)?).into();
+                Ok(self.advance_by(10, params, res))
+            }
+
+            [_, CsiParam::P(b';'), CsiParam::Integer(2), CsiParam::P(b';'), red, CsiParam::P(b';'), green, CsiParam::P(b';'), blue, ..] |
+            [_, CsiParam::P(b':'), CsiParam::Integer(2), CsiParam::P(b':'), red, CsiParam::P(b':'), green, CsiParam::P(b':'), blue, ..] =>
+            {
+                let res = RgbColor::new_8bpc(to_u8(red)?, to_u8(green)?, to_u8(blue)?).into();
+                Ok(self.advance_by(9, params, res))
+            }
+
+            [_, CsiParam::P(b';'), CsiParam::Integer(5), CsiParam::P(b';'), idx, ..] |
+            [_, CsiParam::P(b':'), CsiParam::Integer(5), CsiParam::P(b':'), idx, ..] => {
+                Ok(self.advance_by(5, params, ColorSpec::PaletteIndex(to_u8(idx)?)))
+            }
+            // The color space is neither 2 (true color) nor 5 (palette)
+            [_, CsiParam::P(_), CsiParam::Integer(value), ..] if *value != 2 && *value != 5 => {
+                Err(CsiParseError::OutOfRangeParameter { value: *value })
+            }
+            _ => Err(wrong_parameter_count(params)),
+        }
+    }
+
+    fn window(&mut self, params: &'a [CsiParam]) -> Result<Window, CsiParseError> {
+        let params = Cracked::parse(params)?;
+
+        let p = params.int(0)?;
+        let arg1 = params.opt_int(1);
+        let arg2 = params.opt_int(2);
+        // The error for an unsupported first argument
+        let bad_arg1 = || match arg1 {
+            Some(value) => CsiParseError::OutOfRangeParameter { value },
+            None => CsiParseError::MissingParameter { index: 1 },
+        };
+
+        match p {
+            1 => Ok(Window::DeIconify),
+            2 => Ok(Window::Iconify),
+            3 => Ok(Window::MoveWindow {
+                x: arg1.unwrap_or(0),
+                y: arg2.unwrap_or(0),
+            }),
+            4 => Ok(Window::ResizeWindowPixels {
+                height: arg1,
+                width: arg2,
+            }),
+            5 => Ok(Window::RaiseWindow),
+            6 => match params.len() {
+                1 => Ok(Window::LowerWindow),
+                _ => Ok(Window::ReportCellSizePixelsResponse {
+                    height: arg1,
+                    width: arg2,
+                }),
+            },
+            7 => Ok(Window::RefreshWindow),
+            8 => Ok(Window::ResizeWindowCells {
+                height: arg1,
+                width: arg2,
+            }),
+            9 => match arg1 {
+                Some(0) => Ok(Window::RestoreMaximizedWindow),
+                Some(1) => Ok(Window::MaximizeWindow),
+                Some(2) => Ok(Window::MaximizeWindowVertically),
+                Some(3) => Ok(Window::MaximizeWindowHorizontally),
+                _ => Err(bad_arg1()),
+            },
+            10 => match arg1 {
+                Some(0) => Ok(Window::UndoFullScreenMode),
+                Some(1) => Ok(Window::ChangeToFullScreenMode),
+                Some(2) => Ok(Window::ToggleFullScreen),
+                _ => Err(bad_arg1()),
+            },
+            11 => Ok(Window::ReportWindowState),
+            13 => match arg1 {
+                None => Ok(Window::ReportWindowPosition),
+                Some(2) => Ok(Window::ReportTextAreaPosition),
+                _ => Err(bad_arg1()),
+            },
+            14 => match arg1 {
+                None => Ok(Window::ReportTextAreaSizePixels),
+                Some(2) => Ok(Window::ReportWindowSizePixels),
+                _ => Err(bad_arg1()),
+            },
+            15 => Ok(Window::ReportScreenSizePixels),
+            16 => Ok(Window::ReportCellSizePixels),
+            18 => Ok(Window::ReportTextAreaSizeCells),
+            19 => Ok(Window::ReportScreenSizeCells),
+            20 => Ok(Window::ReportIconLabel),
+            21 => Ok(Window::ReportWindowTitle),
+            22 => match arg1 {
+                Some(0) => Ok(Window::PushIconAndWindowTitle),
+                Some(1) => Ok(Window::PushIconTitle),
+                Some(2) => Ok(Window::PushWindowTitle),
+                _ => Err(bad_arg1()),
+            },
+            23 => match arg1 {
+                Some(0) => Ok(Window::PopIconAndWindowTitle),
+                Some(1) => Ok(Window::PopIconTitle),
+                Some(2) => Ok(Window::PopWindowTitle),
+                _ => Err(bad_arg1()),
+            },
+            value => Err(CsiParseError::OutOfRangeParameter { value }),
+        }
+    }
+
+    /// Called when `sgr` is unable to interpret the code at the start
+    /// of params.  If that code has colon-delimited subparameters, such
+    /// as `38:9:1` or `1:2`, then the code and all of its subparameters
+    /// are skipped over, to be returned as a single `CSI::Unspecified`,
+    /// and parsing resumes with the next code, rather than splitting
+    /// the group across several values.
+    fn skip_sgr_subparameters(&mut self, params: &'a [CsiParam]) {
+        let mut n = match params {
+            [CsiParam::Integer(_), CsiParam::P(b':'), ..] => 1,
+            _ => return,
+        };
+        while let Some(CsiParam::P(b':')) = params.get(n) {
+            n += 1;
+            if let Some(CsiParam::Integer(_)) = params.get(n) {
+                n += 1;
+            }
+        }
+        self.skipped = Some(&params[..n]);
+        self.advance_by(n, params, ());
+    }
+
+    fn underline(&mut self, params: &'a [CsiParam]) -> Result<Sgr, CsiParseError> {
+        let (sgr, n) = match params {
+            [_, CsiParam::P(b':'), CsiParam::Integer(0), ..] => {
+                (Sgr::Underline(Underline::None), 3)
+            }
+            [_, CsiParam::P(b':'), CsiParam::Integer(1), ..] => {
+                (Sgr::Underline(Underline::Single), 3)
+            }
+            [_, CsiParam::P(b':'), CsiParam::Integer(2), ..] => {
+                (Sgr::Underline(Underline::Double), 3)
+            }
+            [_, CsiParam::P(b':'), CsiParam::Integer(3), ..] => {
+                (Sgr::Underline(Underline::Curly), 3)
+            }
+            [_, CsiParam::P(b':'), CsiParam::Integer(4), ..] => {
+                (Sgr::Underline(Underline::Dotted), 3)
+            }
+            [_, CsiParam::P(b':'), CsiParam::Integer(5), ..] => {
+                (Sgr::Underline(Underline::Dashed), 3)
+            }
+            [_, CsiParam::P(b':'), CsiParam::Integer(style), ..] => {
+                let style = narrow(*style)?;
+                (Sgr::UnknownUnderline(style), 3)
+            }
+            _ => (Sgr::Underline(Underline::Single), 1),
+        };
+
+        Ok(self.advance_by(n, params, sgr))
+    }
+
+    fn sgr(&mut self, params: &'a [CsiParam]) -> Result<Sgr, CsiParseError> {
+        if params.is_empty() {
+            // With no parameters, treat as equivalent to Reset.
+            Ok(Sgr::Reset)
+        } else {
+            for p in params {
+                match p {
+                    CsiParam::P(b';') | CsiParam::P(b':') | CsiParam::Integer(_) => {}
+                    _ => return Err(CsiParseError::UnexpectedParameter(p.clone())),
+                }
+            }
+
+            // Consume a single parameter and return the parsed result.
+            // Codes that don't accept subparameters are rejected if they
+            // have any, so that the whole group is left to
+            // sgr_subparameters.
+            macro_rules! one {
+                ($t:expr) => {
+                    match params.get(1) {
+                        Some(p @ CsiParam::P(b':')) => {
+                            Err(CsiParseError::UnexpectedParameter(p.clone()))
+                        }
+                        _ => Ok(self.advance_by(1, params, $t)),
+                    }
+                };
+            }
 
             match &params[0] {
                 CsiParam::P(b';') => {
                     // Starting with an empty item is equivalent to a reset
                     self.advance_by(1, params, Ok(Sgr::Reset))
                 }
-                CsiParam::P(_) => Err(()),
+                p @ CsiParam::P(_) => Err(CsiParseError::UnexpectedParameter(p.clone())),
                 CsiParam::Integer(i) => match FromPrimitive::from_i64(*i) {
-                    None => Err(()),
+                    None => match params.get(1) {
+                        // Skip over the unknown code so that we can
+                        // continue to interpret any subsequent codes,
+                        // but only if it doesn't have subparameters
+                        // that we wouldn't know how to skip.
+                        None | Some(CsiParam::P(b';')) => one!(Sgr::Unknown(*i)),
+                        _ => Err(CsiParseError::UnknownSgrCode(*i)),
+                    },
                     Some(sgr) => match sgr {
                         SgrCode::Reset => one!(Sgr::Reset),
                         SgrCode::IntensityBold => one!(Sgr::Intensity(Intensity::Bold)),
@@ -2223,6 +3943,7 @@ impl<'a> CSIParser<'a> {
                         SgrCode::BlinkOff => one!(Sgr::Blink(Blink::None)),
                         SgrCode::ItalicOn => one!(Sgr::Italic(true)),
                         SgrCode::ItalicOff => one!(Sgr::Italic(false)),
+                        SgrCode::Fraktur => one!(Sgr::Fraktur(true)),
                         SgrCode::ForegroundColor => {
                             self.parse_sgr_color(params).map(Sgr::Foreground)
                         }
@@ -2262,386 +3983,2495 @@ impl<'a> CSIParser<'a> {
                             one!(Sgr::Foreground(AnsiColor::White.into()))
                         }
 
-                        SgrCode::BackgroundColor => {
-                            self.parse_sgr_color(params).map(Sgr::Background)
-                        }
-                        SgrCode::BackgroundBlack => one!(Sgr::Background(AnsiColor::Black.into())),
-                        SgrCode::BackgroundRed => one!(Sgr::Background(AnsiColor::Maroon.into())),
-                        SgrCode::BackgroundGreen => one!(Sgr::Background(AnsiColor::Green.into())),
-                        SgrCode::BackgroundYellow => one!(Sgr::Background(AnsiColor::Olive.into())),
-                        SgrCode::BackgroundBlue => one!(Sgr::Background(AnsiColor::Navy.into())),
-                        SgrCode::BackgroundMagenta => {
-                            one!(Sgr::Background(AnsiColor::Purple.into()))
-                        }
-                        SgrCode::BackgroundCyan => one!(Sgr::Background(AnsiColor::Teal.into())),
-                        SgrCode::BackgroundWhite => one!(Sgr::Background(AnsiColor::Silver.into())),
-                        SgrCode::BackgroundDefault => one!(Sgr::Background(ColorSpec::Default)),
-                        SgrCode::BackgroundBrightBlack => {
-                            one!(Sgr::Background(AnsiColor::Grey.into()))
-                        }
-                        SgrCode::BackgroundBrightRed => {
-                            one!(Sgr::Background(AnsiColor::Red.into()))
-                        }
-                        SgrCode::BackgroundBrightGreen => {
-                            one!(Sgr::Background(AnsiColor::Lime.into()))
-                        }
-                        SgrCode::BackgroundBrightYellow => {
-                            one!(Sgr::Background(AnsiColor::Yellow.into()))
-                        }
-                        SgrCode::BackgroundBrightBlue => {
-                            one!(Sgr::Background(AnsiColor::Blue.into()))
-                        }
-                        SgrCode::BackgroundBrightMagenta => {
-                            one!(Sgr::Background(AnsiColor::Fuchsia.into()))
-                        }
-                        SgrCode::BackgroundBrightCyan => {
-                            one!(Sgr::Background(AnsiColor::Aqua.into()))
-                        }
-                        SgrCode::BackgroundBrightWhite => {
-                            one!(Sgr::Background(AnsiColor::White.into()))
-                        }
+                        SgrCode::BackgroundColor => {
+                            self.parse_sgr_color(params).map(Sgr::Background)
+                        }
+                        SgrCode::BackgroundBlack => one!(Sgr::Background(AnsiColor::Black.into())),
+                        SgrCode::BackgroundRed => one!(Sgr::Background(AnsiColor::Maroon.into())),
+                        SgrCode::BackgroundGreen => one!(Sgr::Background(AnsiColor::Green.into())),
+                        SgrCode::BackgroundYellow => one!(Sgr::Background(AnsiColor::Olive.into())),
+                        SgrCode::BackgroundBlue => one!(Sgr::Background(AnsiColor::Navy.into())),
+                        SgrCode::BackgroundMagenta => {
+                            one!(Sgr::Background(AnsiColor::Purple.into()))
+                        }
+                        SgrCode::BackgroundCyan => one!(Sgr::Background(AnsiColor::Teal.into())),
+                        SgrCode::BackgroundWhite => one!(Sgr::Background(AnsiColor::Silver.into())),
+                        SgrCode::BackgroundDefault => one!(Sgr::Background(ColorSpec::Default)),
+                        SgrCode::BackgroundBrightBlack => {
+                            one!(Sgr::Background(AnsiColor::Grey.into()))
+                        }
+                        SgrCode::BackgroundBrightRed => {
+                            one!(Sgr::Background(AnsiColor::Red.into()))
+                        }
+                        SgrCode::BackgroundBrightGreen => {
+                            one!(Sgr::Background(AnsiColor::Lime.into()))
+                        }
+                        SgrCode::BackgroundBrightYellow => {
+                            one!(Sgr::Background(AnsiColor::Yellow.into()))
+                        }
+                        SgrCode::BackgroundBrightBlue => {
+                            one!(Sgr::Background(AnsiColor::Blue.into()))
+                        }
+                        SgrCode::BackgroundBrightMagenta => {
+                            one!(Sgr::Background(AnsiColor::Fuchsia.into()))
+                        }
+                        SgrCode::BackgroundBrightCyan => {
+                            one!(Sgr::Background(AnsiColor::Aqua.into()))
+                        }
+                        SgrCode::BackgroundBrightWhite => {
+                            one!(Sgr::Background(AnsiColor::White.into()))
+                        }
+
+                        SgrCode::InverseOn => one!(Sgr::Inverse(true)),
+                        SgrCode::InverseOff => one!(Sgr::Inverse(false)),
+                        SgrCode::InvisibleOn => one!(Sgr::Invisible(true)),
+                        SgrCode::InvisibleOff => one!(Sgr::Invisible(false)),
+                        SgrCode::StrikeThroughOn => one!(Sgr::StrikeThrough(true)),
+                        SgrCode::StrikeThroughOff => one!(Sgr::StrikeThrough(false)),
+                        SgrCode::OverlineOn => one!(Sgr::Overline(true)),
+                        SgrCode::OverlineOff => one!(Sgr::Overline(false)),
+                        SgrCode::DefaultFont => one!(Sgr::Font(Font::Default)),
+                        SgrCode::AltFont1 => one!(Sgr::Font(Font::Alternate(1))),
+                        SgrCode::AltFont2 => one!(Sgr::Font(Font::Alternate(2))),
+                        SgrCode::AltFont3 => one!(Sgr::Font(Font::Alternate(3))),
+                        SgrCode::AltFont4 => one!(Sgr::Font(Font::Alternate(4))),
+                        SgrCode::AltFont5 => one!(Sgr::Font(Font::Alternate(5))),
+                        SgrCode::AltFont6 => one!(Sgr::Font(Font::Alternate(6))),
+                        SgrCode::AltFont7 => one!(Sgr::Font(Font::Alternate(7))),
+                        SgrCode::AltFont8 => one!(Sgr::Font(Font::Alternate(8))),
+                        SgrCode::AltFont9 => one!(Sgr::Font(Font::Alternate(9))),
+                    },
+                },
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, FromPrimitive)]
+pub enum SgrCode {
+    Reset = 0,
+    IntensityBold = 1,
+    IntensityDim = 2,
+    ItalicOn = 3,
+    UnderlineOn = 4,
+    /// Blinks < 150 times per minute
+    BlinkOn = 5,
+    /// Blinks > 150 times per minute
+    RapidBlinkOn = 6,
+    InverseOn = 7,
+    InvisibleOn = 8,
+    StrikeThroughOn = 9,
+    DefaultFont = 10,
+    AltFont1 = 11,
+    AltFont2 = 12,
+    AltFont3 = 13,
+    AltFont4 = 14,
+    AltFont5 = 15,
+    AltFont6 = 16,
+    AltFont7 = 17,
+    AltFont8 = 18,
+    AltFont9 = 19,
+    Fraktur = 20,
+    UnderlineDouble = 21,
+    NormalIntensity = 22,
+    ItalicOff = 23,
+    UnderlineOff = 24,
+    BlinkOff = 25,
+    InverseOff = 27,
+    InvisibleOff = 28,
+    StrikeThroughOff = 29,
+    ForegroundBlack = 30,
+    ForegroundRed = 31,
+    ForegroundGreen = 32,
+    ForegroundYellow = 33,
+    ForegroundBlue = 34,
+    ForegroundMagenta = 35,
+    ForegroundCyan = 36,
+    ForegroundWhite = 37,
+    ForegroundDefault = 39,
+    BackgroundBlack = 40,
+    BackgroundRed = 41,
+    BackgroundGreen = 42,
+    BackgroundYellow = 43,
+    BackgroundBlue = 44,
+    BackgroundMagenta = 45,
+    BackgroundCyan = 46,
+    BackgroundWhite = 47,
+    BackgroundDefault = 49,
+    OverlineOn = 53,
+    OverlineOff = 55,
+
+    UnderlineColor = 58,
+    ResetUnderlineColor = 59,
+
+    ForegroundBrightBlack = 90,
+    ForegroundBrightRed = 91,
+    ForegroundBrightGreen = 92,
+    ForegroundBrightYellow = 93,
+    ForegroundBrightBlue = 94,
+    ForegroundBrightMagenta = 95,
+    ForegroundBrightCyan = 96,
+    ForegroundBrightWhite = 97,
+
+    BackgroundBrightBlack = 100,
+    BackgroundBrightRed = 101,
+    BackgroundBrightGreen = 102,
+    BackgroundBrightYellow = 103,
+    BackgroundBrightBlue = 104,
+    BackgroundBrightMagenta = 105,
+    BackgroundBrightCyan = 106,
+    BackgroundBrightWhite = 107,
+
+    /// Maybe followed either either a 256 color palette index or
+    /// a sequence describing a true color rgb value
+    ForegroundColor = 38,
+    BackgroundColor = 48,
+}
+
+impl SgrCode {
+    /// Returns a short, human readable description of the code,
+    /// suitable for use in help output and debugging tools.
+    /// The descriptions are considered to be stable.
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Self::Reset => "reset",
+            Self::IntensityBold => "bold",
+            Self::IntensityDim => "dim",
+            Self::ItalicOn => "italic",
+            Self::UnderlineOn => "underline",
+            Self::BlinkOn => "slow blink",
+            Self::RapidBlinkOn => "rapid blink",
+            Self::InverseOn => "inverse",
+            Self::InvisibleOn => "invisible",
+            Self::StrikeThroughOn => "strikethrough",
+            Self::DefaultFont => "default font",
+            Self::AltFont1 => "alternate font 1",
+            Self::AltFont2 => "alternate font 2",
+            Self::AltFont3 => "alternate font 3",
+            Self::AltFont4 => "alternate font 4",
+            Self::AltFont5 => "alternate font 5",
+            Self::AltFont6 => "alternate font 6",
+            Self::AltFont7 => "alternate font 7",
+            Self::AltFont8 => "alternate font 8",
+            Self::AltFont9 => "alternate font 9",
+            Self::Fraktur => "fraktur",
+            Self::UnderlineDouble => "double underline",
+            Self::NormalIntensity => "normal intensity",
+            Self::ItalicOff => "italic off",
+            Self::UnderlineOff => "underline off",
+            Self::BlinkOff => "blink off",
+            Self::InverseOff => "inverse off",
+            Self::InvisibleOff => "invisible off",
+            Self::StrikeThroughOff => "strikethrough off",
+            Self::ForegroundBlack => "black foreground",
+            Self::ForegroundRed => "red foreground",
+            Self::ForegroundGreen => "green foreground",
+            Self::ForegroundYellow => "yellow foreground",
+            Self::ForegroundBlue => "blue foreground",
+            Self::ForegroundMagenta => "magenta foreground",
+            Self::ForegroundCyan => "cyan foreground",
+            Self::ForegroundWhite => "white foreground",
+            Self::ForegroundDefault => "default foreground",
+            Self::BackgroundBlack => "black background",
+            Self::BackgroundRed => "red background",
+            Self::BackgroundGreen => "green background",
+            Self::BackgroundYellow => "yellow background",
+            Self::BackgroundBlue => "blue background",
+            Self::BackgroundMagenta => "magenta background",
+            Self::BackgroundCyan => "cyan background",
+            Self::BackgroundWhite => "white background",
+            Self::BackgroundDefault => "default background",
+            Self::OverlineOn => "overline",
+            Self::OverlineOff => "overline off",
+            Self::UnderlineColor => "underline color",
+            Self::ResetUnderlineColor => "default underline color",
+            Self::ForegroundBrightBlack => "bright black foreground",
+            Self::ForegroundBrightRed => "bright red foreground",
+            Self::ForegroundBrightGreen => "bright green foreground",
+            Self::ForegroundBrightYellow => "bright yellow foreground",
+            Self::ForegroundBrightBlue => "bright blue foreground",
+            Self::ForegroundBrightMagenta => "bright magenta foreground",
+            Self::ForegroundBrightCyan => "bright cyan foreground",
+            Self::ForegroundBrightWhite => "bright white foreground",
+            Self::BackgroundBrightBlack => "bright black background",
+            Self::BackgroundBrightRed => "bright red background",
+            Self::BackgroundBrightGreen => "bright green background",
+            Self::BackgroundBrightYellow => "bright yellow background",
+            Self::BackgroundBrightBlue => "bright blue background",
+            Self::BackgroundBrightMagenta => "bright magenta background",
+            Self::BackgroundBrightCyan => "bright cyan background",
+            Self::BackgroundBrightWhite => "bright white background",
+            Self::ForegroundColor => "extended foreground color",
+            Self::BackgroundColor => "extended background color",
+        }
+    }
+}
+
+impl Sgr {
+    /// Returns the SgrCode that introduces the encoded form of self,
+    /// using the default `SgrEncodeOptions`.
+    /// Returns `None` for `Sgr::Unknown` and for fonts that have no code.
+    fn code(&self) -> Option<SgrCode> {
+        let palette = |idx: u8, base: SgrCode, bright: SgrCode, extended: SgrCode| match idx {
+            0..=7 => FromPrimitive::from_u8(base as u8 + idx),
+            8..=15 => FromPrimitive::from_u8(bright as u8 + idx - 8),
+            _ => Some(extended),
+        };
+
+        Some(match self {
+            Sgr::Reset => SgrCode::Reset,
+            Sgr::Intensity(Intensity::Bold) => SgrCode::IntensityBold,
+            Sgr::Intensity(Intensity::Half) => SgrCode::IntensityDim,
+            Sgr::Intensity(Intensity::Normal) => SgrCode::NormalIntensity,
+            Sgr::Underline(Underline::None) => SgrCode::UnderlineOff,
+            Sgr::Underline(Underline::Double) => SgrCode::UnderlineDouble,
+            Sgr::Underline(Underline::Single)
+            | Sgr::Underline(Underline::Curly)
+            | Sgr::Underline(Underline::Dotted)
+            | Sgr::Underline(Underline::Dashed)
+            | Sgr::UnknownUnderline(_) => SgrCode::UnderlineOn,
+            Sgr::Blink(Blink::Slow) => SgrCode::BlinkOn,
+            Sgr::Blink(Blink::Rapid) => SgrCode::RapidBlinkOn,
+            Sgr::Blink(Blink::None) => SgrCode::BlinkOff,
+            Sgr::Italic(true) => SgrCode::ItalicOn,
+            Sgr::Italic(false) | Sgr::Fraktur(false) => SgrCode::ItalicOff,
+            Sgr::Fraktur(true) => SgrCode::Fraktur,
+            Sgr::Inverse(true) => SgrCode::InverseOn,
+            Sgr::Inverse(false) => SgrCode::InverseOff,
+            Sgr::Invisible(true) => SgrCode::InvisibleOn,
+            Sgr::Invisible(false) => SgrCode::InvisibleOff,
+            Sgr::StrikeThrough(true) => SgrCode::StrikeThroughOn,
+            Sgr::StrikeThrough(false) => SgrCode::StrikeThroughOff,
+            Sgr::Overline(true) => SgrCode::OverlineOn,
+            Sgr::Overline(false) => SgrCode::OverlineOff,
+            Sgr::Font(Font::Default) => SgrCode::DefaultFont,
+            Sgr::Font(Font::Alternate(1)) => SgrCode::AltFont1,
+            Sgr::Font(Font::Alternate(2)) => SgrCode::AltFont2,
+            Sgr::Font(Font::Alternate(3)) => SgrCode::AltFont3,
+            Sgr::Font(Font::Alternate(4)) => SgrCode::AltFont4,
+            Sgr::Font(Font::Alternate(5)) => SgrCode::AltFont5,
+            Sgr::Font(Font::Alternate(6)) => SgrCode::AltFont6,
+            Sgr::Font(Font::Alternate(7)) => SgrCode::AltFont7,
+            Sgr::Font(Font::Alternate(8)) => SgrCode::AltFont8,
+            Sgr::Font(Font::Alternate(9)) => SgrCode::AltFont9,
+            Sgr::Font(Font::Alternate(_)) => return None,
+            Sgr::Foreground(ColorSpec::Default) => SgrCode::ForegroundDefault,
+            Sgr::Foreground(ColorSpec::TrueColor(_)) => SgrCode::ForegroundColor,
+            Sgr::Foreground(ColorSpec::PaletteIndex(idx)) => {
+                return palette(
+                    *idx,
+                    SgrCode::ForegroundBlack,
+                    SgrCode::ForegroundBrightBlack,
+                    SgrCode::ForegroundColor,
+                )
+            }
+            Sgr::Background(ColorSpec::Default) => SgrCode::BackgroundDefault,
+            Sgr::Background(ColorSpec::TrueColor(_)) => SgrCode::BackgroundColor,
+            Sgr::Background(ColorSpec::PaletteIndex(idx)) => {
+                return palette(
+                    *idx,
+                    SgrCode::BackgroundBlack,
+                    SgrCode::BackgroundBrightBlack,
+                    SgrCode::BackgroundColor,
+                )
+            }
+            Sgr::UnderlineColor(ColorSpec::Default) => SgrCode::ResetUnderlineColor,
+            Sgr::UnderlineColor(_) => SgrCode::UnderlineColor,
+            Sgr::Unknown(_) => return None,
+        })
+    }
+}
+
+/// Returns the set of numeric SGR codes used by the `Sgr` values
+/// in `seq`, such as `1` for bold.
+/// Colors that are encoded as a subsequence, such as `38:5:160`,
+/// are reported using the lead code, `38` in that example.
+/// Unknown codes are included, which is useful when surveying the
+/// attributes that a program uses.
+pub fn sgr_codes_used(seq: &[CSI]) -> std::collections::BTreeSet<i64> {
+    seq.iter()
+        .filter_map(|csi| match csi {
+            CSI::Sgr(Sgr::Unknown(code)) => Some(*code),
+            CSI::Sgr(sgr) => sgr.code().map(|code| code as i64),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Produces a debugging view of a sequence of CSI values.
+/// Returns a `(escape, description)` pair for each element of `seq`,
+/// where `escape` is the encoded form of the element with control
+/// characters rendered as `\xNN`, and `description` is a short
+/// human readable description of it.
+pub fn annotate(seq: &[CSI]) -> Vec<(String, String)> {
+    seq.iter()
+        .map(|csi| {
+            let escape = csi
+                .to_string()
+                .chars()
+                .map(|c| {
+                    if c.is_control() {
+                        format!("\\x{:02x}", c as u32)
+                    } else {
+                        c.to_string()
+                    }
+                })
+                .collect();
+            let description = match csi {
+                CSI::Sgr(sgr) => match sgr.code() {
+                    Some(code) => code.describe().to_string(),
+                    None => format!("{:?}", sgr),
+                },
+                _ => format!("{:?}", csi),
+            };
+            (escape, description)
+        })
+        .collect()
+}
+
+impl<'a> CSIParser<'a> {
+    /// Parses the next subsequence.  If it couldn't be interpreted then
+    /// the reason is returned along with the `Unspecified` value that
+    /// holds the parameters that were skipped over.
+    fn next_result(&mut self) -> Option<Result<CSI, (CsiParseError, Box<Unspecified>)>> {
+        let params = self.params.take()?;
+
+        Some(self.parse_next(params).map_err(|err| {
+            let unspec = Box::new(Unspecified {
+                params: self.skipped.take().unwrap_or(params).to_vec(),
+                parameters_truncated: self.parameters_truncated,
+                control: self.control,
+                payload: String::new(),
+            });
+            (err, unspec)
+        }))
+    }
+}
+
+impl<'a> Iterator for CSIParser<'a> {
+    type Item = CSI;
+
+    fn next(&mut self) -> Option<CSI> {
+        self.next_result()
+            .map(|res| res.unwrap_or_else(|(_, unspec)| CSI::Unspecified(unspec)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    fn parse(control: char, params: &[i64], expected: &str) -> Vec<CSI> {
+        parse_params(control, join_params(params), expected)
+    }
+
+    /// Like `parse`, but with `intermediate` following the parameters
+    fn parse_intermediate(
+        control: char,
+        intermediate: u8,
+        params: &[i64],
+        expected: &str,
+    ) -> Vec<CSI> {
+        let mut cparams = join_params(params);
+        cparams.push(CsiParam::P(intermediate));
+        parse_params(control, cparams, expected)
+    }
+
+    fn join_params(params: &[i64]) -> Vec<CsiParam> {
+        let mut cparams = vec![];
+        for &p in params {
+            if !cparams.is_empty() {
+                cparams.push(CsiParam::P(b';'));
+            }
+            cparams.push(CsiParam::Integer(p));
+        }
+        cparams
+    }
+
+    fn parse_params(control: char, cparams: Vec<CsiParam>, expected: &str) -> Vec<CSI> {
+        let res = CSI::parse(&cparams, false, control).collect();
+        println!("parsed -> {:#?}", res);
+        assert_eq!(encode(&res), expected);
+        res
+    }
+
+    fn encode(seq: &Vec<CSI>) -> String {
+        let mut res = Vec::new();
+        for s in seq {
+            write!(res, "{}", s).unwrap();
+        }
+        String::from_utf8(res).unwrap()
+    }
+
+    #[test]
+    fn basic() {
+        assert_eq!(parse('m', &[], "\x1b[0m"), vec![CSI::Sgr(Sgr::Reset)]);
+        assert_eq!(parse('m', &[0], "\x1b[0m"), vec![CSI::Sgr(Sgr::Reset)]);
+        assert_eq!(
+            parse('m', &[1], "\x1b[1m"),
+            vec![CSI::Sgr(Sgr::Intensity(Intensity::Bold))]
+        );
+        assert_eq!(
+            parse('m', &[1, 3], "\x1b[1m\x1b[3m"),
+            vec![
+                CSI::Sgr(Sgr::Intensity(Intensity::Bold)),
+                CSI::Sgr(Sgr::Italic(true)),
+            ]
+        );
+
+        // Verify that we propagate Unknown for codes
+        // that we don't recognize, and continue to parse
+        // the codes that follow.
+        assert_eq!(
+            parse('m', &[1, 3, 1231231], "\x1b[1m\x1b[3m\x1b[1231231m"),
+            vec![
+                CSI::Sgr(Sgr::Intensity(Intensity::Bold)),
+                CSI::Sgr(Sgr::Italic(true)),
+                CSI::Sgr(Sgr::Unknown(1231231)),
+            ]
+        );
+        assert_eq!(
+            parse('m', &[1, 1231231, 3], "\x1b[1m\x1b[1231231m\x1b[3m"),
+            vec![
+                CSI::Sgr(Sgr::Intensity(Intensity::Bold)),
+                CSI::Sgr(Sgr::Unknown(1231231)),
+                CSI::Sgr(Sgr::Italic(true)),
+            ]
+        );
+        assert_eq!(
+            parse('m', &[1231231, 3], "\x1b[1231231m\x1b[3m"),
+            vec![CSI::Sgr(Sgr::Unknown(1231231)), CSI::Sgr(Sgr::Italic(true)),]
+        );
+        assert_eq!(
+            parse('m', &[1, 9999, 4], "\x1b[1m\x1b[9999m\x1b[4m"),
+            vec![
+                CSI::Sgr(Sgr::Intensity(Intensity::Bold)),
+                CSI::Sgr(Sgr::Unknown(9999)),
+                CSI::Sgr(Sgr::Underline(Underline::Single)),
+            ]
+        );
+
+        // An unknown code with subparameters can't be skipped
+        let res: Vec<_> = CSI::parse(
+            &[
+                CsiParam::Integer(9999),
+                CsiParam::P(b':'),
+                CsiParam::Integer(2),
+            ],
+            false,
+            'm',
+        )
+        .collect();
+        assert_eq!(
+            res,
+            vec![CSI::Unspecified(Box::new(Unspecified {
+                params: [
+                    CsiParam::Integer(9999),
+                    CsiParam::P(b':'),
+                    CsiParam::Integer(2)
+                ]
+                .to_vec(),
+                parameters_truncated: false,
+                control: 'm',
+                payload: String::new(),
+            }))]
+        );
+    }
+
+    #[test]
+    fn bare_reset() {
+        assert_eq!(format!("\x1b[{}", Sgr::Reset), "\x1b[0m");
+        let options = SgrEncodeOptions {
+            omit_default_reset_param: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            format!("\x1b[{}", Sgr::Reset.with_options(options)),
+            "\x1b[m"
+        );
+        // Other codes are unaffected
+        assert_eq!(
+            format!("\x1b[{}", Sgr::Italic(true).with_options(options)),
+            "\x1b[3m"
+        );
+        // and the bare form parses back to a Reset
+        assert_eq!(
+            CSI::parse(&[], false, 'm').collect::<Vec<_>>(),
+            vec![CSI::Sgr(Sgr::Reset)]
+        );
+    }
+
+    #[test]
+    fn coalesce_conpty_resets() {
+        let mut seq = vec![];
+        for _ in 0..3 {
+            seq.append(&mut parse('m', &[0, 1, 31], "\x1b[0m\x1b[1m\x1b[31m"));
+        }
+        seq.append(&mut parse('m', &[0, 1, 32], "\x1b[0m\x1b[1m\x1b[32m"));
+        seq.append(&mut parse('m', &[0, 32], "\x1b[0m\x1b[32m"));
+        seq.append(&mut parse('m', &[0], "\x1b[0m"));
+
+        let coalesced = coalesce_sgr_resets(&seq);
+        assert_eq!(
+            encode(&coalesced),
+            "\x1b[0m\x1b[1m\x1b[31m\x1b[32m\x1b[22m\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn encoded_sizes() {
+        let seq = vec![
+            CSI::Sgr(Sgr::Intensity(Intensity::Bold)),
+            CSI::Sgr(Sgr::Foreground(AnsiColor::Maroon.into())),
+            CSI::Sgr(Sgr::Background(AnsiColor::Navy.into())),
+            CSI::Cursor(Cursor::Up(2)),
+            CSI::Sgr(Sgr::Reset),
+        ];
+        assert_eq!(encode(&seq), "\x1b[1m\x1b[31m\x1b[44m\x1b[2A\x1b[0m");
+        assert_eq!(encoded_size(&seq), encode(&seq).len());
+        assert_eq!(collapsed_size(&seq), "\x1b[1;31;44m\x1b[2A\x1b[0m".len());
+        assert!(collapsed_size(&seq) < encoded_size(&seq));
+
+        // Nothing to collapse
+        let seq = vec![CSI::Sgr(Sgr::Reset), CSI::Cursor(Cursor::Up(1))];
+        assert_eq!(collapsed_size(&seq), encoded_size(&seq));
+        assert_eq!(encoded_size(&[]), 0);
+        assert_eq!(collapsed_size(&[]), 0);
+    }
+
+    #[test]
+    fn alt_screen() {
+        let set = |code| CSI::Mode(Mode::SetDecPrivateMode(DecPrivateMode::Code(code)));
+        let reset = |code| CSI::Mode(Mode::ResetDecPrivateMode(DecPrivateMode::Code(code)));
+        let other = CSI::Cursor(Cursor::Up(1));
+
+        assert_eq!(enters_alt_screen(&[]), None);
+        assert_eq!(enters_alt_screen(&[other.clone()]), None);
+        assert_eq!(
+            enters_alt_screen(&[
+                set(DecPrivateModeCode::ClearAndEnableAlternateScreen),
+                other.clone()
+            ]),
+            Some(true)
+        );
+        assert_eq!(
+            enters_alt_screen(&[
+                set(DecPrivateModeCode::ClearAndEnableAlternateScreen),
+                other.clone(),
+                reset(DecPrivateModeCode::ClearAndEnableAlternateScreen),
+            ]),
+            Some(false)
+        );
+        assert_eq!(
+            enters_alt_screen(&[set(DecPrivateModeCode::OptEnableAlternateScreen)]),
+            Some(true)
+        );
+        assert_eq!(
+            enters_alt_screen(&[reset(DecPrivateModeCode::EnableAlternateScreen)]),
+            Some(false)
+        );
+        assert_eq!(
+            enters_alt_screen(&[set(DecPrivateModeCode::BracketedPaste)]),
+            None
+        );
+    }
+
+    #[test]
+    fn sgr_to_css() {
+        let palette = crate::color::xterm_256_palette();
+        let mut state = SgrState::new();
+        assert_eq!(super::sgr_to_css(&state, palette), "");
+
+        state.apply(&Sgr::Intensity(Intensity::Bold));
+        state.apply(&Sgr::Foreground(AnsiColor::Maroon.into()));
+        state.apply(&Sgr::Background(
+            RgbColor::new_8bpc(0x10, 0x20, 0x30).into(),
+        ));
+        state.apply(&Sgr::Underline(Underline::Single));
+        assert_eq!(
+            super::sgr_to_css(&state, palette),
+            "color:#cd0000;background-color:#102030;font-weight:bold;text-decoration:underline"
+        );
+
+        state.apply(&Sgr::Inverse(true));
+        state.apply(&Sgr::Underline(Underline::Curly));
+        state.apply(&Sgr::UnderlineColor(AnsiColor::Red.into()));
+        state.apply(&Sgr::StrikeThrough(true));
+        assert_eq!(
+            super::sgr_to_css(&state, palette),
+            "color:#102030;background-color:#cd0000;font-weight:bold;\
+             text-decoration:underline line-through wavy #ff0000"
+        );
+    }
+
+    #[test]
+    fn sgr_codes_used() {
+        let seq = vec![
+            CSI::Sgr(Sgr::Reset),
+            CSI::Sgr(Sgr::Intensity(Intensity::Bold)),
+            CSI::Sgr(Sgr::Foreground(ColorSpec::PaletteIndex(160))),
+            CSI::Sgr(Sgr::Background(RgbColor::new_8bpc(1, 2, 3).into())),
+            CSI::Sgr(Sgr::Foreground(AnsiColor::Red.into())),
+            CSI::Sgr(Sgr::Underline(Underline::Curly)),
+            CSI::Cursor(Cursor::Up(2)),
+            CSI::Sgr(Sgr::Unknown(77)),
+            CSI::Sgr(Sgr::Intensity(Intensity::Bold)),
+        ];
+        assert_eq!(
+            super::sgr_codes_used(&seq).into_iter().collect::<Vec<_>>(),
+            vec![0, 1, 4, 38, 48, 77, 91]
+        );
+        assert!(super::sgr_codes_used(&[]).is_empty());
+    }
+
+    #[test]
+    fn reset_clears_extended_attributes() {
+        let mut state = SgrState::new();
+        state.apply(&Sgr::Overline(true));
+        state.apply(&Sgr::UnderlineColor(AnsiColor::Blue.into()));
+        state.apply(&Sgr::Underline(Underline::Curly));
+        assert_eq!(
+            state.to_sgr_sequence(),
+            vec![
+                Sgr::Overline(true),
+                Sgr::UnderlineColor(AnsiColor::Blue.into()),
+                Sgr::Underline(Underline::Curly),
+            ]
+        );
+
+        state.apply(&Sgr::Reset);
+        assert!(state.is_default());
+        assert_eq!(state, SgrState::default());
+        assert_eq!(state.to_sgr_sequence(), vec![]);
+    }
+
+    #[test]
+    fn needs_reset_to_transition() {
+        let state = |sgrs: &[Sgr]| {
+            let mut state = SgrState::new();
+            for sgr in sgrs {
+                state.apply(sgr);
+            }
+            state
+        };
+
+        // Adding an attribute is shorter than rebuilding everything
+        let from = state(&[Sgr::Intensity(Intensity::Bold)]);
+        let to = state(&[
+            Sgr::Intensity(Intensity::Bold),
+            Sgr::Underline(Underline::Single),
+        ]);
+        assert!(!super::needs_reset_to_transition(&from, &to));
+        assert!(!super::needs_reset_to_transition(&to, &to));
+
+        // Dropping most of a rich state is shorter via a reset
+        let from = state(&[
+            Sgr::Intensity(Intensity::Bold),
+            Sgr::Italic(true),
+            Sgr::Underline(Underline::Single),
+            Sgr::Background(AnsiColor::Navy.into()),
+            Sgr::Foreground(AnsiColor::Maroon.into()),
+        ]);
+        let to = state(&[Sgr::Foreground(AnsiColor::Green.into())]);
+        assert!(super::needs_reset_to_transition(&from, &to));
+
+        // coalesce_sgr_resets makes the same choice.  Here the reset is
+        // followed by fewer codes than the direct change has, but the
+        // true color makes it the longer sequence, so it is dropped.
+        let color = Sgr::Foreground(RgbColor::new_8bpc(10, 20, 30).into());
+        let seq: Vec<CSI> = vec![
+            Sgr::Reset,
+            Sgr::Intensity(Intensity::Bold),
+            Sgr::Italic(true),
+            color.clone(),
+            Sgr::Reset,
+            color.clone(),
+        ]
+        .into_iter()
+        .map(CSI::Sgr)
+        .collect();
+        assert_eq!(
+            coalesce_sgr_resets(&seq),
+            vec![
+                CSI::Sgr(Sgr::Reset),
+                CSI::Sgr(Sgr::Intensity(Intensity::Bold)),
+                CSI::Sgr(Sgr::Italic(true)),
+                CSI::Sgr(color),
+                CSI::Sgr(Sgr::Intensity(Intensity::Normal)),
+                CSI::Sgr(Sgr::Italic(false)),
+            ]
+        );
+    }
+
+    #[test]
+    fn fraktur() {
+        assert_eq!(
+            parse('m', &[20], "\x1b[20m"),
+            vec![CSI::Sgr(Sgr::Fraktur(true))]
+        );
+        assert_eq!(
+            parse('m', &[23], "\x1b[23m"),
+            vec![CSI::Sgr(Sgr::Italic(false))]
+        );
+        assert_eq!(format!("{}", Sgr::Fraktur(false)), "23m");
+
+        // 23 turns off both italic and fraktur
+        let mut state = SgrState::default();
+        state.apply(&Sgr::Italic(true));
+        state.apply(&Sgr::Fraktur(true));
+        state.apply(&Sgr::Italic(false));
+        assert!(state.attributes.is_empty());
+
+        // so when turning off fraktur we need to restore italic
+        let mut from = SgrState::default();
+        from.apply(&Sgr::Italic(true));
+        from.apply(&Sgr::Fraktur(true));
+        let mut to = SgrState::default();
+        to.apply(&Sgr::Italic(true));
+        assert_eq!(
+            from.changes_to(&to),
+            vec![Sgr::Fraktur(false), Sgr::Italic(true)]
+        );
+    }
+
+    #[test]
+    fn blinks() {
+        assert_eq!(
+            parse('m', &[5], "\x1b[5m"),
+            vec![CSI::Sgr(Sgr::Blink(Blink::Slow))]
+        );
+        assert_eq!(
+            parse('m', &[6], "\x1b[6m"),
+            vec![CSI::Sgr(Sgr::Blink(Blink::Rapid))]
+        );
+        assert_eq!(
+            parse('m', &[25], "\x1b[25m"),
+            vec![CSI::Sgr(Sgr::Blink(Blink::None))]
+        );
+    }
+
+    #[test]
+    fn underlines() {
+        assert_eq!(
+            parse('m', &[21], "\x1b[21m"),
+            vec![CSI::Sgr(Sgr::Underline(Underline::Double))]
+        );
+        assert_eq!(
+            parse('m', &[4], "\x1b[4m"),
+            vec![CSI::Sgr(Sgr::Underline(Underline::Single))]
+        );
+
+        for (style, underline, expected) in &[
+            (0, Underline::None, "\x1b[24m"),
+            (1, Underline::Single, "\x1b[4m"),
+            (2, Underline::Double, "\x1b[21m"),
+            (3, Underline::Curly, "\x1b[4:3m"),
+            (4, Underline::Dotted, "\x1b[4:4m"),
+            (5, Underline::Dashed, "\x1b[4:5m"),
+        ] {
+            let params = [
+                CsiParam::Integer(4),
+                CsiParam::P(b':'),
+                CsiParam::Integer(*style),
+            ];
+            let res: Vec<_> = CSI::parse(&params, false, 'm').collect();
+            assert_eq!(res, vec![CSI::Sgr(Sgr::Underline(*underline))]);
+            assert_eq!(encode(&res), *expected);
+        }
+    }
+
+    #[test]
+    fn sgr_subparameter_groups() {
+        // An unknown color type keeps its subparameters together, and
+        // the codes either side of it are still interpreted
+        let params = [
+            CsiParam::Integer(1),
+            CsiParam::P(b';'),
+            CsiParam::Integer(38),
+            CsiParam::P(b':'),
+            CsiParam::Integer(9),
+            CsiParam::P(b':'),
+            CsiParam::P(b':'),
+            CsiParam::Integer(1),
+            CsiParam::P(b';'),
+            CsiParam::Integer(3),
+        ];
+        let res: Vec<_> = CSI::parse(&params, false, 'm').collect();
+        assert_eq!(
+            res,
+            vec![
+                CSI::Sgr(Sgr::Intensity(Intensity::Bold)),
+                CSI::Unspecified(Box::new(Unspecified {
+                    params: params[2..8].to_vec(),
+                    parameters_truncated: false,
+                    control: 'm',
+                    payload: String::new(),
+                })),
+                CSI::Sgr(Sgr::Italic(true)),
+            ]
+        );
+        assert_eq!(encode(&res), "\x1b[1m\x1b[38:9::1m\x1b[3m");
+
+        // As does a code that doesn't accept subparameters
+        let params = [
+            CsiParam::Integer(1),
+            CsiParam::P(b':'),
+            CsiParam::Integer(2),
+            CsiParam::P(b';'),
+            CsiParam::Integer(3),
+        ];
+        let res: Vec<_> = CSI::parse(&params, false, 'm').collect();
+        assert_eq!(
+            res,
+            vec![
+                CSI::Unspecified(Box::new(Unspecified {
+                    params: params[0..3].to_vec(),
+                    parameters_truncated: false,
+                    control: 'm',
+                    payload: String::new(),
+                })),
+                CSI::Sgr(Sgr::Italic(true)),
+            ]
+        );
+    }
+
+    #[test]
+    fn overline() {
+        assert_eq!(
+            parse('m', &[4, 53], "\x1b[4m\x1b[53m"),
+            vec![
+                CSI::Sgr(Sgr::Underline(Underline::Single)),
+                CSI::Sgr(Sgr::Overline(true)),
+            ]
+        );
+        assert_eq!(
+            parse('m', &[55], "\x1b[55m"),
+            vec![CSI::Sgr(Sgr::Overline(false))]
+        );
+    }
+
+    #[test]
+    fn underline_color() {
+        assert_eq!(
+            parse('m', &[58, 2], "\x1b[58;2m"),
+            vec![CSI::Unspecified(Box::new(Unspecified {
+                params: [
+                    CsiParam::Integer(58),
+                    CsiParam::P(b';'),
+                    CsiParam::Integer(2)
+                ]
+                .to_vec(),
+                parameters_truncated: false,
+                control: 'm',
+                payload: String::new(),
+            }))]
+        );
+
+        assert_eq!(
+            parse('m', &[58, 2, 255, 255, 255], "\x1b[58:2::255:255:255m"),
+            vec![CSI::Sgr(Sgr::UnderlineColor(ColorSpec::TrueColor(
+                RgbColor::new_8bpc(255, 255, 255),
+            )))]
+        );
+        assert_eq!(
+            parse(
+                'm',
+                &[58, 5, 220, 255, 255],
+                "\x1b[58:5:220m\x1b[255m\x1b[255m"
+            ),
+            vec![
+                CSI::Sgr(Sgr::UnderlineColor(ColorSpec::PaletteIndex(220))),
+                CSI::Sgr(Sgr::Unknown(255)),
+                CSI::Sgr(Sgr::Unknown(255)),
+            ]
+        );
+        assert_eq!(
+            parse('m', &[59], "\x1b[59m"),
+            vec![CSI::Sgr(Sgr::UnderlineColor(ColorSpec::Default))]
+        );
+
+        // A red squiggle that leaves the foreground color alone
+        assert_eq!(
+            Sgr::parse_list("4:3;58;2;255;0;0").unwrap(),
+            vec![
+                Sgr::Underline(Underline::Curly),
+                Sgr::UnderlineColor(RgbColor::new_8bpc(255, 0, 0).into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn palette_foreground_encoding_length() {
+        // The 16 ANSI colors use the short `3x` and `9x` codes, so they
+        // encode more compactly than the rest of the palette
+        let encode = |idx: u8| format!("{}", Sgr::Foreground(ColorSpec::PaletteIndex(idx)));
+        assert_eq!(encode(1), "31m");
+        assert_eq!(encode(9), "91m");
+        assert_eq!(encode(100), "38:5:100m");
+        assert!(encode(1).len() < encode(100).len());
+    }
+
+    #[test]
+    fn bright_colors_as_256() {
+        let options = SgrEncodeOptions {
+            bright_colors_as_256: true,
+            ..Default::default()
+        };
+        let encode_with = |sgr: Sgr| format!("{}", sgr.with_options(options));
+
+        assert_eq!(
+            encode_with(Sgr::Foreground(AnsiColor::Red.into())),
+            "38;5;9m"
+        );
+        assert_eq!(
+            encode_with(Sgr::Background(AnsiColor::White.into())),
+            "48;5;15m"
+        );
+        // The standard colors retain their short form
+        assert_eq!(
+            encode_with(Sgr::Foreground(AnsiColor::Maroon.into())),
+            "31m"
+        );
+        assert_eq!(encode_with(Sgr::Background(AnsiColor::Navy.into())), "44m");
+        // The default is unchanged
+        assert_eq!(format!("{}", Sgr::Foreground(AnsiColor::Red.into())), "91m");
+
+        // The 256 color form parses back to the same value
+        assert_eq!(
+            Sgr::parse_list("38;5;9").unwrap(),
+            vec![Sgr::Foreground(AnsiColor::Red.into())]
+        );
+    }
+
+    #[test]
+    fn bright_foreground_as_bold() {
+        let options = SgrEncodeOptions {
+            bright_foreground_as_bold: true,
+            ..Default::default()
+        };
+        let bright_red = Sgr::Foreground(AnsiColor::Red.into());
+        assert_eq!(format!("\x1b[{}", bright_red), "\x1b[91m");
+        assert_eq!(
+            format!("\x1b[{}", bright_red.with_options(options)),
+            "\x1b[1;31m"
+        );
+
+        let bright_white = Sgr::Foreground(AnsiColor::White.into());
+        assert_eq!(format!("\x1b[{}", bright_white), "\x1b[97m");
+        assert_eq!(
+            format!("\x1b[{}", bright_white.with_options(options)),
+            "\x1b[1;37m"
+        );
+
+        // Normal colors and bright backgrounds are unaffected
+        let red = Sgr::Foreground(AnsiColor::Maroon.into());
+        assert_eq!(format!("\x1b[{}", red.with_options(options)), "\x1b[31m");
+        let bright_bg = Sgr::Background(AnsiColor::Red.into());
+        assert_eq!(
+            format!("\x1b[{}", bright_bg.with_options(options)),
+            "\x1b[101m"
+        );
+    }
+
+    #[test]
+    fn unknown_underline_style() {
+        let params = [
+            CsiParam::Integer(4),
+            CsiParam::P(b':'),
+            CsiParam::Integer(9),
+        ];
+        let res: Vec<_> = CSI::parse(&params, false, 'm').collect();
+        assert_eq!(res, vec![CSI::Sgr(Sgr::UnknownUnderline(9))]);
+        assert_eq!(encode(&res), "\x1b[4:9m");
+
+        // Subsequent codes are still interpreted
+        let params = [
+            CsiParam::Integer(4),
+            CsiParam::P(b':'),
+            CsiParam::Integer(9),
+            CsiParam::P(b';'),
+            CsiParam::Integer(1),
+        ];
+        let res: Vec<_> = CSI::parse(&params, false, 'm').collect();
+        assert_eq!(
+            res,
+            vec![
+                CSI::Sgr(Sgr::UnknownUnderline(9)),
+                CSI::Sgr(Sgr::Intensity(Intensity::Bold))
+            ]
+        );
+
+        // The known styles are unaffected
+        let params = [
+            CsiParam::Integer(4),
+            CsiParam::P(b':'),
+            CsiParam::Integer(5),
+        ];
+        let res: Vec<_> = CSI::parse(&params, false, 'm').collect();
+        assert_eq!(res, vec![CSI::Sgr(Sgr::Underline(Underline::Dashed))]);
+
+        // SgrState treats the unknown style as an underline style
+        let mut state = SgrState::new();
+        state.apply(&Sgr::Underline(Underline::Single));
+        state.apply(&Sgr::UnknownUnderline(9));
+        assert_eq!(state.to_sgr_sequence(), vec![Sgr::UnknownUnderline(9)]);
+        state.apply(&Sgr::Underline(Underline::Double));
+        assert_eq!(
+            state.to_sgr_sequence(),
+            vec![Sgr::Underline(Underline::Double)]
+        );
+        state.apply(&Sgr::UnknownUnderline(9));
+        state.apply(&Sgr::Underline(Underline::None));
+        assert!(state.is_default());
+    }
+
+    #[test]
+    fn ten_bpc_color() {
+        // True colors are always encoded with 8 bits per channel
+        let ten = Sgr::Foreground(RgbColor::new_10bpc(1023, 512, 1).into());
+        assert_eq!(format!("\x1b[{}", ten), "\x1b[38:2::255:127:0m");
+    }
+
+    #[test]
+    fn color() {
+        assert_eq!(
+            parse('m', &[38, 2], "\x1b[38;2m"),
+            vec![CSI::Unspecified(Box::new(Unspecified {
+                params: [
+                    CsiParam::Integer(38),
+                    CsiParam::P(b';'),
+                    CsiParam::Integer(2)
+                ]
+                .to_vec(),
+                parameters_truncated: false,
+                control: 'm',
+                payload: String::new(),
+            }))]
+        );
+
+        assert_eq!(
+            parse('m', &[38, 2, 255, 255, 255], "\x1b[38:2::255:255:255m"),
+            vec![CSI::Sgr(Sgr::Foreground(ColorSpec::TrueColor(
+                RgbColor::new_8bpc(255, 255, 255),
+            )))]
+        );
+        assert_eq!(
+            parse(
+                'm',
+                &[38, 5, 220, 255, 255],
+                "\x1b[38:5:220m\x1b[255m\x1b[255m"
+            ),
+            vec![
+                CSI::Sgr(Sgr::Foreground(ColorSpec::PaletteIndex(220))),
+                CSI::Sgr(Sgr::Unknown(255)),
+                CSI::Sgr(Sgr::Unknown(255)),
+            ]
+        );
+    }
+
+    #[test]
+    fn edit() {
+        assert_eq!(
+            parse('J', &[], "\x1b[J"),
+            vec![CSI::Edit(Edit::EraseInDisplay(
+                EraseInDisplay::EraseToEndOfDisplay,
+            ))]
+        );
+        assert_eq!(
+            parse('J', &[0], "\x1b[J"),
+            vec![CSI::Edit(Edit::EraseInDisplay(
+                EraseInDisplay::EraseToEndOfDisplay,
+            ))]
+        );
+        assert_eq!(
+            parse('J', &[1], "\x1b[1J"),
+            vec![CSI::Edit(Edit::EraseInDisplay(
+                EraseInDisplay::EraseToStartOfDisplay,
+            ))]
+        );
+        assert_eq!(
+            parse('J', &[2], "\x1b[2J"),
+            vec![CSI::Edit(Edit::EraseInDisplay(
+                EraseInDisplay::EraseDisplay
+            ))]
+        );
+        assert_eq!(
+            parse('J', &[3], "\x1b[3J"),
+            vec![CSI::Edit(Edit::EraseInDisplay(
+                EraseInDisplay::EraseScrollback,
+            ))]
+        );
+
+        // DECSED selective erase is not supported
+        let params = [CsiParam::P(b'?'), CsiParam::Integer(2)];
+        let res: Vec<_> = CSI::parse(&params, false, 'J').collect();
+        assert_eq!(
+            res,
+            vec![CSI::Unspecified(Box::new(Unspecified {
+                params: params.to_vec(),
+                parameters_truncated: false,
+                control: 'J',
+                payload: String::new(),
+            }))]
+        );
+        assert_eq!(encode(&res), "\x1b[?2J");
+    }
+
+    #[test]
+    fn insert_delete_character() {
+        assert_eq!(
+            parse('@', &[], "\x1b[@"),
+            vec![CSI::Edit(Edit::InsertCharacter(1))]
+        );
+        assert_eq!(
+            parse('@', &[1], "\x1b[@"),
+            vec![CSI::Edit(Edit::InsertCharacter(1))]
+        );
+        assert_eq!(
+            parse('@', &[5], "\x1b[5@"),
+            vec![CSI::Edit(Edit::InsertCharacter(5))]
+        );
+        assert_eq!(
+            parse('P', &[], "\x1b[P"),
+            vec![CSI::Edit(Edit::DeleteCharacter(1))]
+        );
+        assert_eq!(
+            parse('P', &[1], "\x1b[P"),
+            vec![CSI::Edit(Edit::DeleteCharacter(1))]
+        );
+        assert_eq!(
+            parse('P', &[5], "\x1b[5P"),
+            vec![CSI::Edit(Edit::DeleteCharacter(5))]
+        );
+    }
+
+    #[test]
+    fn insert_delete_line() {
+        assert_eq!(
+            parse('L', &[], "\x1b[L"),
+            vec![CSI::Edit(Edit::InsertLine(1))]
+        );
+        assert_eq!(
+            parse('L', &[3], "\x1b[3L"),
+            vec![CSI::Edit(Edit::InsertLine(3))]
+        );
+        assert_eq!(
+            parse('M', &[], "\x1b[M"),
+            vec![CSI::Edit(Edit::DeleteLine(1))]
+        );
+        assert_eq!(
+            parse('M', &[3], "\x1b[3M"),
+            vec![CSI::Edit(Edit::DeleteLine(3))]
+        );
+
+        // A count that doesn't fit in a u32 is not understood
+        let too_big = i64::from(u32::max_value()) + 1;
+        for control in &['L', 'M'] {
+            let res = parse(
+                *control,
+                &[too_big],
+                &format!("\x1b[{}{}", too_big, control),
+            );
+            assert!(matches!(res[0], CSI::Unspecified(_)), "{:?}", res);
+        }
+    }
+
+    #[test]
+    fn scroll_up_down() {
+        assert_eq!(
+            parse('S', &[], "\x1b[S"),
+            vec![CSI::Edit(Edit::ScrollUp(1))]
+        );
+        assert_eq!(
+            parse('S', &[4], "\x1b[4S"),
+            vec![CSI::Edit(Edit::ScrollUp(4))]
+        );
+        assert_eq!(
+            parse('T', &[], "\x1b[T"),
+            vec![CSI::Edit(Edit::ScrollDown(1))]
+        );
+        assert_eq!(
+            parse('T', &[4], "\x1b[4T"),
+            vec![CSI::Edit(Edit::ScrollDown(4))]
+        );
+
+        // With more parameters, T is the mouse highlight tracking control
+        let res = parse('T', &[1, 2, 3, 4, 5], "\x1b[1;2;3;4;5T");
+        assert!(matches!(res[0], CSI::Unspecified(_)), "{:?}", res);
+    }
+
+    #[test]
+    fn erase_character() {
+        assert_eq!(
+            parse('X', &[], "\x1b[X"),
+            vec![CSI::Edit(Edit::EraseCharacter(1))]
+        );
+        assert_eq!(
+            parse('X', &[7], "\x1b[7X"),
+            vec![CSI::Edit(Edit::EraseCharacter(7))]
+        );
+        // ECH and DCH are distinct
+        assert_ne!(parse('X', &[7], "\x1b[7X"), parse('P', &[7], "\x1b[7P"));
+    }
 
-                        SgrCode::InverseOn => one!(Sgr::Inverse(true)),
-                        SgrCode::InverseOff => one!(Sgr::Inverse(false)),
-                        SgrCode::InvisibleOn => one!(Sgr::Invisible(true)),
-                        SgrCode::InvisibleOff => one!(Sgr::Invisible(false)),
-                        SgrCode::StrikeThroughOn => one!(Sgr::StrikeThrough(true)),
-                        SgrCode::StrikeThroughOff => one!(Sgr::StrikeThrough(false)),
-                        SgrCode::OverlineOn => one!(Sgr::Overline(true)),
-                        SgrCode::OverlineOff => one!(Sgr::Overline(false)),
-                        SgrCode::DefaultFont => one!(Sgr::Font(Font::Default)),
-                        SgrCode::AltFont1 => one!(Sgr::Font(Font::Alternate(1))),
-                        SgrCode::AltFont2 => one!(Sgr::Font(Font::Alternate(2))),
-                        SgrCode::AltFont3 => one!(Sgr::Font(Font::Alternate(3))),
-                        SgrCode::AltFont4 => one!(Sgr::Font(Font::Alternate(4))),
-                        SgrCode::AltFont5 => one!(Sgr::Font(Font::Alternate(5))),
-                        SgrCode::AltFont6 => one!(Sgr::Font(Font::Alternate(6))),
-                        SgrCode::AltFont7 => one!(Sgr::Font(Font::Alternate(7))),
-                        SgrCode::AltFont8 => one!(Sgr::Font(Font::Alternate(8))),
-                        SgrCode::AltFont9 => one!(Sgr::Font(Font::Alternate(9))),
-                    },
-                },
+    #[test]
+    fn erase_in_line() {
+        assert_eq!(
+            parse('K', &[], "\x1b[K"),
+            vec![CSI::Edit(Edit::EraseInLine(EraseInLine::EraseToEndOfLine))]
+        );
+        // check that the default of 0 is omitted
+        assert_eq!(
+            parse('K', &[0], "\x1b[K"),
+            vec![CSI::Edit(Edit::EraseInLine(EraseInLine::EraseToEndOfLine))]
+        );
+        assert_eq!(
+            parse('K', &[1], "\x1b[1K"),
+            vec![CSI::Edit(Edit::EraseInLine(
+                EraseInLine::EraseToStartOfLine
+            ))]
+        );
+        assert_eq!(
+            parse('K', &[2], "\x1b[2K"),
+            vec![CSI::Edit(Edit::EraseInLine(EraseInLine::EraseLine))]
+        );
+    }
+
+    #[test]
+    fn repeat() {
+        assert_eq!(parse('b', &[], "\x1b[b"), vec![CSI::Edit(Edit::Repeat(1))]);
+        assert_eq!(parse('b', &[0], "\x1b[b"), vec![CSI::Edit(Edit::Repeat(1))]);
+        assert_eq!(
+            parse('b', &[3], "\x1b[3b"),
+            vec![CSI::Edit(Edit::Repeat(3))]
+        );
+        assert_eq!(
+            parse('b', &[i64::max_value()], "\x1b[65535b"),
+            vec![CSI::Edit(Edit::Repeat(MAX_REPEAT_COUNT))]
+        );
+        assert_eq!(
+            parse('b', &[i64::from(u32::max_value())], "\x1b[65535b"),
+            vec![CSI::Edit(Edit::Repeat(MAX_REPEAT_COUNT))]
+        );
+        assert_eq!(
+            parse('b', &[i64::from(u32::max_value()) + 1], "\x1b[65535b"),
+            vec![CSI::Edit(Edit::Repeat(MAX_REPEAT_COUNT))]
+        );
+        assert!(matches!(
+            parse('b', &[2, 3], "\x1b[2;3b")[0],
+            CSI::Unspecified(_)
+        ));
+
+        // The clamp only applies when parsing
+        assert_eq!(
+            encode(&vec![CSI::Edit(Edit::Repeat(u32::max_value()))]),
+            "\x1b[4294967295b"
+        );
+    }
+
+    #[test]
+    fn validate() {
+        assert_eq!(CSI::validate('H', &[], &[]), Ok(()));
+        assert_eq!(CSI::validate('H', &[], &[2, 3]), Ok(()));
+        assert_eq!(
+            CSI::validate('H', &[], &[1, 2, 3]),
+            Err(CsiValidationError::TooManyParameters {
+                control: 'H',
+                max: 2,
+                actual: 3
+            })
+        );
+        assert_eq!(
+            CSI::validate('C', &[], &[-1]),
+            Err(CsiValidationError::ParameterOutOfRange {
+                control: 'C',
+                index: 0,
+                value: -1
+            })
+        );
+        assert_eq!(
+            CSI::validate('J', &[], &[4]),
+            Err(CsiValidationError::ParameterOutOfRange {
+                control: 'J',
+                index: 0,
+                value: 4
+            })
+        );
+        assert_eq!(CSI::validate('q', b" ", &[2]), Ok(()));
+        assert_eq!(
+            CSI::validate('C', b"$", &[]),
+            Err(CsiValidationError::UnexpectedIntermediates {
+                control: 'C',
+                intermediates: b"$".to_vec()
+            })
+        );
+        assert_eq!(
+            CSI::validate('~', &[], &[]),
+            Err(CsiValidationError::UnknownControl('~'))
+        );
+
+        assert_eq!(CSI::validate('m', &[], &[1, 38, 5, 220, 4]), Ok(()));
+        assert_eq!(CSI::validate('m', &[], &[48, 2, 255, 0, 10, 0]), Ok(()));
+        assert_eq!(
+            CSI::validate('m', &[], &[38, 2, 255, 0]),
+            Err(CsiValidationError::IncompleteSgrColor { index: 4 })
+        );
+        assert_eq!(
+            CSI::validate('m', &[], &[38, 5, 256]),
+            Err(CsiValidationError::ParameterOutOfRange {
+                control: 'm',
+                index: 2,
+                value: 256
+            })
+        );
+        assert_eq!(
+            CSI::validate('m', &[], &[38]),
+            Err(CsiValidationError::IncompleteSgrColor { index: 0 })
+        );
+    }
+
+    #[test]
+    fn annotated() {
+        let mut seq = parse('m', &[1, 91], "\x1b[1m\x1b[91m");
+        seq.append(&mut parse('m', &[38, 5, 220], "\x1b[38:5:220m"));
+        seq.append(&mut parse('C', &[4], "\x1b[4C"));
+        assert_eq!(
+            annotate(&seq),
+            vec![
+                ("\\x1b[1m".to_string(), "bold".to_string()),
+                ("\\x1b[91m".to_string(), "bright red foreground".to_string()),
+                (
+                    "\\x1b[38:5:220m".to_string(),
+                    "extended foreground color".to_string()
+                ),
+                ("\\x1b[4C".to_string(), "Cursor(Right(4))".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn sgr_code() {
+        // The code agrees with the one that the encoder leads with
+        let sgrs = vec![
+            Sgr::Reset,
+            Sgr::Intensity(Intensity::Half),
+            Sgr::Underline(Underline::Curly),
+            Sgr::Underline(Underline::None),
+            Sgr::Fraktur(false),
+            Sgr::Font(Font::Alternate(3)),
+            Sgr::Foreground(AnsiColor::Navy.into()),
+            Sgr::Foreground(AnsiColor::White.into()),
+            Sgr::Foreground(ColorSpec::PaletteIndex(16)),
+            Sgr::Background(AnsiColor::Grey.into()),
+            Sgr::Background(RgbColor::new_8bpc(1, 2, 3).into()),
+            Sgr::UnderlineColor(ColorSpec::Default),
+        ];
+        for sgr in sgrs {
+            let encoded = sgr.to_string();
+            let end = encoded.find(|c: char| !c.is_ascii_digit()).unwrap();
+            assert_eq!(
+                sgr.code().map(|code| code as i64),
+                Some(encoded[..end].parse().unwrap()),
+                "{:?}",
+                sgr
+            );
+        }
+        assert_eq!(Sgr::Unknown(77).code(), None);
+    }
+
+    #[test]
+    fn decstbm() {
+        assert_eq!(
+            parse('r', &[], "\x1b[r"),
+            vec![CSI::Cursor(Cursor::SetTopAndBottomMargins {
+                top: OneBased::new(1),
+                bottom: None
+            })]
+        );
+        assert_eq!(
+            parse('r', &[3], "\x1b[3r"),
+            vec![CSI::Cursor(Cursor::SetTopAndBottomMargins {
+                top: OneBased::new(3),
+                bottom: None
+            })]
+        );
+        assert_eq!(
+            parse('r', &[3, 0], "\x1b[3r"),
+            vec![CSI::Cursor(Cursor::SetTopAndBottomMargins {
+                top: OneBased::new(3),
+                bottom: None
+            })]
+        );
+        assert_eq!(
+            parse('r', &[2, 10], "\x1b[2;10r"),
+            vec![CSI::Cursor(Cursor::SetTopAndBottomMargins {
+                top: OneBased::new(2),
+                bottom: Some(OneBased::new(10))
+            })]
+        );
+        assert_eq!(
+            parse('r', &[2, 10, 1], "\x1b[2;10r"),
+            vec![CSI::Cursor(Cursor::SetTopAndBottomMargins {
+                top: OneBased::new(2),
+                bottom: Some(OneBased::new(10))
+            })]
+        );
+
+        // The private form is XTRESTORE, not a margin change
+        let res: Vec<_> =
+            CSI::parse(&[CsiParam::P(b'?'), CsiParam::Integer(1049)], false, 'r').collect();
+        assert_eq!(
+            res,
+            vec![CSI::Mode(Mode::RestoreDecPrivateMode(
+                DecPrivateMode::Code(DecPrivateModeCode::ClearAndEnableAlternateScreen)
+            ))]
+        );
+        assert_eq!(encode(&res), "\x1b[?1049r");
+    }
+
+    #[test]
+    fn checked_constructors() {
+        assert_eq!(
+            Cursor::try_position(2, 3),
+            Ok(Cursor::Position {
+                line: OneBased::new(2),
+                col: OneBased::new(3),
+            })
+        );
+        assert_eq!(
+            Cursor::try_position(0, 0),
+            Err(CsiBuildError::Zero { name: "line" })
+        );
+        assert_eq!(
+            Cursor::try_position(1, 0),
+            Err(CsiBuildError::Zero { name: "col" })
+        );
+        assert_eq!(
+            Cursor::try_position(-1, 1),
+            Err(CsiBuildError::OutOfRange {
+                name: "line",
+                value: -1
+            })
+        );
+        assert_eq!(
+            Cursor::try_position(1, 1 << 40),
+            Err(CsiBuildError::OutOfRange {
+                name: "col",
+                value: 1 << 40
+            })
+        );
+
+        assert_eq!(
+            Cursor::try_character_absolute(5),
+            Ok(Cursor::CharacterAbsolute(OneBased::new(5)))
+        );
+        assert!(Cursor::try_character_absolute(0).is_err());
+
+        assert_eq!(
+            Cursor::try_set_top_and_bottom_margins(1, 24),
+            Ok(Cursor::SetTopAndBottomMargins {
+                top: OneBased::new(1),
+                bottom: Some(OneBased::new(24)),
+            })
+        );
+        assert_eq!(
+            Cursor::try_set_top_and_bottom_margins(10, 10),
+            Err(CsiBuildError::InvalidMargins {
+                top: 10,
+                bottom: 10
+            })
+        );
+        assert_eq!(
+            CsiBuildError::Zero { name: "line" }.to_string(),
+            "line is 1-based and must not be zero"
+        );
+    }
+
+    #[test]
+    fn decslrm() {
+        assert_eq!(
+            parse('s', &[2, 40], "\x1b[2;40s"),
+            vec![CSI::Cursor(Cursor::SetLeftAndRightMargins {
+                left: OneBased::new(2),
+                right: OneBased::new(40),
+            })]
+        );
+        // Without params this is SCP; only the DECLRMM state can
+        // tell the two apart
+        assert_eq!(
+            parse('s', &[], "\x1b[s"),
+            vec![CSI::Cursor(Cursor::SaveCursor)]
+        );
+        // The default margins are encoded explicitly so that they
+        // are not mistaken for SCP
+        assert_eq!(
+            parse('s', &[1], "\x1b[1s"),
+            vec![CSI::Cursor(Cursor::SetLeftAndRightMargins {
+                left: OneBased::new(1),
+                right: OneBased::new(u32::max_value()),
+            })]
+        );
+    }
+
+    #[test]
+    fn save_restore() {
+        assert_eq!(
+            parse('s', &[], "\x1b[s"),
+            vec![CSI::Cursor(Cursor::SaveCursor)]
+        );
+        assert_eq!(
+            parse('u', &[], "\x1b[u"),
+            vec![CSI::Cursor(Cursor::RestoreCursor)]
+        );
+        assert!(Cursor::SaveCursor.is_save_restore());
+        assert!(Cursor::RestoreCursor.is_save_restore());
+        assert!(!Cursor::Up(1).is_save_restore());
+        assert!(!Cursor::RequestActivePositionReport.is_save_restore());
+    }
+
+    #[test]
+    fn tabulation_clear() {
+        assert_eq!(
+            parse('g', &[3], "\x1b[3g"),
+            vec![CSI::Cursor(Cursor::TabulationClear(
+                TabulationClear::ClearAllCharacterTabStops
+            ))]
+        );
+        assert_eq!(
+            parse('g', &[], "\x1b[g"),
+            vec![CSI::Cursor(Cursor::TabulationClear(
+                TabulationClear::ClearCharacterTabStopAtActivePosition
+            ))]
+        );
+        assert_eq!(
+            parse('g', &[0], "\x1b[g"),
+            vec![CSI::Cursor(Cursor::TabulationClear(
+                TabulationClear::ClearCharacterTabStopAtActivePosition
+            ))]
+        );
+        assert!(matches!(
+            parse('g', &[6], "\x1b[6g")[0],
+            CSI::Unspecified(_)
+        ));
+    }
+
+    #[test]
+    fn hvp() {
+        assert_eq!(
+            parse('f', &[3, 7], "\x1b[3;7f"),
+            vec![CSI::Cursor(Cursor::CharacterAndLinePosition {
+                line: OneBased::new(3),
+                col: OneBased::new(7),
+            })]
+        );
+        assert_eq!(
+            parse('f', &[], "\x1b[1;1f"),
+            vec![CSI::Cursor(Cursor::CharacterAndLinePosition {
+                line: OneBased::new(1),
+                col: OneBased::new(1),
+            })]
+        );
+        assert_eq!(
+            parse('H', &[3, 7], "\x1b[3;7H"),
+            vec![CSI::Cursor(Cursor::Position {
+                line: OneBased::new(3),
+                col: OneBased::new(7),
+            })]
+        );
+    }
+
+    #[test]
+    fn position_by_axis() {
+        assert_eq!(
+            parse('d', &[], "\x1b[d"),
+            vec![CSI::Cursor(Cursor::LinePositionAbsolute(1))]
+        );
+        assert_eq!(
+            parse('d', &[7], "\x1b[7d"),
+            vec![CSI::Cursor(Cursor::LinePositionAbsolute(7))]
+        );
+        assert_eq!(
+            parse('e', &[], "\x1b[e"),
+            vec![CSI::Cursor(Cursor::LinePositionForward(1))]
+        );
+        assert_eq!(
+            parse('e', &[3], "\x1b[3e"),
+            vec![CSI::Cursor(Cursor::LinePositionForward(3))]
+        );
+        assert_eq!(
+            parse('`', &[], "\x1b[`"),
+            vec![CSI::Cursor(Cursor::CharacterPositionAbsolute(
+                OneBased::new(1)
+            ))]
+        );
+        assert_eq!(
+            parse('`', &[12], "\x1b[12`"),
+            vec![CSI::Cursor(Cursor::CharacterPositionAbsolute(
+                OneBased::new(12)
+            ))]
+        );
+        assert_eq!(
+            parse('a', &[], "\x1b[a"),
+            vec![CSI::Cursor(Cursor::CharacterPositionForward(1))]
+        );
+        assert_eq!(
+            parse('a', &[4], "\x1b[4a"),
+            vec![CSI::Cursor(Cursor::CharacterPositionForward(4))]
+        );
+    }
+
+    #[test]
+    fn next_and_preceding_line() {
+        assert_eq!(
+            parse('E', &[], "\x1b[E"),
+            vec![CSI::Cursor(Cursor::NextLine(1))]
+        );
+        assert_eq!(Cursor::NextLine(1).resolve(5), Some((6, 1)));
+        assert_eq!(Cursor::NextLine(3).resolve(5), Some((8, 1)));
+        assert_eq!(Cursor::NextLine(0).resolve(5), Some((6, 1)));
+
+        assert_eq!(
+            parse('F', &[2], "\x1b[2F"),
+            vec![CSI::Cursor(Cursor::PrecedingLine(2))]
+        );
+        assert_eq!(Cursor::PrecedingLine(2).resolve(5), Some((3, 1)));
+        assert_eq!(Cursor::PrecedingLine(10).resolve(5), Some((1, 1)));
+        assert_eq!(Cursor::PrecedingLine(0).resolve(5), Some((4, 1)));
+
+        assert_eq!(Cursor::LinePositionForward(1).resolve(5), None);
+    }
+
+    #[test]
+    fn assign_color() {
+        let res: Vec<_> = CSI::parse(
+            &[
+                CsiParam::Integer(1),
+                CsiParam::P(b';'),
+                CsiParam::Integer(7),
+                CsiParam::P(b';'),
+                CsiParam::Integer(4),
+                CsiParam::P(b','),
+            ],
+            false,
+            '|',
+        )
+        .collect();
+        assert_eq!(
+            res,
+            vec![CSI::Mode(Mode::AssignColor {
+                item: 1,
+                fg: 7,
+                bg: 4
+            })]
+        );
+        assert_eq!(encode(&res), "\x1b[1;7;4,|");
+
+        // All three parameters are required
+        let res: Vec<_> =
+            CSI::parse(&[CsiParam::Integer(1), CsiParam::P(b',')], false, '|').collect();
+        assert!(matches!(res[0], CSI::Unspecified(_)));
+        assert_eq!(encode(&res), "\x1b[1,|");
+    }
+
+    #[test]
+    fn palette_index_round_trip() {
+        let cases = [
+            (0, "30m", "40m"),
+            (7, "37m", "47m"),
+            (8, "90m", "100m"),
+            (15, "97m", "107m"),
+            (200, "38:5:200m", "48:5:200m"),
+        ];
+        for &(idx, fg_code, bg_code) in &cases {
+            let fg = Sgr::Foreground(ColorSpec::PaletteIndex(idx));
+            let bg = Sgr::Background(ColorSpec::PaletteIndex(idx));
+            for (sgr, code) in &[(fg.clone(), fg_code), (bg.clone(), bg_code)] {
+                assert_eq!(format!("{}", sgr), *code);
+                assert_eq!(
+                    Sgr::parse_list(code.trim_end_matches('m')),
+                    Ok(vec![sgr.clone()])
+                );
             }
+            // The semicolon form parses to the same value
+            assert_eq!(Sgr::parse_list(&format!("38;5;{}", idx)), Ok(vec![fg]));
+            assert_eq!(Sgr::parse_list(&format!("48;5;{}", idx)), Ok(vec![bg]));
         }
+        assert_eq!(
+            Sgr::Foreground(AnsiColor::Maroon.into()),
+            Sgr::Foreground(ColorSpec::PaletteIndex(1))
+        );
     }
-}
 
-#[derive(Debug, Clone, PartialEq, Eq, FromPrimitive)]
-pub enum SgrCode {
-    Reset = 0,
-    IntensityBold = 1,
-    IntensityDim = 2,
-    ItalicOn = 3,
-    UnderlineOn = 4,
-    /// Blinks < 150 times per minute
-    BlinkOn = 5,
-    /// Blinks > 150 times per minute
-    RapidBlinkOn = 6,
-    InverseOn = 7,
-    InvisibleOn = 8,
-    StrikeThroughOn = 9,
-    DefaultFont = 10,
-    AltFont1 = 11,
-    AltFont2 = 12,
-    AltFont3 = 13,
-    AltFont4 = 14,
-    AltFont5 = 15,
-    AltFont6 = 16,
-    AltFont7 = 17,
-    AltFont8 = 18,
-    AltFont9 = 19,
-    // Fraktur = 20,
-    UnderlineDouble = 21,
-    NormalIntensity = 22,
-    ItalicOff = 23,
-    UnderlineOff = 24,
-    BlinkOff = 25,
-    InverseOff = 27,
-    InvisibleOff = 28,
-    StrikeThroughOff = 29,
-    ForegroundBlack = 30,
-    ForegroundRed = 31,
-    ForegroundGreen = 32,
-    ForegroundYellow = 33,
-    ForegroundBlue = 34,
-    ForegroundMagenta = 35,
-    ForegroundCyan = 36,
-    ForegroundWhite = 37,
-    ForegroundDefault = 39,
-    BackgroundBlack = 40,
-    BackgroundRed = 41,
-    BackgroundGreen = 42,
-    BackgroundYellow = 43,
-    BackgroundBlue = 44,
-    BackgroundMagenta = 45,
-    BackgroundCyan = 46,
-    BackgroundWhite = 47,
-    BackgroundDefault = 49,
-    OverlineOn = 53,
-    OverlineOff = 55,
+    #[test]
+    fn sgr_parse_list() {
+        assert_eq!(
+            Sgr::parse_list("1;3;38;5;160;48:2::10:20:30;4:3").unwrap(),
+            vec![
+                Sgr::Intensity(Intensity::Bold),
+                Sgr::Italic(true),
+                Sgr::Foreground(ColorSpec::PaletteIndex(160)),
+                Sgr::Background(RgbColor::new_8bpc(10, 20, 30).into()),
+                Sgr::Underline(Underline::Curly),
+            ]
+        );
+        assert_eq!(Sgr::parse_list("").unwrap(), vec![Sgr::Reset]);
+        assert_eq!(
+            Sgr::parse_list("1;x"),
+            Err(SgrParseError::InvalidCharacter('x'))
+        );
+        assert_eq!(
+            Sgr::parse_list("1;38;5"),
+            Err(SgrParseError::Unrecognized("38;5".to_string()))
+        );
+    }
 
-    UnderlineColor = 58,
-    ResetUnderlineColor = 59,
+    #[test]
+    fn is_lossy() {
+        let params = [CsiParam::Integer(1), CsiParam::P(b'!'), CsiParam::P(b'!')];
+        let res: Vec<_> = CSI::parse(&params, true, 'z').collect();
+        assert_eq!(res.len(), 1);
+        assert!(res[0].is_lossy());
+
+        let res: Vec<_> = CSI::parse(&params, false, 'z').collect();
+        assert!(!res[0].is_lossy());
+        assert!(!CSI::Cursor(Cursor::Up(1)).is_lossy());
+
+        // Recognized sequences are still interpreted, and don't
+        // carry the flag
+        let res: Vec<_> = CSI::parse(&[CsiParam::Integer(1)], true, 'A').collect();
+        assert_eq!(res, vec![CSI::Cursor(Cursor::Up(1))]);
+        assert!(!res[0].is_lossy());
+    }
 
-    ForegroundBrightBlack = 90,
-    ForegroundBrightRed = 91,
-    ForegroundBrightGreen = 92,
-    ForegroundBrightYellow = 93,
-    ForegroundBrightBlue = 94,
-    ForegroundBrightMagenta = 95,
-    ForegroundBrightCyan = 96,
-    ForegroundBrightWhite = 97,
+    #[test]
+    fn push_pop_colors() {
+        let res: Vec<_> = CSI::parse(&[CsiParam::P(b'#')], false, 'P').collect();
+        assert_eq!(res, vec![CSI::StateStack(StateStack::PushColors(vec![]))]);
+        assert_eq!(encode(&res), "\x1b[#P");
 
-    BackgroundBrightBlack = 100,
-    BackgroundBrightRed = 101,
-    BackgroundBrightGreen = 102,
-    BackgroundBrightYellow = 103,
-    BackgroundBrightBlue = 104,
-    BackgroundBrightMagenta = 105,
-    BackgroundBrightCyan = 106,
-    BackgroundBrightWhite = 107,
+        let res: Vec<_> = CSI::parse(
+            &[
+                CsiParam::Integer(2),
+                CsiParam::P(b';'),
+                CsiParam::Integer(10),
+                CsiParam::P(b'#'),
+            ],
+            false,
+            'P',
+        )
+        .collect();
+        assert_eq!(
+            res,
+            vec![CSI::StateStack(StateStack::PushColors(vec![2, 10]))]
+        );
+        assert_eq!(encode(&res), "\x1b[2;10#P");
+
+        let res: Vec<_> = CSI::parse(&[CsiParam::P(b'#')], false, 'Q').collect();
+        assert_eq!(res, vec![CSI::StateStack(StateStack::PopColors(vec![]))]);
+        assert_eq!(encode(&res), "\x1b[#Q");
+
+        let res: Vec<_> =
+            CSI::parse(&[CsiParam::Integer(3), CsiParam::P(b'#')], false, 'Q').collect();
+        assert_eq!(res, vec![CSI::StateStack(StateStack::PopColors(vec![3]))]);
+        assert_eq!(encode(&res), "\x1b[3#Q");
+
+        let res: Vec<_> = CSI::parse(&[CsiParam::P(b'#')], false, 'R').collect();
+        assert_eq!(res, vec![CSI::StateStack(StateStack::ReportColors)]);
+        assert_eq!(encode(&res), "\x1b[#R");
+
+        // Slots are in the range 1-10
+        let res: Vec<_> =
+            CSI::parse(&[CsiParam::Integer(11), CsiParam::P(b'#')], false, 'P').collect();
+        assert!(matches!(res[0], CSI::Unspecified(_)));
+        assert_eq!(encode(&res), "\x1b[11#P");
+
+        // Other controls with the `#` intermediate are not understood,
+        // and retain the intermediate
+        let res: Vec<_> =
+            CSI::parse(&[CsiParam::Integer(2), CsiParam::P(b'#')], false, 'J').collect();
+        assert!(matches!(res[0], CSI::Unspecified(_)));
+        assert_eq!(encode(&res), "\x1b[2#J");
+    }
 
-    /// Maybe followed either either a 256 color palette index or
-    /// a sequence describing a true color rgb value
-    ForegroundColor = 38,
-    BackgroundColor = 48,
-}
+    #[test]
+    fn push_pop_sgr() {
+        let res: Vec<_> = CSI::parse(
+            &[
+                CsiParam::Integer(1),
+                CsiParam::P(b';'),
+                CsiParam::Integer(30),
+                CsiParam::P(b';'),
+                CsiParam::Integer(31),
+                CsiParam::P(b'#'),
+            ],
+            false,
+            '{',
+        )
+        .collect();
+        assert_eq!(
+            res,
+            vec![CSI::StateStack(StateStack::PushSgr(vec![
+                SgrAspect::Bold,
+                SgrAspect::Foreground,
+                SgrAspect::Background
+            ]))]
+        );
+        assert_eq!(encode(&res), "\x1b[1;30;31#{");
 
-impl<'a> Iterator for CSIParser<'a> {
-    type Item = CSI;
+        let res: Vec<_> = CSI::parse(&[CsiParam::P(b'#')], false, '{').collect();
+        assert_eq!(res, vec![CSI::StateStack(StateStack::PushSgr(vec![]))]);
+        assert_eq!(encode(&res), "\x1b[#{");
 
-    fn next(&mut self) -> Option<CSI> {
-        let params = match self.params.take() {
-            None => return None,
-            Some(params) => params,
-        };
+        let res: Vec<_> = CSI::parse(&[CsiParam::P(b'#')], false, '}').collect();
+        assert_eq!(res, vec![CSI::StateStack(StateStack::PopSgr)]);
+        assert_eq!(encode(&res), "\x1b[#}");
 
-        match self.parse_next(&params) {
-            Ok(csi) => Some(csi),
-            Err(()) => Some(CSI::Unspecified(Box::new(Unspecified {
-                params: params.to_vec(),
-                parameters_truncated: self.parameters_truncated,
-                control: self.control,
-            }))),
+        // The `#p` and `#q` aliases keep their final byte
+        let res: Vec<_> = CSI::parse(
+            &[
+                CsiParam::Integer(1),
+                CsiParam::P(b';'),
+                CsiParam::Integer(30),
+                CsiParam::P(b'#'),
+            ],
+            false,
+            'p',
+        )
+        .collect();
+        assert_eq!(
+            res,
+            vec![CSI::StateStack(StateStack::PushSgrAlias(vec![
+                SgrAspect::Bold,
+                SgrAspect::Foreground
+            ]))]
+        );
+        assert_eq!(encode(&res), "\x1b[1;30#p");
+        let res: Vec<_> = CSI::parse(&[CsiParam::P(b'#')], false, 'p').collect();
+        assert_eq!(res, vec![CSI::StateStack(StateStack::PushSgrAlias(vec![]))]);
+        assert_eq!(encode(&res), "\x1b[#p");
+        let res: Vec<_> = CSI::parse(&[CsiParam::P(b'#')], false, 'q').collect();
+        assert_eq!(res, vec![CSI::StateStack(StateStack::PopSgrAlias)]);
+        assert_eq!(encode(&res), "\x1b[#q");
+
+        // 6 is not a valid aspect
+        let res: Vec<_> =
+            CSI::parse(&[CsiParam::Integer(6), CsiParam::P(b'#')], false, '{').collect();
+        assert_eq!(encode(&res), "\x1b[6#{");
+        assert!(matches!(res[0], CSI::Unspecified(_)));
+    }
+
+    #[cfg(feature = "use_serde")]
+    #[test]
+    fn serde_round_trip() {
+        fn round_trip(csi: CSI) -> String {
+            let json = serde_json::to_string(&csi).unwrap();
+            let decoded: CSI = serde_json::from_str(&json).unwrap();
+            assert_eq!(decoded, csi);
+            json
         }
+
+        assert_eq!(
+            round_trip(CSI::Sgr(Sgr::Intensity(Intensity::Bold))),
+            r#"{"Sgr":{"Intensity":"Bold"}}"#
+        );
+        assert_eq!(
+            round_trip(CSI::Sgr(Sgr::Foreground(ColorSpec::TrueColor(
+                RgbColor::new_8bpc(0xff, 0, 0)
+            )))),
+            r##"{"Sgr":{"Foreground":{"TrueColor":"#ff0000"}}}"##
+        );
+        assert_eq!(
+            round_trip(CSI::Cursor(Cursor::Position {
+                line: OneBased::new(2),
+                col: OneBased::new(3),
+            })),
+            r#"{"Cursor":{"Position":{"line":2,"col":3}}}"#
+        );
+        round_trip(CSI::Mode(Mode::SetDecPrivateMode(DecPrivateMode::Code(
+            DecPrivateModeCode::BracketedPaste,
+        ))));
+
+        let unspec: Vec<_> = CSI::parse(
+            &[CsiParam::P(b'<'), CsiParam::Integer(3), CsiParam::P(b'$')],
+            false,
+            'z',
+        )
+        .collect();
+        assert!(matches!(unspec[0], CSI::Unspecified(_)));
+        assert_eq!(
+            round_trip(unspec[0].clone()),
+            r#"{"Unspecified":{"params":[{"P":60},{"Integer":3},{"P":36}],"parameters_truncated":false,"control":"z"}}"#
+        );
+
+        let decoded: CSI = serde_json::from_str(r#"{"Sgr":{"Italic":true}}"#).unwrap();
+        assert_eq!(decoded, CSI::Sgr(Sgr::Italic(true)));
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use std::io::Write;
+    #[test]
+    fn status_display() {
+        assert_eq!(
+            parse_intermediate('~', b'$', &[2], "\x1b[2$~"),
+            vec![CSI::Device(Box::new(Device::SelectStatusDisplayType(
+                StatusDisplayType::HostWritable
+            )))]
+        );
+        assert_eq!(
+            parse_intermediate('~', b'$', &[], "\x1b[0$~"),
+            vec![CSI::Device(Box::new(Device::SelectStatusDisplayType(
+                StatusDisplayType::None
+            )))]
+        );
+        assert_eq!(
+            parse_intermediate('}', b'$', &[1], "\x1b[1$}"),
+            vec![CSI::Device(Box::new(Device::SelectActiveStatusDisplay(
+                ActiveStatusDisplay::StatusLine
+            )))]
+        );
+        assert_eq!(
+            parse_intermediate('}', b'$', &[0], "\x1b[0$}"),
+            vec![CSI::Device(Box::new(Device::SelectActiveStatusDisplay(
+                ActiveStatusDisplay::MainDisplay
+            )))]
+        );
 
-    fn parse(control: char, params: &[i64], expected: &str) -> Vec<CSI> {
-        let mut cparams = vec![];
-        for &p in params {
-            if !cparams.is_empty() {
-                cparams.push(CsiParam::P(b';'));
+        let res = parse_intermediate('~', b'$', &[3], "\x1b[3$~");
+        assert!(matches!(res[0], CSI::Unspecified(_)));
+        let res = parse_intermediate('}', b'$', &[2], "\x1b[2$}");
+        assert!(matches!(res[0], CSI::Unspecified(_)));
+        assert_eq!(
+            parse_intermediate('}', b'$', &[], "\x1b[0$}"),
+            vec![CSI::Device(Box::new(Device::SelectActiveStatusDisplay(
+                ActiveStatusDisplay::MainDisplay
+            )))]
+        );
+
+        assert_eq!(CSI::validate('~', b"$", &[1]), Ok(()));
+        assert_eq!(
+            CSI::validate('}', b"$", &[2]),
+            Err(CsiValidationError::ParameterOutOfRange {
+                control: '}',
+                index: 0,
+                value: 2
+            })
+        );
+    }
+
+    #[test]
+    fn deccra() {
+        let mut params = vec![];
+        for &p in &[2, 5, 10, 40, 1, 12, 5, 1] {
+            if !params.is_empty() {
+                params.push(CsiParam::P(b';'));
             }
-            cparams.push(CsiParam::Integer(p));
+            params.push(CsiParam::Integer(p));
         }
-        let res = CSI::parse(&cparams, false, control).collect();
-        println!("parsed -> {:#?}", res);
-        assert_eq!(encode(&res), expected);
-        res
+        params.push(CsiParam::P(b'$'));
+        let res: Vec<_> = CSI::parse(&params, false, 'v').collect();
+        assert_eq!(
+            res,
+            vec![CSI::Window(Window::CopyRectangularArea {
+                top: OneBased::new(2),
+                left: OneBased::new(5),
+                bottom: Some(OneBased::new(10)),
+                right: Some(OneBased::new(40)),
+                page: 1,
+                dest_top: OneBased::new(12),
+                dest_left: OneBased::new(5),
+                dest_page: 1,
+            })]
+        );
+        assert_eq!(encode(&res), "\x1b[2;5;10;40;1;12;5;1$v");
+
+        // Omitted parameters take on their defaults
+        let params = [CsiParam::P(b'$')];
+        let res: Vec<_> = CSI::parse(&params, false, 'v').collect();
+        assert_eq!(
+            res,
+            vec![CSI::Window(Window::CopyRectangularArea {
+                top: OneBased::new(1),
+                left: OneBased::new(1),
+                bottom: None,
+                right: None,
+                page: 1,
+                dest_top: OneBased::new(1),
+                dest_left: OneBased::new(1),
+                dest_page: 1,
+            })]
+        );
+        assert_eq!(encode(&res), "\x1b[1;1;;;1;1;1;1$v");
     }
 
-    fn encode(seq: &Vec<CSI>) -> String {
-        let mut res = Vec::new();
-        for s in seq {
-            write!(res, "{}", s).unwrap();
-        }
-        String::from_utf8(res).unwrap()
+    #[test]
+    fn clear_below() {
+        let seq = super::clear_below(0);
+        assert_eq!(
+            seq,
+            vec![
+                CSI::Cursor(Cursor::CharacterAbsolute(OneBased::new(1))),
+                CSI::Edit(Edit::EraseInDisplay(EraseInDisplay::EraseToEndOfDisplay)),
+            ]
+        );
+        assert_eq!(encode(&seq), "\x1b[G\x1b[J");
+        assert_eq!(encode(&super::clear_below(9)), "\x1b[10G\x1b[J");
+    }
+
+    #[test]
+    fn dec_dsr() {
+        let res: Vec<_> =
+            CSI::parse(&[CsiParam::P(b'?'), CsiParam::Integer(15)], false, 'n').collect();
+        assert_eq!(
+            res,
+            vec![CSI::Device(Box::new(Device::RequestPrinterStatus))]
+        );
+        assert_eq!(encode(&res), "\x1b[?15n");
+
+        let res: Vec<_> =
+            CSI::parse(&[CsiParam::P(b'?'), CsiParam::Integer(25)], false, 'n').collect();
+        assert_eq!(
+            res,
+            vec![CSI::Device(Box::new(Device::RequestUserDefinedKeysStatus))]
+        );
+        assert_eq!(encode(&res), "\x1b[?25n");
+
+        let res: Vec<_> =
+            CSI::parse(&[CsiParam::P(b'?'), CsiParam::Integer(6)], false, 'n').collect();
+        assert_eq!(
+            res,
+            vec![CSI::Cursor(Cursor::RequestExtendedActivePositionReport)]
+        );
+        assert_eq!(encode(&res), "\x1b[?6n");
+
+        let params = [
+            CsiParam::P(b'?'),
+            CsiParam::Integer(3),
+            CsiParam::P(b';'),
+            CsiParam::Integer(7),
+            CsiParam::P(b';'),
+            CsiParam::Integer(1),
+        ];
+        let res: Vec<_> = CSI::parse(&params, false, 'R').collect();
+        assert_eq!(
+            res,
+            vec![CSI::Cursor(Cursor::ExtendedActivePositionReport {
+                line: OneBased::new(3),
+                col: OneBased::new(7),
+                page: OneBased::new(1),
+            })]
+        );
+        assert_eq!(encode(&res), "\x1b[?3;7;1R");
+        let res: Vec<_> = CSI::parse(&params[..4], false, 'R').collect();
+        assert!(matches!(res[0], CSI::Unspecified(_)));
+
+        let res: Vec<_> =
+            CSI::parse(&[CsiParam::P(b'?'), CsiParam::Integer(5)], false, 'n').collect();
+        assert!(matches!(res[0], CSI::Unspecified(_)));
+        assert_eq!(encode(&res), "\x1b[?5n");
+
+        // Without the marker, these are the ANSI forms
+        assert_eq!(
+            parse('n', &[5], "\x1b[5n"),
+            vec![CSI::Device(Box::new(Device::StatusReport))]
+        );
+        assert_eq!(
+            parse('n', &[6], "\x1b[6n"),
+            vec![CSI::Cursor(Cursor::RequestActivePositionReport)]
+        );
+        assert!(matches!(
+            parse('n', &[15], "\x1b[15n")[0],
+            CSI::Unspecified(_)
+        ));
+    }
+
+    #[test]
+    fn decll() {
+        assert_eq!(
+            parse('q', &[], "\x1b[0q"),
+            vec![CSI::Device(Box::new(Device::SetKeyboardLeds(vec![0])))]
+        );
+        assert_eq!(
+            parse('q', &[1, 2], "\x1b[1;2q"),
+            vec![CSI::Device(Box::new(Device::SetKeyboardLeds(vec![1, 2])))]
+        );
+        assert_eq!(
+            parse('q', &[0, 3, 21], "\x1b[0;3;21q"),
+            vec![CSI::Device(Box::new(Device::SetKeyboardLeds(vec![
+                0, 3, 21
+            ])))]
+        );
+
+        let res = parse('q', &[1, 5], "\x1b[1;5q");
+        assert!(matches!(res[0], CSI::Unspecified(_)));
+
+        assert_eq!(CSI::validate('q', b"", &[1, 2, 24]), Ok(()));
+        assert_eq!(
+            CSI::validate('q', b"", &[1, 5]),
+            Err(CsiValidationError::ParameterOutOfRange {
+                control: 'q',
+                index: 1,
+                value: 5
+            })
+        );
     }
 
     #[test]
-    fn basic() {
-        assert_eq!(parse('m', &[], "\x1b[0m"), vec![CSI::Sgr(Sgr::Reset)]);
-        assert_eq!(parse('m', &[0], "\x1b[0m"), vec![CSI::Sgr(Sgr::Reset)]);
+    fn try_parse() {
+        let try_parse = |params: &[CsiParam], control: char| -> Vec<_> {
+            CSI::try_parse(params, false, control).collect()
+        };
+
         assert_eq!(
-            parse('m', &[1], "\x1b[1m"),
-            vec![CSI::Sgr(Sgr::Intensity(Intensity::Bold))]
+            try_parse(&[CsiParam::Integer(3)], 'A'),
+            vec![Ok(CSI::Cursor(Cursor::Up(3)))]
         );
         assert_eq!(
-            parse('m', &[1, 3], "\x1b[1m\x1b[3m"),
-            vec![
-                CSI::Sgr(Sgr::Intensity(Intensity::Bold)),
-                CSI::Sgr(Sgr::Italic(true)),
-            ]
+            try_parse(&[CsiParam::Integer(1)], 'y'),
+            vec![Err(CsiParseError::UnknownControl {
+                control: 'y',
+                intermediates: vec![],
+            })]
+        );
+        assert_eq!(
+            try_parse(&[CsiParam::P(b'?'), CsiParam::Integer(2)], 'J'),
+            vec![Err(CsiParseError::UnknownControl {
+                control: 'J',
+                intermediates: vec![b'?'],
+            })]
+        );
+        assert_eq!(
+            try_parse(&[CsiParam::Integer(1), CsiParam::P(b'$')], 'z'),
+            vec![Err(CsiParseError::UnknownControl {
+                control: 'z',
+                intermediates: vec![b'$'],
+            })]
+        );
+        assert_eq!(
+            try_parse(
+                &[
+                    CsiParam::Integer(2),
+                    CsiParam::P(b';'),
+                    CsiParam::Integer(3)
+                ],
+                'T'
+            ),
+            vec![Err(CsiParseError::WrongParameterCount { count: 2 })]
+        );
+        assert_eq!(
+            try_parse(&[CsiParam::Integer(7)], 'J'),
+            vec![Err(CsiParseError::OutOfRangeParameter { value: 7 })]
+        );
+        assert_eq!(
+            try_parse(&[CsiParam::P(b';'), CsiParam::Integer(5)], 't'),
+            vec![Err(CsiParseError::MissingParameter { index: 0 })]
+        );
+        assert_eq!(
+            try_parse(
+                &[
+                    CsiParam::Integer(3),
+                    CsiParam::P(b':'),
+                    CsiParam::Integer(1)
+                ],
+                'm'
+            ),
+            vec![Err(CsiParseError::UnexpectedParameter(CsiParam::P(b':')))]
         );
 
-        // Verify that we propagate Unspecified for codes
-        // that we don't recognize.
+        let mut sgr = vec![];
+        for (idx, p) in [1, 38, 5, 300].iter().enumerate() {
+            if idx > 0 {
+                sgr.push(CsiParam::P(b';'));
+            }
+            sgr.push(CsiParam::Integer(*p));
+        }
         assert_eq!(
-            parse('m', &[1, 3, 1231231], "\x1b[1m\x1b[3m\x1b[1231231m"),
+            try_parse(&sgr, 'm'),
             vec![
-                CSI::Sgr(Sgr::Intensity(Intensity::Bold)),
-                CSI::Sgr(Sgr::Italic(true)),
-                CSI::Unspecified(Box::new(Unspecified {
-                    params: [CsiParam::Integer(1231231)].to_vec(),
-                    parameters_truncated: false,
-                    control: 'm',
-                })),
+                Ok(CSI::Sgr(Sgr::Intensity(Intensity::Bold))),
+                Err(CsiParseError::OutOfRangeParameter { value: 300 }),
             ]
         );
+
+        // The subparameter group is skipped over and parsing continues
+        // after it, as it does for `CSI::parse`
         assert_eq!(
-            parse('m', &[1, 1231231, 3], "\x1b[1m\x1b[1231231;3m"),
+            try_parse(
+                &[
+                    CsiParam::Integer(38),
+                    CsiParam::P(b':'),
+                    CsiParam::Integer(9),
+                    CsiParam::P(b';'),
+                    CsiParam::Integer(1),
+                    CsiParam::P(b';'),
+                    CsiParam::Integer(77)
+                ],
+                'm'
+            ),
             vec![
-                CSI::Sgr(Sgr::Intensity(Intensity::Bold)),
-                CSI::Unspecified(Box::new(Unspecified {
-                    params: [
-                        CsiParam::Integer(1231231),
-                        CsiParam::P(b';'),
-                        CsiParam::Integer(3)
-                    ]
-                    .to_vec(),
-                    parameters_truncated: false,
-                    control: 'm',
-                })),
+                Err(CsiParseError::OutOfRangeParameter { value: 9 }),
+                Ok(CSI::Sgr(Sgr::Intensity(Intensity::Bold))),
+                Err(CsiParseError::UnknownSgrCode(77)),
             ]
         );
         assert_eq!(
-            parse('m', &[1231231, 3], "\x1b[1231231;3m"),
-            vec![CSI::Unspecified(Box::new(Unspecified {
-                params: [
-                    CsiParam::Integer(1231231),
-                    CsiParam::P(b';'),
-                    CsiParam::Integer(3)
-                ]
-                .to_vec(),
-                parameters_truncated: false,
-                control: 'm',
-            }))]
+            CsiParseError::UnknownSgrCode(77).to_string(),
+            "unknown SGR code 77"
         );
     }
 
     #[test]
-    fn blinks() {
-        assert_eq!(
-            parse('m', &[5], "\x1b[5m"),
-            vec![CSI::Sgr(Sgr::Blink(Blink::Slow))]
-        );
+    fn parse_with_handler() {
+        // A made-up `CSI ? 42 z` sequence that the handler maps to a soft reset
+        let handler = |unspec: &Unspecified| match (unspec.control, unspec.params.as_slice()) {
+            ('z', [CsiParam::P(b'?'), CsiParam::Integer(42)]) => {
+                Some(CSI::Device(Box::new(Device::SoftReset)))
+            }
+            _ => None,
+        };
+
+        let res: Vec<_> = CSI::parse_with_handler(
+            &[CsiParam::P(b'?'), CsiParam::Integer(42)],
+            false,
+            'z',
+            handler,
+        )
+        .collect();
+        assert_eq!(res, vec![CSI::Device(Box::new(Device::SoftReset))]);
+
+        // Sequences that the handler doesn't recognize remain unspecified
+        let res: Vec<_> = CSI::parse_with_handler(
+            &[CsiParam::P(b'?'), CsiParam::Integer(7)],
+            false,
+            'z',
+            handler,
+        )
+        .collect();
+        assert_eq!(encode(&res), "\x1b[?7z");
+        assert!(matches!(res[0], CSI::Unspecified(_)));
+
+        // and known sequences don't reach the handler at all
+        let res: Vec<_> = CSI::parse_with_handler(&[CsiParam::Integer(1)], false, 'm', |_| {
+            panic!("should not be called")
+        })
+        .collect();
+        assert_eq!(res, vec![CSI::Sgr(Sgr::Intensity(Intensity::Bold))]);
+    }
+
+    #[test]
+    fn cursor_style() {
+        let styles = [
+            CursorStyle::Default,
+            CursorStyle::BlinkingBlock,
+            CursorStyle::SteadyBlock,
+            CursorStyle::BlinkingUnderline,
+            CursorStyle::SteadyUnderline,
+            CursorStyle::BlinkingBar,
+            CursorStyle::SteadyBar,
+        ];
+        for (n, style) in styles.iter().enumerate() {
+            let res: Vec<_> = CSI::parse(
+                &[CsiParam::Integer(n as i64), CsiParam::P(b' ')],
+                false,
+                'q',
+            )
+            .collect();
+            assert_eq!(res, vec![CSI::Cursor(Cursor::CursorStyle(*style))]);
+            assert_eq!(encode(&res), format!("\x1b[{} q", n));
+        }
+
         assert_eq!(
-            parse('m', &[6], "\x1b[6m"),
-            vec![CSI::Sgr(Sgr::Blink(Blink::Rapid))]
+            styles
+                .iter()
+                .map(|style| (style.shape(), style.blinking()))
+                .collect::<Vec<_>>(),
+            vec![
+                (CursorStyleShape::Default, false),
+                (CursorStyleShape::Block, true),
+                (CursorStyleShape::Block, false),
+                (CursorStyleShape::Underline, true),
+                (CursorStyleShape::Underline, false),
+                (CursorStyleShape::Bar, true),
+                (CursorStyleShape::Bar, false),
+            ]
         );
+
+        let res: Vec<_> = CSI::parse(&[CsiParam::P(b' ')], false, 'q').collect();
         assert_eq!(
-            parse('m', &[25], "\x1b[25m"),
-            vec![CSI::Sgr(Sgr::Blink(Blink::None))]
+            res,
+            vec![CSI::Cursor(Cursor::CursorStyle(CursorStyle::Default))]
         );
+        assert_eq!(encode(&res), "\x1b[0 q");
+
+        let res: Vec<_> =
+            CSI::parse(&[CsiParam::Integer(7), CsiParam::P(b' ')], false, 'q').collect();
+        assert!(matches!(res[0], CSI::Unspecified(_)));
+        assert_eq!(encode(&res), "\x1b[7 q");
     }
 
     #[test]
-    fn underlines() {
-        assert_eq!(
-            parse('m', &[21], "\x1b[21m"),
-            vec![CSI::Sgr(Sgr::Underline(Underline::Double))]
-        );
+    fn unknown_space_intermediate() {
+        for control in &['~', '@', 'A', 'm', 'q'] {
+            let params = [CsiParam::Integer(5), CsiParam::P(b' ')];
+            let res: Vec<_> = CSI::parse(&params, false, *control).collect();
+            if *control == 'q' {
+                // DECSCUSR is known
+                assert_eq!(
+                    res,
+                    vec![CSI::Cursor(Cursor::CursorStyle(CursorStyle::BlinkingBar))]
+                );
+                continue;
+            }
+            assert_eq!(
+                res,
+                vec![CSI::Unspecified(Box::new(Unspecified {
+                    params: params.to_vec(),
+                    parameters_truncated: false,
+                    control: *control,
+                    payload: String::new(),
+                }))]
+            );
+            assert_eq!(encode(&res), format!("\x1b[5 {}", control));
+        }
+    }
+
+    #[test]
+    fn space_intermediate_table() {
+        // SPI needs two parameters, so it isn't recognized with just one
+        let known = "BFPQR^q";
+        for control in (0x40u8..=0x7e).map(char::from) {
+            let params = [CsiParam::Integer(5), CsiParam::P(b' ')];
+            let res: Vec<_> = CSI::parse(&params, false, control).collect();
+            assert_eq!(res.len(), 1);
+            assert_eq!(
+                !matches!(res[0], CSI::Unspecified(_)),
+                known.contains(control),
+                "{:?} -> {:?}",
+                control,
+                res
+            );
+            if control == 'B' {
+                // GSM re-encodes the defaulted width
+                continue;
+            }
+            assert_eq!(encode(&res), format!("\x1b[5 {}", control));
+        }
+
         assert_eq!(
-            parse('m', &[4], "\x1b[4m"),
-            vec![CSI::Sgr(Sgr::Underline(Underline::Single))]
+            CSI::parse(&[CsiParam::Integer(3), CsiParam::P(b' ')], false, '^').collect::<Vec<_>>(),
+            vec![CSI::Presentation(Presentation::SelectiveTabulation(3))]
         );
+        let res: Vec<_> = CSI::parse(&[CsiParam::P(b' ')], false, '^').collect();
+        assert!(matches!(res[0], CSI::Unspecified(_)));
+        assert_eq!(encode(&res), "\x1b[ ^");
     }
 
     #[test]
-    fn underline_color() {
+    fn graphic_size_modification() {
+        let res: Vec<_> = CSI::parse(
+            &[
+                CsiParam::Integer(200),
+                CsiParam::P(b';'),
+                CsiParam::Integer(50),
+                CsiParam::P(b' '),
+            ],
+            false,
+            'B',
+        )
+        .collect();
         assert_eq!(
-            parse('m', &[58, 2], "\x1b[58;2m"),
-            vec![CSI::Unspecified(Box::new(Unspecified {
-                params: [
-                    CsiParam::Integer(58),
-                    CsiParam::P(b';'),
-                    CsiParam::Integer(2)
-                ]
-                .to_vec(),
-                parameters_truncated: false,
-                control: 'm',
-            }))]
+            res,
+            vec![CSI::Presentation(Presentation::GraphicSizeModification {
+                height: 200,
+                width: 50
+            })]
         );
+        assert_eq!(encode(&res), "\x1b[200;50 B");
 
+        let res: Vec<_> = CSI::parse(&[CsiParam::P(b' ')], false, 'B').collect();
         assert_eq!(
-            parse('m', &[58, 2, 255, 255, 255], "\x1b[58:2::255:255:255m"),
-            vec![CSI::Sgr(Sgr::UnderlineColor(ColorSpec::TrueColor(
-                RgbColor::new_8bpc(255, 255, 255),
-            )))]
+            res,
+            vec![CSI::Presentation(Presentation::GraphicSizeModification {
+                height: 100,
+                width: 100
+            })]
         );
+        assert_eq!(encode(&res), "\x1b[100;100 B");
+
+        // Without the intermediate, this is still cursor down
         assert_eq!(
-            parse('m', &[58, 5, 220, 255, 255], "\x1b[58:5:220m\x1b[255;255m"),
-            vec![
-                CSI::Sgr(Sgr::UnderlineColor(ColorSpec::PaletteIndex(220))),
-                CSI::Unspecified(Box::new(Unspecified {
-                    params: [
-                        CsiParam::Integer(255),
-                        CsiParam::P(b';'),
-                        CsiParam::Integer(255)
-                    ]
-                    .to_vec(),
-                    parameters_truncated: false,
-                    control: 'm',
-                })),
-            ]
+            parse('B', &[2], "\x1b[2B"),
+            vec![CSI::Cursor(Cursor::Down(2))]
         );
     }
 
     #[test]
-    fn color() {
+    fn page_position() {
         assert_eq!(
-            parse('m', &[38, 2], "\x1b[38;2m"),
-            vec![CSI::Unspecified(Box::new(Unspecified {
-                params: [
-                    CsiParam::Integer(38),
-                    CsiParam::P(b';'),
-                    CsiParam::Integer(2)
-                ]
-                .to_vec(),
-                parameters_truncated: false,
-                control: 'm',
-            }))]
+            parse_intermediate('P', b' ', &[3], "\x1b[3 P"),
+            vec![CSI::Presentation(Presentation::PagePositionAbsolute(3))]
         );
-
         assert_eq!(
-            parse('m', &[38, 2, 255, 255, 255], "\x1b[38:2::255:255:255m"),
-            vec![CSI::Sgr(Sgr::Foreground(ColorSpec::TrueColor(
-                RgbColor::new_8bpc(255, 255, 255),
-            )))]
+            parse_intermediate('P', b' ', &[], "\x1b[1 P"),
+            vec![CSI::Presentation(Presentation::PagePositionAbsolute(1))]
         );
         assert_eq!(
-            parse('m', &[38, 5, 220, 255, 255], "\x1b[38:5:220m\x1b[255;255m"),
-            vec![
-                CSI::Sgr(Sgr::Foreground(ColorSpec::PaletteIndex(220))),
-                CSI::Unspecified(Box::new(Unspecified {
-                    params: [
-                        CsiParam::Integer(255),
-                        CsiParam::P(b';'),
-                        CsiParam::Integer(255)
-                    ]
-                    .to_vec(),
-                    parameters_truncated: false,
-                    control: 'm',
-                })),
-            ]
+            parse_intermediate('Q', b' ', &[2], "\x1b[2 Q"),
+            vec![CSI::Presentation(Presentation::PagePositionForward(2))]
+        );
+        assert_eq!(
+            parse_intermediate('Q', b' ', &[], "\x1b[1 Q"),
+            vec![CSI::Presentation(Presentation::PagePositionForward(1))]
+        );
+        assert_eq!(
+            parse_intermediate('R', b' ', &[4], "\x1b[4 R"),
+            vec![CSI::Presentation(Presentation::PagePositionBackward(4))]
+        );
+        assert_eq!(
+            parse_intermediate('R', b' ', &[], "\x1b[1 R"),
+            vec![CSI::Presentation(Presentation::PagePositionBackward(1))]
+        );
+
+        // Without the intermediate, P is still DCH
+        assert_eq!(
+            parse('P', &[2], "\x1b[2P"),
+            vec![CSI::Edit(Edit::DeleteCharacter(2))]
         );
     }
 
     #[test]
-    fn edit() {
+    fn justify_and_spacing_increment() {
         assert_eq!(
-            parse('J', &[], "\x1b[J"),
-            vec![CSI::Edit(Edit::EraseInDisplay(
-                EraseInDisplay::EraseToEndOfDisplay,
-            ))]
+            parse_intermediate('F', b' ', &[2, 6], "\x1b[2;6 F"),
+            vec![CSI::Presentation(Presentation::Justify(vec![2, 6]))]
         );
         assert_eq!(
-            parse('J', &[0], "\x1b[J"),
-            vec![CSI::Edit(Edit::EraseInDisplay(
-                EraseInDisplay::EraseToEndOfDisplay,
-            ))]
+            parse_intermediate('F', b' ', &[], "\x1b[0 F"),
+            vec![CSI::Presentation(Presentation::Justify(vec![0]))]
         );
+        assert!(matches!(
+            parse_intermediate('F', b' ', &[9], "\x1b[9 F")[0],
+            CSI::Unspecified(_)
+        ));
+
         assert_eq!(
-            parse('J', &[1], "\x1b[1J"),
-            vec![CSI::Edit(Edit::EraseInDisplay(
-                EraseInDisplay::EraseToStartOfDisplay,
-            ))]
+            parse_intermediate('G', b' ', &[12, 5], "\x1b[12;5 G"),
+            vec![CSI::Presentation(Presentation::SpacingIncrement {
+                line: 12,
+                character: 5
+            })]
+        );
+        assert!(matches!(
+            parse_intermediate('G', b' ', &[12], "\x1b[12 G")[0],
+            CSI::Unspecified(_)
+        ));
+
+        // Without the intermediate, these are CPL and CHA
+        assert_eq!(
+            parse('F', &[2], "\x1b[2F"),
+            vec![CSI::Cursor(Cursor::PrecedingLine(2))]
+        );
+        assert_eq!(
+            parse('G', &[2], "\x1b[2G"),
+            vec![CSI::Cursor(Cursor::CharacterAbsolute(OneBased::new(2)))]
         );
     }
 
@@ -2705,6 +6535,22 @@ mod test {
                 TerminalModeCode::AutomaticNewline
             )))]
         );
+        assert_eq!(
+            parse('h', &[4], "\x1b[4h"),
+            vec![CSI::Mode(Mode::SetMode(TerminalMode::Code(
+                TerminalModeCode::Insert
+            )))]
+        );
+        assert_eq!(
+            parse('l', &[4], "\x1b[4l"),
+            vec![CSI::Mode(Mode::ResetMode(TerminalMode::Code(
+                TerminalModeCode::Insert
+            )))]
+        );
+        assert_eq!(
+            parse('h', &[33], "\x1b[33h"),
+            vec![CSI::Mode(Mode::SetMode(TerminalMode::Unspecified(33)))]
+        );
     }
 
     #[test]
@@ -2739,6 +6585,16 @@ mod test {
         let res: Vec<_> = CSI::parse(&[CsiParam::P(b'!')], false, 'p').collect();
         assert_eq!(encode(&res), "\x1b[!p");
         assert_eq!(res, vec![CSI::Device(Box::new(Device::SoftReset))],);
+
+        // DECSTR takes no parameters, and other controls with the `!`
+        // intermediate are unknown; both are preserved as Unspecified
+        let res: Vec<_> =
+            CSI::parse(&[CsiParam::Integer(1), CsiParam::P(b'!')], false, 'p').collect();
+        assert!(matches!(res[0], CSI::Unspecified(_)));
+        assert_eq!(encode(&res), "\x1b[1!p");
+        let res: Vec<_> = CSI::parse(&[CsiParam::P(b'!')], false, 'q').collect();
+        assert!(matches!(res[0], CSI::Unspecified(_)));
+        assert_eq!(encode(&res), "\x1b[!q");
     }
 
     #[test]
@@ -2783,4 +6639,108 @@ mod test {
         );
         assert_eq!(encode(&res), "\x1b[?63;1;2;4;6;9;15;22c");
     }
+
+    #[test]
+    fn device_attr_capabilities() {
+        // The response from a VT220
+        let res: Vec<_> = CSI::parse(
+            &[
+                CsiParam::P(b'?'),
+                CsiParam::Integer(62),
+                CsiParam::P(b';'),
+                CsiParam::Integer(1),
+                CsiParam::P(b';'),
+                CsiParam::Integer(2),
+                CsiParam::P(b';'),
+                CsiParam::Integer(6),
+                CsiParam::P(b';'),
+                CsiParam::Integer(8),
+                CsiParam::P(b';'),
+                CsiParam::Integer(9),
+                CsiParam::P(b';'),
+                CsiParam::Integer(99),
+            ],
+            false,
+            'c',
+        )
+        .collect();
+        assert_eq!(encode(&res), "\x1b[?62;1;2;6;8;9;99c");
+
+        let caps = match &res[0] {
+            CSI::Device(dev) => match &**dev {
+                Device::DeviceAttributes(attr) => attr.capabilities(),
+                _ => panic!("unexpected {:?}", dev),
+            },
+            _ => panic!("unexpected {:?}", res),
+        };
+        assert_eq!(
+            caps,
+            vec![
+                DaCapability::Vt220,
+                DaCapability::Attribute(DeviceAttributeCodes::Columns132),
+                DaCapability::Attribute(DeviceAttributeCodes::Printer),
+                DaCapability::Attribute(DeviceAttributeCodes::SelectiveErase),
+                DaCapability::Attribute(DeviceAttributeCodes::UserDefinedKeys),
+                DaCapability::Attribute(DeviceAttributeCodes::NationalReplacementCharsets),
+                DaCapability::Unknown(CsiParam::Integer(99)),
+            ]
+        );
+
+        assert_eq!(
+            DeviceAttributes::Vt102.capabilities(),
+            vec![DaCapability::Vt102]
+        );
+    }
+
+    #[test]
+    fn sgr_code_describe() {
+        assert_eq!(
+            SgrCode::ForegroundBrightRed.describe(),
+            "bright red foreground"
+        );
+        assert_eq!(SgrCode::Reset.describe(), "reset");
+
+        for i in 0..=255 {
+            let code: Option<SgrCode> = FromPrimitive::from_i64(i);
+            if let Some(code) = code {
+                assert!(!code.describe().is_empty(), "{:?}", code);
+            }
+        }
+    }
+
+    #[test]
+    fn display_output() {
+        let cases = vec![
+            (CSI::Sgr(Sgr::Reset), "\x1b[0m"),
+            (CSI::Sgr(Sgr::Intensity(Intensity::Bold)), "\x1b[1m"),
+            (
+                CSI::Sgr(Sgr::Foreground(ColorSpec::PaletteIndex(200))),
+                "\x1b[38:5:200m",
+            ),
+            (
+                CSI::Cursor(Cursor::Position {
+                    line: OneBased::new(3),
+                    col: OneBased::new(7),
+                }),
+                "\x1b[3;7H",
+            ),
+            (CSI::Cursor(Cursor::SaveCursor), "\x1b[s"),
+            (
+                CSI::Mode(Mode::SetDecPrivateMode(DecPrivateMode::Code(
+                    DecPrivateModeCode::BracketedPaste,
+                ))),
+                "\x1b[?2004h",
+            ),
+        ];
+        for (seq, expected) in cases {
+            assert_eq!(format!("{}", seq), expected, "{:?}", seq);
+        }
+
+        // The sub-enums render the part after the `ESC [` introducer,
+        // which the CSI impl prepends
+        let sgr = Sgr::Underline(Underline::Double);
+        assert_eq!(format!("\x1b[{}", sgr), CSI::Sgr(sgr).to_string());
+        let cursor = Cursor::Left(4);
+        assert_eq!(format!("\x1b[{}", cursor), CSI::Cursor(cursor).to_string());
+    }
 }
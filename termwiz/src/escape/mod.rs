@@ -7,6 +7,8 @@
 //! escape sequences.  It provides encoding and decoding functionality
 //! only; it does not provide terminal emulation facilities itself.
 use num_derive::*;
+#[cfg(feature = "use_serde")]
+use serde::{Deserialize, Serialize};
 use std::fmt::{Display, Error as FmtError, Formatter, Write as FmtWrite};
 
 pub mod apc;
@@ -142,7 +144,8 @@ pub struct EnterDeviceControlMode {
     pub params: Vec<i64>,
     pub intermediates: Vec<u8>,
     /// if true, more than two intermediates arrived and the
-    /// remaining data was ignored
+    /// remaining data was ignored, so `intermediates` does not
+    /// faithfully represent the original sequence.
     pub ignored_extra_intermediates: bool,
 }
 
@@ -480,8 +483,54 @@ pub enum ControlCode {
     APC = 0x9f,
 }
 
+/// Identifies the kind of sequence that begins at the start of
+/// a byte stream.  See `classify_introducer`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Introducer {
+    /// `ESC [` or the 8-bit C1 CSI
+    Csi,
+    /// `ESC ]` or the 8-bit C1 OSC
+    Osc,
+    /// `ESC P` or the 8-bit C1 DCS.  Sixel data is carried in DCS.
+    Dcs,
+    /// `ESC _` or the 8-bit C1 APC
+    Apc,
+    /// `ESC N` or the 8-bit C1 SS2
+    SingleShiftTwo,
+    /// `ESC O` or the 8-bit C1 SS3
+    SingleShiftThree,
+    /// Some other escape sequence, such as `ESC 7`
+    Esc,
+    /// A lone `ESC`; more data is required to classify it
+    Incomplete,
+    /// The data doesn't start with an introducer
+    None,
+}
+
+/// Classifies the sequence introducer at the start of `bytes`.
+/// The CSI parser in this crate only deals with the parameters
+/// of CSI sequences; callers that are splitting up a raw stream
+/// can use this to route DCS (eg: sixel), OSC and other sequences
+/// to the appropriate place rather than mistaking them for CSI.
+/// Both the 7-bit `ESC` prefixed and the 8-bit C1 forms are recognized.
+pub fn classify_introducer(bytes: &[u8]) -> Introducer {
+    match bytes {
+        [0x1b, b'[', ..] | [0x9b, ..] => Introducer::Csi,
+        [0x1b, b']', ..] | [0x9d, ..] => Introducer::Osc,
+        [0x1b, b'P', ..] | [0x90, ..] => Introducer::Dcs,
+        [0x1b, b'_', ..] | [0x9f, ..] => Introducer::Apc,
+        [0x1b, b'N', ..] | [0x8e, ..] => Introducer::SingleShiftTwo,
+        [0x1b, b'O', ..] | [0x8f, ..] => Introducer::SingleShiftThree,
+        [0x1b] => Introducer::Incomplete,
+        [0x1b, ..] => Introducer::Esc,
+        _ => Introducer::None,
+    }
+}
+
 /// A helper type to avoid accidentally tripping over problems with
 /// 1-based values in escape sequences.
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "use_serde", serde(transparent))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct OneBased {
     value: u32,
@@ -548,3 +597,25 @@ impl Display for OneBased {
         self.value.fmt(f)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn introducers() {
+        assert_eq!(classify_introducer(b"\x1b[1m"), Introducer::Csi);
+        assert_eq!(classify_introducer(b"\x9b1m"), Introducer::Csi);
+        assert_eq!(classify_introducer(b"\x1b]0;title\x07"), Introducer::Osc);
+        assert_eq!(classify_introducer(b"\x1bPq#0;2;0;0;0"), Introducer::Dcs);
+        assert_eq!(classify_introducer(b"\x90q"), Introducer::Dcs);
+        assert_eq!(classify_introducer(b"\x1b_Gf=24"), Introducer::Apc);
+        assert_eq!(classify_introducer(b"\x1bNa"), Introducer::SingleShiftTwo);
+        assert_eq!(classify_introducer(b"\x1bOP"), Introducer::SingleShiftThree);
+        assert_eq!(classify_introducer(b"\x8fP"), Introducer::SingleShiftThree);
+        assert_eq!(classify_introducer(b"\x1b7"), Introducer::Esc);
+        assert_eq!(classify_introducer(b"\x1b"), Introducer::Incomplete);
+        assert_eq!(classify_introducer(b"hello"), Introducer::None);
+        assert_eq!(classify_introducer(b""), Introducer::None);
+    }
+}
@@ -63,6 +63,39 @@ pub enum DynamicColorNumber {
     HighlightForegroundColor = 19,
 }
 
+/// Parses the color from a terminal's response to an OSC 10, 11 or 12
+/// query, which report the default foreground, background and text
+/// cursor colors respectively.
+/// `payload` is the portion of the response that follows the `;`,
+/// which is typically of the form `rgb:RRRR/GGGG/BBBB`.
+/// Returns None if `osc_code` isn't one of those codes, or if
+/// the payload couldn't be parsed.
+pub fn parse_osc_default_color(osc_code: u8, payload: &str) -> Option<RgbColor> {
+    match FromPrimitive::from_u8(osc_code)? {
+        DynamicColorNumber::TextForegroundColor
+        | DynamicColorNumber::TextBackgroundColor
+        | DynamicColorNumber::TextCursorColor => RgbColor::from_rgb_str(payload),
+        _ => None,
+    }
+}
+
+/// Returns the `OSC 2` sequence that sets the window title to `title`.
+/// Control characters are removed from `title`, so that it cannot
+/// terminate the sequence early or smuggle in other sequences.
+pub fn set_title(title: &str) -> String {
+    OperatingSystemCommand::SetWindowTitle(strip_control_chars(title)).to_string()
+}
+
+/// Returns the `OSC 1` sequence that sets the icon title to `title`.
+/// As with `set_title`, control characters are removed from `title`.
+pub fn set_icon_title(title: &str) -> String {
+    OperatingSystemCommand::SetIconName(strip_control_chars(title)).to_string()
+}
+
+fn strip_control_chars(s: &str) -> String {
+    s.chars().filter(|c| !c.is_control()).collect()
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ChangeColorPair {
     pub palette_index: u8,
@@ -1271,6 +1304,35 @@ mod test {
         );
     }
 
+    #[test]
+    fn default_color_report() {
+        assert_eq!(
+            parse_osc_default_color(11, "rgb:1e1e/1e1e/1e1e"),
+            Some(RgbColor::new_8bpc(0x1e, 0x1e, 0x1e))
+        );
+        assert_eq!(
+            parse_osc_default_color(10, "rgb:d0d0/c8c8/c0c0"),
+            Some(RgbColor::new_8bpc(0xd0, 0xc8, 0xc0))
+        );
+        assert_eq!(
+            parse_osc_default_color(12, "rgb:52/ad/70"),
+            Some(RgbColor::new_8bpc(0x52, 0xad, 0x70))
+        );
+        assert_eq!(parse_osc_default_color(13, "rgb:1e1e/1e1e/1e1e"), None);
+        assert_eq!(parse_osc_default_color(4, "rgb:1e1e/1e1e/1e1e"), None);
+        assert_eq!(parse_osc_default_color(11, "?"), None);
+    }
+
+    #[test]
+    fn set_title() {
+        assert_eq!(super::set_title("hello"), "\x1b]2;hello\x1b\\");
+        assert_eq!(super::set_icon_title("hello"), "\x1b]1;hello\x1b\\");
+        assert_eq!(
+            super::set_title("evil\x07\x1b]0;pwned\x1b\\ \u{9c}\u{1f915}"),
+            "\x1b]2;evil]0;pwned\\ \u{1f915}\x1b\\"
+        );
+    }
+
     #[test]
     fn title() {
         assert_eq!(
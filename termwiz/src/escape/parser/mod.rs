@@ -1,5 +1,6 @@
 #![allow(clippy::many_single_char_names)]
 use crate::color::RgbColor;
+use crate::escape::csi::Unspecified;
 use crate::escape::{
     Action, DeviceControlMode, EnterDeviceControlMode, Esc, OperatingSystemCommand,
     ShortDeviceControl, Sixel, SixelData, CSI,
@@ -53,6 +54,9 @@ struct ParseState {
     sixel: Option<SixelBuilder>,
     dcs: Option<ShortDeviceControl>,
     get_tcap: Option<GetTcapBuilder>,
+    /// An unrecognized CSI sequence that is collecting the text that
+    /// follows it as its payload
+    pending_csi: Option<Box<Unspecified>>,
 }
 
 /// The `Parser` struct holds the state machine that is used to decode
@@ -64,6 +68,7 @@ struct ParseState {
 pub struct Parser {
     state_machine: VTParser,
     state: RefCell<ParseState>,
+    capture_unknown_csi_payload: bool,
 }
 
 impl Default for Parser {
@@ -77,15 +82,32 @@ impl Parser {
         Self {
             state_machine: VTParser::new(),
             state: RefCell::new(Default::default()),
+            capture_unknown_csi_payload: false,
         }
     }
 
+    /// Some applications send vendor specific data as text that
+    /// immediately follows a CSI sequence.  When enabled, the text
+    /// that follows a CSI sequence that is returned as
+    /// `CSI::Unspecified` is collected into its `payload`, up to the
+    /// next control, rather than being returned as `Action::Print`.
+    /// This is best-effort: there is no way to tell such a payload
+    /// apart from regular text, the payload ends at the end of the
+    /// bytes passed to `parse`, and `parse_first` and
+    /// `parse_first_as_vec` don't collect it.
+    /// It is disabled by default.
+    pub fn set_capture_unknown_csi_payload(&mut self, enable: bool) {
+        self.capture_unknown_csi_payload = enable;
+    }
+
     pub fn parse<F: FnMut(Action)>(&mut self, bytes: &[u8], mut callback: F) {
         let mut perform = Performer {
             callback: &mut callback,
             state: &mut self.state.borrow_mut(),
+            capture_unknown_csi_payload: self.capture_unknown_csi_payload,
         };
         self.state_machine.parse(bytes, &mut perform);
+        perform.flush_pending_csi();
     }
 
     /// A specialized version of the parser that halts after recognizing the
@@ -109,6 +131,7 @@ impl Parser {
                     *first.borrow_mut() = Some(action);
                 },
                 state: &mut self.state.borrow_mut(),
+                capture_unknown_csi_payload: false,
             };
             for (idx, b) in bytes.iter().enumerate() {
                 self.state_machine.parse_byte(*b, &mut perform);
@@ -144,6 +167,7 @@ impl Parser {
                 &mut Performer {
                     callback: &mut |action| actions.push(action),
                     state: &mut self.state.borrow_mut(),
+                    capture_unknown_csi_payload: false,
                 },
             );
             if !actions.is_empty() {
@@ -159,6 +183,16 @@ impl Parser {
 struct Performer<'a, F: FnMut(Action) + 'a> {
     callback: &'a mut F,
     state: &'a mut ParseState,
+    capture_unknown_csi_payload: bool,
+}
+
+impl<'a, F: FnMut(Action)> Performer<'a, F> {
+    /// Emits the CSI sequence that is collecting a payload, if any
+    fn flush_pending_csi(&mut self) {
+        if let Some(unspec) = self.state.pending_csi.take() {
+            (self.callback)(Action::CSI(CSI::Unspecified(unspec)));
+        }
+    }
 }
 
 fn is_short_dcs(intermediates: &[u8], byte: u8) -> bool {
@@ -172,10 +206,15 @@ fn is_short_dcs(intermediates: &[u8], byte: u8) -> bool {
 
 impl<'a, F: FnMut(Action)> VTActor for Performer<'a, F> {
     fn print(&mut self, c: char) {
-        (self.callback)(Action::Print(c));
+        if let Some(unspec) = self.state.pending_csi.as_mut() {
+            unspec.payload.push(c);
+        } else {
+            (self.callback)(Action::Print(c));
+        }
     }
 
     fn execute_c0_or_c1(&mut self, byte: u8) {
+        self.flush_pending_csi();
         match FromPrimitive::from_u8(byte) {
             Some(code) => (self.callback)(Action::Control(code)),
             None => error!(
@@ -186,6 +225,7 @@ impl<'a, F: FnMut(Action)> VTActor for Performer<'a, F> {
     }
 
     fn apc_dispatch(&mut self, data: Vec<u8>) {
+        self.flush_pending_csi();
         if let Some(img) = super::KittyImage::parse_apc(&data) {
             (self.callback)(Action::KittyImage(img))
         } else {
@@ -200,6 +240,7 @@ impl<'a, F: FnMut(Action)> VTActor for Performer<'a, F> {
         intermediates: &[u8],
         ignored_extra_intermediates: bool,
     ) {
+        self.flush_pending_csi();
         self.state.sixel.take();
         self.state.get_tcap.take();
         self.state.dcs.take();
@@ -254,13 +295,25 @@ impl<'a, F: FnMut(Action)> VTActor for Performer<'a, F> {
     }
 
     fn osc_dispatch(&mut self, osc: &[&[u8]]) {
+        self.flush_pending_csi();
         let osc = OperatingSystemCommand::parse(osc);
         (self.callback)(Action::OperatingSystemCommand(Box::new(osc)));
     }
 
     fn csi_dispatch(&mut self, params: &[CsiParam], parameters_truncated: bool, control: u8) {
-        for action in CSI::parse(params, parameters_truncated, control as char) {
-            (self.callback)(Action::CSI(action));
+        self.flush_pending_csi();
+        let mut actions = CSI::parse(params, parameters_truncated, control as char).peekable();
+        while let Some(action) = actions.next() {
+            match action {
+                // Only the last action from the sequence can be
+                // followed by its payload
+                CSI::Unspecified(unspec)
+                    if self.capture_unknown_csi_payload && actions.peek().is_none() =>
+                {
+                    self.state.pending_csi.replace(unspec);
+                }
+                action => (self.callback)(Action::CSI(action)),
+            }
         }
     }
 
@@ -271,6 +324,7 @@ impl<'a, F: FnMut(Action)> VTActor for Performer<'a, F> {
         _ignored_extra_intermediates: bool,
         control: u8,
     ) {
+        self.flush_pending_csi();
         // It doesn't appear to be possible for params.len() > 1 due to the way
         // that the state machine in vte functions.  As such, it also seems to
         // be impossible for ignored_extra_intermediates to be true too.
@@ -475,10 +529,10 @@ mod test {
     use crate::cell::{Intensity, Underline};
     use crate::color::ColorSpec;
     use crate::escape::csi::{
-        DecPrivateMode, DecPrivateModeCode, Device, Mode, Sgr, Window, XtSmGraphics,
-        XtSmGraphicsItem, XtermKeyModifierResource,
+        Cursor, DecPrivateMode, DecPrivateModeCode, Device, Mode, Sgr, Unspecified, Window,
+        XtSmGraphics, XtSmGraphicsItem, XtermKeyModifierResource,
     };
-    use crate::escape::{EscCode, OneBased};
+    use crate::escape::{ControlCode, EscCode, OneBased};
     use pretty_assertions::assert_eq;
     use std::io::Write;
 
@@ -860,8 +914,79 @@ mod test {
         );
     }
 
+    #[test]
+    fn bytes_after_unknown_csi() {
+        let unknown = |payload: &str| {
+            Action::CSI(CSI::Unspecified(Box::new(Unspecified {
+                params: vec![CsiParam::P(b'?'), CsiParam::Integer(5)],
+                parameters_truncated: false,
+                control: 'z',
+                payload: payload.to_string(),
+            })))
+        };
+
+        // By default, text after the final byte is printed
+        assert_eq!(
+            round_trip_parse("\x1b[?5zab"),
+            vec![unknown(""), Action::Print('a'), Action::Print('b')]
+        );
+
+        let mut p = Parser::new();
+        p.set_capture_unknown_csi_payload(true);
+
+        // The payload runs up to the next control
+        let input = "\x1b[?5zab c\r\x1b[?5z\x1b[1mx";
+        let actions = p.parse_as_vec(input.as_bytes());
+        assert_eq!(
+            actions,
+            vec![
+                unknown("ab c"),
+                Action::Control(ControlCode::CarriageReturn),
+                unknown(""),
+                Action::CSI(CSI::Sgr(Sgr::Intensity(Intensity::Bold))),
+                Action::Print('x'),
+            ]
+        );
+        assert_eq!(encode(&actions), input);
+
+        // Recognized sequences are unaffected, and the payload ends
+        // with the input
+        assert_eq!(
+            p.parse_as_vec(b"\x1b[1mx\x1b[?5zy"),
+            vec![
+                Action::CSI(CSI::Sgr(Sgr::Intensity(Intensity::Bold))),
+                Action::Print('x'),
+                unknown("y"),
+            ]
+        );
+    }
+
+    #[test]
+    fn save_restore_cursor() {
+        assert_eq!(
+            round_trip_parse("\x1b[sx\x1b[u"),
+            vec![
+                Action::CSI(CSI::Cursor(Cursor::SaveCursor)),
+                Action::Print('x'),
+                Action::CSI(CSI::Cursor(Cursor::RestoreCursor)),
+            ]
+        );
+    }
+
     #[test]
     fn req_attr() {
+        assert_eq!(
+            round_trip_parse("\x1b[c"),
+            vec![Action::CSI(CSI::Device(Box::new(
+                Device::RequestPrimaryDeviceAttributes
+            )))]
+        );
+        assert_eq!(
+            parse_as("\x1b[>0c", "\x1b[>c"),
+            vec![Action::CSI(CSI::Device(Box::new(
+                Device::RequestSecondaryDeviceAttributes
+            )))]
+        );
         assert_eq!(
             round_trip_parse("\x1b[=c"),
             vec![Action::CSI(CSI::Device(Box::new(
@@ -1022,6 +1147,27 @@ mod test {
                 )))),
             ]
         );
+
+        assert_eq!(
+            round_trip_parse("\x1b[?1049h\x1b[?25l\x1b[?2004h\x1b[?1000l\x1b[?1004h"),
+            vec![
+                Action::CSI(CSI::Mode(Mode::SetDecPrivateMode(DecPrivateMode::Code(
+                    DecPrivateModeCode::ClearAndEnableAlternateScreen,
+                )))),
+                Action::CSI(CSI::Mode(Mode::ResetDecPrivateMode(DecPrivateMode::Code(
+                    DecPrivateModeCode::ShowCursor,
+                )))),
+                Action::CSI(CSI::Mode(Mode::SetDecPrivateMode(DecPrivateMode::Code(
+                    DecPrivateModeCode::BracketedPaste,
+                )))),
+                Action::CSI(CSI::Mode(Mode::ResetDecPrivateMode(DecPrivateMode::Code(
+                    DecPrivateModeCode::MouseTracking,
+                )))),
+                Action::CSI(CSI::Mode(Mode::SetDecPrivateMode(DecPrivateMode::Code(
+                    DecPrivateModeCode::FocusTracking,
+                )))),
+            ]
+        );
     }
 
     #[test]
@@ -11,6 +11,8 @@
 //! [termwiz](https://docs.rs/termwiz/) crate if you don't want to have to research
 //! all those possible escape sequences for yourself.
 #![allow(clippy::upper_case_acronyms)]
+#[cfg(feature = "use_serde")]
+use serde::{Deserialize, Serialize};
 use utf8parse::Parser as Utf8Parser;
 mod enums;
 use crate::enums::*;
@@ -358,6 +360,7 @@ pub struct VTParser {
 /// and I are intermediate bytes in the range 0x20-0x2F
 /// and F is the final byte in the range 0x40-0x7E
 ///
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Clone, PartialEq, Eq, Debug, Hash)]
 pub enum CsiParam {
     Integer(i64),
@@ -3,7 +3,7 @@ use config::lua::{format_as_escapes, FormatItem};
 use config::{ConfigHandle, TabBarColors};
 use mlua::FromLua;
 use termwiz::cell::unicode_column_width;
-use termwiz::cell::{Cell, CellAttributes};
+use termwiz::cell::{Cell, CellAttributes, Underline};
 use termwiz::color::ColorSpec;
 use termwiz::escape::csi::Sgr;
 use termwiz::escape::parser::Parser;
@@ -421,6 +421,9 @@ fn parse_status_text(text: &str, default_cell: CellAttributes) -> Line {
                         Sgr::Underline(u) => {
                             pen.set_underline(u);
                         }
+                        Sgr::UnknownUnderline(_) => {
+                            pen.set_underline(Underline::Single);
+                        }
                         Sgr::Overline(o) => {
                             pen.set_overline(o);
                         }
@@ -456,7 +459,7 @@ fn parse_status_text(text: &str, default_cell: CellAttributes) -> Line {
                         Sgr::UnderlineColor(col) => {
                             pen.set_underline_color(col);
                         }
-                        Sgr::Font(_) => {}
+                        Sgr::Font(_) | Sgr::Fraktur(_) | Sgr::Unknown(_) => {}
                     },
                     _ => {}
                 }